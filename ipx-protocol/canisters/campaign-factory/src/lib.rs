@@ -1,11 +1,30 @@
-use ic_cdk::api::{msg_caller, time};
-use ic_cdk::api::management_canister::main::{CreateCanisterArgument, CanisterSettings, CanisterId};
-use ic_cdk::api::call::call;
-use candid::{CandidType, Principal};
+use ic_cdk::api::{canister_cycle_balance, canister_self, is_controller, msg_caller, time};
+use ic_cdk::call::Call;
+use ic_cdk::management_canister::{
+    CanisterInstallMode, CanisterSettings, CanisterStatusArgs, CreateCanisterArgs, DeleteCanisterArgs,
+    DepositCyclesArgs, InstallCodeArgs, StartCanisterArgs, StopCanisterArgs,
+};
+use candid::{CandidType, Decode, Encode, Nat, Principal};
 use ic_cdk_macros::*;
+use ic_stable_structures::{
+    memory_manager::{MemoryId, MemoryManager, VirtualMemory},
+    storable::Bound,
+    DefaultMemoryImpl, StableBTreeMap, StableCell, StableLog, Storable,
+};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use ipx_types::{
+    impl_candid_storable, nat_to_u64, revenue_platform_for_host, Account, RevenueLimit, TransferArg, TransferError,
+    TransferFromArgs, TransferFromError,
+};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Bump when the layout or encoding of a stable region changes, and add a migration step
+const SCHEMA_VERSION: u32 = 3;
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct CampaignMetadata {
@@ -18,6 +37,181 @@ pub struct CampaignMetadata {
     pub vault_canister_id: Option<Principal>,
     pub created_at: u64,
     pub status: CampaignStatus,
+    pub funding_deadline: Option<u64>, // Nanoseconds since epoch; raise is settled once it passes
+    pub minimum_funding: Option<u64>, // Amount needed by the deadline; defaults to the funding goal
+    pub details: Option<CampaignDetails>,
+    pub verification: Option<CreatorVerification>, // Present when the campaign names a YouTube channel
+    pub template_id: Option<String>, // Template the campaign was created from
+    pub payout_stream: Option<PayoutStreamSettings>, // Vault defaults apply when omitted
+    pub insurance: Option<InsuranceSettings>, // Vault defaults apply when omitted
+    pub provisioning: Option<Provisioning>, // None for campaigns created before provisioning existed
+}
+
+// Wiring of a new vault to the rest of the platform. Incomplete steps are retried by the
+// heartbeat and by `retry_provisioning`; the campaign can't launch until both are done.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Provisioning {
+    pub canister_refs_set: bool, // Vault knows the nft-registry, ipx-stream and revenue-api-connector
+    pub oracle_registered: bool, // revenue-api-connector polls the campaign's endpoints
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub updated_at: u64,
+}
+
+impl Provisioning {
+    fn is_complete(&self) -> bool {
+        self.canister_refs_set && self.oracle_registered
+    }
+}
+
+// Platform canisters every new vault is wired to. The nft-registry comes from the verification config.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PlatformCanisters {
+    pub ipx_stream: Option<Principal>,
+    pub revenue_connector: Option<Principal>,
+    pub ledger: Option<Principal>, // ICRC-1/ICRC-2 ledger holding campaign funds
+}
+
+// Revenue source in the shape revenue-api-connector's `register_campaign_oracle` takes
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ApiEndpoint {
+    pub platform: String,
+    pub url: String,
+    pub auth_header: Option<String>,
+    pub data_path: String, // JSON pointer to the revenue figure
+}
+
+// Payout schedule of the streams ipx-stream opens when the vault distributes revenue
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum StreamType {
+    Linear,
+    Cliff,
+    Exponential,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PayoutStreamSettings {
+    pub stream_type: StreamType,
+    pub duration_seconds: u64,
+}
+
+// Mirrors the vault's insurance init settings
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SlashingConditions {
+    pub missed_revenue_reports_threshold: u8,
+    pub revenue_decline_threshold_percentage: u8,
+    pub minimum_active_period_days: u64,
+    pub governance_votes_required: u8,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct InsuranceSettings {
+    pub fee_percentage: u8, // At most 20
+    pub coverage_ratio: u8,
+    pub slashing_conditions: SlashingConditions,
+}
+
+// Preset revenue source and payout terms for a kind of campaign, managed by factory controllers
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CampaignTemplate {
+    pub id: String, // Lowercase slug, e.g. "youtube-ad-revenue"
+    pub name: String,
+    pub description: String,
+    pub revenue_share_percentage: u8,
+    pub oracle_endpoints: Vec<String>, // revenue-api-connector endpoints for the revenue source
+    pub payout_stream: PayoutStreamSettings,
+    pub insurance: InsuranceSettings,
+    pub category: Option<CampaignCategory>,
+    pub tags: Vec<String>,
+    pub updated_at: u64,
+}
+
+// Campaign-specific values for `create_campaign_from_template`. Insurance and slashing
+// terms always come from the template so backers get the terms it advertises.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TemplateOverrides {
+    pub title: String,
+    pub description: String,
+    pub funding_goal: u64,
+    pub revenue_share_percentage: Option<u8>,
+    pub oracle_endpoints: Option<Vec<String>>,
+    pub funding_deadline: Option<u64>,
+    pub minimum_funding: Option<u64>,
+    pub details: Option<CampaignDetails>, // Category and tags default to the template's
+    pub payout_stream: Option<PayoutStreamSettings>,
+}
+
+// Verified-creator badge backed by the creator's YouTube proof in the nft-registry
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CreatorVerification {
+    pub channel_id: String,
+    pub verified: bool, // Cleared when the proof expires and is not renewed
+    pub proof_timestamp: u64, // When the registry verified the proof
+    pub valid_until: Option<u64>,
+    pub subscriber_count: Option<u64>,
+    pub view_count: Option<u64>,
+    pub checked_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VerificationConfig {
+    pub nft_registry: Option<Principal>,
+    pub require_verified_creator: bool, // Reject campaigns that don't name a verified channel
+    pub min_subscribers: Option<u64>,
+    pub min_views: Option<u64>,
+}
+
+// Identity record returned by the nft-registry's `get_youtube_identity`
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct YouTubeIdentity {
+    pub channel_id: String,
+    pub channel_name: Option<String>,
+    pub verification_timestamp: u64,
+    pub valid_until: Option<u64>,
+    pub subscriber_count: Option<u64>,
+    pub view_count: Option<u64>,
+    pub video_count: Option<u64>,
+    pub creation_date: Option<String>,
+}
+
+// Bump when fields are added to `CampaignDetails`; new fields must be optional so
+// details stored under an older version still decode.
+const CAMPAIGN_DETAILS_VERSION: u16 = 1;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum CampaignCategory {
+    Gaming,
+    Music,
+    Education,
+    Technology,
+    Entertainment,
+    Lifestyle,
+    Other,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ExternalLink {
+    pub label: String,
+    pub url: String, // https only
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Milestone {
+    pub title: String,
+    pub description: String,
+    pub target_date: u64, // Nanoseconds since epoch
+}
+
+// Presentation metadata used by the campaign explorer; everything is optional
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CampaignDetails {
+    pub version: u16, // Overwritten with CAMPAIGN_DETAILS_VERSION when stored
+    pub category: Option<CampaignCategory>,
+    pub tags: Vec<String>, // Normalized to lowercase
+    pub cover_image_hashes: Vec<Vec<u8>>, // SHA-256 of assets in the asset canister
+    pub links: Vec<ExternalLink>,
+    pub youtube_channel_id: Option<String>,
+    pub milestones: Vec<Milestone>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -27,170 +221,3605 @@ pub enum CampaignStatus {
     Funded,
     Completed,
     Cancelled,
+    Failed, // Deadline passed below the minimum; backers can claim refunds from the vault
 }
 
-thread_local! {
-    static CAMPAIGN_COUNTER: RefCell<u64> = RefCell::new(0);
-    static CAMPAIGNS: RefCell<HashMap<u64, CampaignMetadata>> = RefCell::new(HashMap::new());
+// Allowed moves: Draft -> Active -> Funded -> Completed, Cancelled from Draft or Active,
+// and Active -> Failed when the deadline passes below the minimum
+impl CampaignStatus {
+    fn can_transition_to(&self, next: &CampaignStatus) -> bool {
+        matches!(
+            (self, next),
+            (CampaignStatus::Draft, CampaignStatus::Active)
+                | (CampaignStatus::Active, CampaignStatus::Funded)
+                | (CampaignStatus::Funded, CampaignStatus::Completed)
+                | (CampaignStatus::Draft, CampaignStatus::Cancelled)
+                | (CampaignStatus::Active, CampaignStatus::Cancelled)
+                | (CampaignStatus::Active, CampaignStatus::Failed)
+        )
+    }
 }
 
-#[init]
-fn init() {
-    ic_cdk::println!("Campaign Factory initialized");
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct StatusTransition {
+    pub from: Option<CampaignStatus>, // None for the initial Draft entry
+    pub to: CampaignStatus,
+    pub actor: Principal,
+    pub timestamp: u64,
 }
 
-#[update]
-async fn create_campaign(
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct StatusHistory {
+    pub transitions: Vec<StatusTransition>,
+}
+
+// Fields left as None are unchanged. While Draft everything may change; once Active
+// only the description can, so backers keep the terms they invested under.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CampaignUpdate {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub funding_goal: Option<u64>,
+    pub revenue_share_percentage: Option<u8>,
+    pub oracle_endpoints: Option<Vec<String>>,
+    pub funding_deadline: Option<u64>,
+    pub minimum_funding: Option<u64>,
+    pub details: Option<CampaignDetails>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct FieldChange {
+    pub field: String,
+    pub old_value: String,
+    pub new_value: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CampaignChange {
+    pub actor: Principal,
+    pub changed_at: u64,
+    pub status: CampaignStatus, // Status of the campaign when the change was made
+    pub changes: Vec<FieldChange>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ChangeLog {
+    pub entries: Vec<CampaignChange>,
+}
+
+// Subset of the campaign the vault keeps its own copy of
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct VaultMetadataUpdate {
+    pub title: Option<String>,
+    pub funding_goal: Option<u64>,
+    pub revenue_share_percentage: Option<u8>,
+    pub oracle_endpoints: Option<Vec<String>>,
+    pub funding_deadline: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VaultWasm {
+    pub module: Vec<u8>,
+    pub hash: Vec<u8>, // SHA-256 of the module
+    pub version: String,
+    pub uploaded_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VaultWasmInfo {
+    pub hash: Vec<u8>,
+    pub version: String,
+    pub size: u64,
+    pub uploaded_at: u64,
+}
+
+// Wasm currently installed on a vault
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VaultDeployment {
+    pub campaign_id: u64,
+    pub vault: Principal,
+    pub wasm_hash: Vec<u8>,
+    pub wasm_version: String,
+    pub installed_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum UpgradeStatus {
+    Running,
+    Halted, // Stopped at the first failure; resume retries the failed vault
+    Completed,
+    Cancelled,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VaultUpgradeFailure {
+    pub campaign_id: u64,
+    pub vault: Principal,
+    pub error: String,
+    pub failed_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct UpgradeRun {
+    pub target_hash: Vec<u8>,
+    pub target_version: String,
+    pub batch_size: u32,
+    pub queue: Vec<u64>, // Campaign IDs still to upgrade, in order
+    pub total: u64,
+    pub upgraded: u64,
+    pub batches_completed: u64,
+    pub status: UpgradeStatus,
+    pub failure: Option<VaultUpgradeFailure>,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct UpgradeProgress {
+    pub target_hash: Vec<u8>,
+    pub target_version: String,
+    pub status: UpgradeStatus,
+    pub total: u64,
+    pub upgraded: u64,
+    pub remaining: u64,
+    pub batches_completed: u64,
+    pub failure: Option<VaultUpgradeFailure>,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CyclesConfig {
+    pub initial_vault_cycles: u128, // Attached on top of the creation fee
+    pub top_up_threshold: u128,
+    pub top_up_amount: u128,
+    pub factory_reserve: u128, // Top-ups never take the factory below this balance
+    pub check_interval_seconds: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VaultCyclesStatus {
+    pub campaign_id: u64,
+    pub vault: Principal,
+    pub cycles: u128,
+    pub checked_at: u64,
+    pub last_error: Option<String>, // Set when the latest status check or top-up failed
+}
+
+// Last figures read from a vault by the stats job
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VaultStats {
+    pub campaign_id: u64,
+    pub current_funding: u64,
+    pub backers: u64,
+    pub total_revenue: u64,
+    pub refreshed_at: u64,
+    pub last_error: Option<String>, // Set when the latest read failed; the figures are from the one before
+}
+
+// Returned by the vault's `get_vault_stats`; fields the stats job doesn't read are skipped
+#[derive(CandidType, Deserialize)]
+struct VaultStatsReply {
+    current_funding: u64,
+    backer_count: u64,
+    total_revenue: u64,
+}
+
+// Returned by ipx-stream's `get_stream_stats`
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct StreamStats {
+    pub total_streams: u64,
+    pub active_streams: u64,
+    pub total_volume: u64,
+    pub claimed_volume: u64,
+}
+
+// Platform-wide totals built from the cached per-vault figures and ipx-stream
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PlatformStats {
+    pub taken_at: u64,
+    pub campaigns: u64,
+    pub campaigns_by_status: Vec<(CampaignStatus, u64)>,
+    pub total_raised: u64,
+    pub total_backers: u64, // Summed per vault, so a principal backing two campaigns counts twice
+    pub total_revenue_reported: u64,
+    pub payouts_streamed: u64,
+    pub payouts_claimed: u64,
+    pub active_streams: u64,
+    pub stale_vaults: u64, // Vaults whose latest read failed
+    pub stream_stats_error: Option<String>, // Stream figures are from the previous snapshot when set
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DailyPlatformStats {
+    pub day: u64, // Days since the Unix epoch
+    pub stats: PlatformStats, // Last snapshot taken that day
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CyclesTopUp {
+    pub campaign_id: u64,
+    pub vault: Principal,
+    pub amount: u128,
+    pub balance_before: u128,
+    pub timestamp: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum CreatorAccess {
+    Allowed,
+    Denied,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AntiSpamConfig {
+    pub max_campaigns_per_window: u32,
+    pub window_seconds: u64,
+    pub allowlist_only: bool, // Only principals the DAO allowed may create campaigns
+    pub dao_canister: Option<Principal>, // Manages the allow/deny list
+    pub deposit_ledger: Option<Principal>, // ICRC-2 ledger the creation deposit is taken on
+    pub deposit_amount: u64, // 0 disables the deposit
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CreationHistory {
+    pub timestamps: Vec<u64>, // Creation attempts inside the current window
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum DepositStatus {
+    Held,
+    Refunding, // Transfer back to the creator in flight
+    Refunded { block_index: u64 },
+    Forfeited, // Campaign failed or was cancelled
+}
+
+// Deposit taken from the creator when the campaign was created
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CreationDeposit {
+    pub creator: Principal,
+    pub ledger: Principal,
+    pub amount: u64,
+    pub block_index: u64,
+    pub status: DepositStatus,
+    pub updated_at: u64,
+    // Fixed by the first refund attempt so retries send the same transfer and the ledger dedups them
+    pub refund_created_at_time: Option<u64>,
+    pub refund_fee: Option<u64>,
+}
+
+// Init argument passed to every vault installed by the factory
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VaultInitArgs {
+    pub campaign_id: u64,
+    pub metadata: CampaignMetadata,
+    pub awaiting_launch: Option<bool>, // Vault rejects investments until the factory launches it
+    pub insurance: Option<InsuranceSettings>,
+    pub payout_stream: Option<PayoutStreamSettings>,
+    pub ledger_canister: Option<Principal>,
+}
+
+// Everything `create_campaign` needs, whichever endpoint it came from
+struct NewCampaign {
     title: String,
     description: String,
     funding_goal: u64,
     revenue_share_percentage: u8,
     oracle_endpoints: Vec<String>,
-) -> Result<u64, String> {
-    let caller = msg_caller();
-    
-    if revenue_share_percentage == 0 || revenue_share_percentage > 100 {
-        return Err("Revenue share must be between 1-100%".to_string());
-    }
-    
-    // Generate unique campaign ID
-    let campaign_id = CAMPAIGN_COUNTER.with(|counter| {
-        let current = *counter.borrow();
-        let next = current + 1;
-        *counter.borrow_mut() = next;
-        next
-    });
-    
-    // Create campaign metadata
-    let metadata = CampaignMetadata {
-        creator: caller,
-        title: title.clone(),
-        description,
-        funding_goal,
-        revenue_share_percentage,
-        oracle_endpoints,
-        vault_canister_id: None,
-        created_at: time(),
-        status: CampaignStatus::Draft,
-    };
-    
-    // Store campaign
-    CAMPAIGNS.with(|campaigns| {
-        campaigns.borrow_mut().insert(campaign_id, metadata.clone());
-    });
-    
-    // Create vault canister for this campaign
-    match create_vault_canister(campaign_id, metadata).await {
-        Ok(vault_id) => {
-            // Update campaign with vault canister ID
-            CAMPAIGNS.with(|campaigns| {
-                if let Some(mut campaign) = campaigns.borrow().get(&campaign_id).cloned() {
-                    campaign.vault_canister_id = Some(vault_id);
-                    campaign.status = CampaignStatus::Active;
-                    campaigns.borrow_mut().insert(campaign_id, campaign);
-                }
-            });
-            
-            ic_cdk::println!("Campaign {} created with vault {}", campaign_id, vault_id.to_text());
-            Ok(campaign_id)
-        }
-        Err(e) => {
-            // Remove campaign if vault creation failed
-            CAMPAIGNS.with(|campaigns| {
-                campaigns.borrow_mut().remove(&campaign_id);
-            });
-            Err(format!("Failed to create vault canister: {}", e))
-        }
-    }
+    funding_deadline: Option<u64>,
+    minimum_funding: Option<u64>,
+    details: Option<CampaignDetails>,
+    template_id: Option<String>,
+    payout_stream: Option<PayoutStreamSettings>,
+    insurance: Option<InsuranceSettings>,
 }
 
-async fn create_vault_canister(
-    _campaign_id: u64,
-    _metadata: CampaignMetadata,
-) -> Result<Principal, String> {
-    
-    // Set up canister settings (optional, can be customized)
-    let settings = CanisterSettings {
-        controllers: Some(vec![ic_cdk::api::id()]),
-        compute_allocation: None,
-        memory_allocation: None,
-        freezing_threshold: None,
-        reserved_cycles_limit: None,
-        wasm_memory_limit: None,
-        log_visibility: None,
-    };
+// All fields are optional; an empty record matches every campaign. Ranges are inclusive.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CampaignFilter {
+    pub status: Option<CampaignStatus>,
+    pub creator: Option<Principal>,
+    pub min_funding_goal: Option<u64>,
+    pub max_funding_goal: Option<u64>,
+    pub min_revenue_share: Option<u8>,
+    pub max_revenue_share: Option<u8>,
+    pub created_after: Option<u64>,
+    pub created_before: Option<u64>,
+    pub category: Option<CampaignCategory>,
+    pub tag: Option<String>, // Matched case-insensitively
+    pub verified_creator: Option<bool>,
+}
 
-    let arg = CreateCanisterArgument {
-        settings: Some(settings),
-    };
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum CampaignSortField {
+    CampaignId,
+    CreatedAt,
+    FundingGoal,
+    RevenueShare,
+}
 
-    // Create the canister
-    match ic_cdk::api::management_canister::main::create_canister(arg, 0).await {
-        Ok((canister_id_record,)) => {
-            Ok(canister_id_record.canister_id)
-        }
-        Err(e) => {
-            Err(format!("Canister creation failed: {:?}", e))
-        }
-    }
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct CampaignSort {
+    pub field: CampaignSortField,
+    pub descending: bool,
 }
 
-#[query]
-fn get_campaign(campaign_id: u64) -> Option<CampaignMetadata> {
-    CAMPAIGNS.with(|campaigns| campaigns.borrow().get(&campaign_id).cloned())
+// Position of the last campaign returned; only valid with the sort it was issued for
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CampaignCursor {
+    pub sort_key: u64,
+    pub campaign_id: u64,
 }
 
-#[query]
-fn get_campaigns_by_creator(creator: Principal) -> Vec<(u64, CampaignMetadata)> {
-    CAMPAIGNS.with(|campaigns| {
-        campaigns
-            .borrow()
-            .iter()
-            .filter(|(_, metadata)| metadata.creator == creator)
-            .map(|(k, v)| (*k, v.clone()))
-            .collect()
-    })
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CampaignPage {
+    pub campaigns: Vec<(u64, CampaignMetadata)>,
+    pub next_cursor: Option<CampaignCursor>, // None once the last page has been returned
 }
 
-#[query]
-fn get_all_campaigns() -> Vec<(u64, CampaignMetadata)> {
-    CAMPAIGNS.with(|campaigns| {
-        campaigns
-            .borrow()
-            .iter()
-            .map(|(k, v)| (*k, v.clone()))
-            .collect()
-    })
+// How often the heartbeat looks for campaigns whose deadline has passed
+const DEADLINE_SWEEP_INTERVAL_NANOS: u64 = 60 * 1_000_000_000;
+
+// How often the heartbeat re-checks creator proofs that have passed `valid_until`
+const VERIFICATION_REFRESH_INTERVAL_NANOS: u64 = 60 * 60 * 1_000_000_000;
+const PROVISIONING_RETRY_INTERVAL_NANOS: u64 = 10 * 60 * 1_000_000_000;
+// The heartbeat gives up after this many attempts; `retry_provisioning` always tries
+const MAX_PROVISIONING_ATTEMPTS: u32 = 5;
+const ORACLE_UPDATE_FREQUENCY_SECONDS: u64 = 24 * 60 * 60;
+const STATS_AGGREGATION_INTERVAL_NANOS: u64 = 60 * 60 * 1_000_000_000;
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const DEFAULT_STATS_HISTORY_DAYS: u32 = 30;
+const MAX_STATS_HISTORY_DAYS: u32 = 365;
+
+const MAX_UPGRADE_BATCH_SIZE: u32 = 50;
+
+const MIN_TERM_LENGTH: usize = 2;
+const MAX_TERM_LENGTH: usize = 32;
+const MAX_QUERY_TERMS: usize = 10;
+
+// Occurrences in the title count more than in tags, which count more than in the description
+const TITLE_WEIGHT: u32 = 3;
+const TAG_WEIGHT: u32 = 2;
+const DESCRIPTION_WEIGHT: u32 = 1;
+
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "has", "have", "in", "is",
+    "it", "its", "of", "on", "or", "our", "that", "the", "this", "to", "was", "we", "will",
+    "with", "you", "your",
+];
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 100;
+
+impl_candid_storable!(
+    CampaignMetadata,
+    VaultWasm,
+    StatusHistory,
+    CyclesConfig,
+    VaultCyclesStatus,
+    CyclesTopUp,
+    VaultDeployment,
+    UpgradeRun,
+    VerificationConfig,
+    ChangeLog,
+    AntiSpamConfig,
+    CreationHistory,
+    CreatorAccess,
+    CreationDeposit,
+    CampaignTemplate,
+    PlatformCanisters,
+    VaultStats,
+    PlatformStats
+);
+
+// Key of the search index. Encoded as the term, a 0 byte and the big-endian campaign ID,
+// so all postings of a term are contiguous and ordered by campaign.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct IndexKey {
+    term: String,
+    campaign_id: u64,
 }
 
-#[query]
-fn get_active_campaigns() -> Vec<(u64, CampaignMetadata)> {
-    CAMPAIGNS.with(|campaigns| {
-        campaigns
-            .borrow()
-            .iter()
-            .filter(|(_, metadata)| metadata.status == CampaignStatus::Active)
-            .map(|(k, v)| (*k, v.clone()))
-            .collect()
-    })
+impl Storable for IndexKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::with_capacity(self.term.len() + 9);
+        bytes.extend_from_slice(self.term.as_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(&self.campaign_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let split = bytes.len() - 9;
+        let mut campaign_id = [0u8; 8];
+        campaign_id.copy_from_slice(&bytes[split + 1..]);
+        IndexKey {
+            term: String::from_utf8(bytes[..split].to_vec()).expect("index term is not UTF-8"),
+            campaign_id: u64::from_be_bytes(campaign_id),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: MAX_TERM_LENGTH as u32 + 9,
+        is_fixed_size: false,
+    };
 }
 
-#[update]
-fn update_campaign_status(campaign_id: u64, status: CampaignStatus) -> Result<(), String> {
-    let caller = msg_caller();
+// Stable memory layout. Never reuse or renumber an ID.
+const SCHEMA_VERSION_MEMORY_ID: MemoryId = MemoryId::new(0);
+const CAMPAIGN_COUNTER_MEMORY_ID: MemoryId = MemoryId::new(1);
+const CAMPAIGNS_MEMORY_ID: MemoryId = MemoryId::new(2);
+const VAULT_WASM_MEMORY_ID: MemoryId = MemoryId::new(3);
+const STATUS_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(4);
+const CYCLES_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(5);
+const VAULT_CYCLES_MEMORY_ID: MemoryId = MemoryId::new(6);
+const TOP_UP_INDEX_MEMORY_ID: MemoryId = MemoryId::new(7);
+const TOP_UP_DATA_MEMORY_ID: MemoryId = MemoryId::new(8);
+const VAULT_DEPLOYMENTS_MEMORY_ID: MemoryId = MemoryId::new(9);
+const UPGRADE_RUN_MEMORY_ID: MemoryId = MemoryId::new(10);
+const VERIFICATION_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(11);
+const SEARCH_INDEX_MEMORY_ID: MemoryId = MemoryId::new(12);
+const CHANGE_LOG_MEMORY_ID: MemoryId = MemoryId::new(13);
+const ANTI_SPAM_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(14);
+const CREATION_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(15);
+const CREATOR_ACCESS_MEMORY_ID: MemoryId = MemoryId::new(16);
+const DEPOSITS_MEMORY_ID: MemoryId = MemoryId::new(17);
+const TEMPLATES_MEMORY_ID: MemoryId = MemoryId::new(18);
+const PLATFORM_CANISTERS_MEMORY_ID: MemoryId = MemoryId::new(19);
+const VAULT_STATS_MEMORY_ID: MemoryId = MemoryId::new(20);
+const PLATFORM_STATS_MEMORY_ID: MemoryId = MemoryId::new(21);
+const DAILY_STATS_MEMORY_ID: MemoryId = MemoryId::new(22);
+
+thread_local! {
+    static MEMORY_MANAGER: MemoryManager<DefaultMemoryImpl> = MemoryManager::init(DefaultMemoryImpl::default());
     
-    CAMPAIGNS.with(|campaigns| {
-        if let Some(mut campaign) = campaigns.borrow().get(&campaign_id).cloned() {
-            if campaign.creator != caller {
-                return Err("Only campaign creator can update status".to_string());
-            }
+    static STORED_SCHEMA_VERSION: RefCell<StableCell<u32, Memory>> = RefCell::new(
+        StableCell::init(memory(SCHEMA_VERSION_MEMORY_ID), 0).expect("failed to init schema version")
+    );
+    
+    static CAMPAIGN_COUNTER: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(memory(CAMPAIGN_COUNTER_MEMORY_ID), 0).expect("failed to init campaign counter")
+    );
+    
+    static CAMPAIGNS: RefCell<StableBTreeMap<u64, CampaignMetadata, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(CAMPAIGNS_MEMORY_ID))
+    );
+    
+    static VAULT_WASM: RefCell<StableCell<Option<VaultWasm>, Memory>> = RefCell::new(
+        StableCell::init(memory(VAULT_WASM_MEMORY_ID), None).expect("failed to init vault wasm")
+    );
+    
+    static STATUS_HISTORY: RefCell<StableBTreeMap<u64, StatusHistory, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(STATUS_HISTORY_MEMORY_ID))
+    );
+    
+    static CYCLES_CONFIG: RefCell<StableCell<CyclesConfig, Memory>> = RefCell::new(
+        StableCell::init(memory(CYCLES_CONFIG_MEMORY_ID), default_cycles_config())
+            .expect("failed to init cycles config")
+    );
+    
+    static VAULT_CYCLES: RefCell<StableBTreeMap<u64, VaultCyclesStatus, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(VAULT_CYCLES_MEMORY_ID))
+    );
+    
+    static TOP_UP_HISTORY: RefCell<StableLog<CyclesTopUp, Memory, Memory>> = RefCell::new(
+        StableLog::init(memory(TOP_UP_INDEX_MEMORY_ID), memory(TOP_UP_DATA_MEMORY_ID))
+            .expect("failed to init top-up history")
+    );
+    
+    static VAULT_DEPLOYMENTS: RefCell<StableBTreeMap<u64, VaultDeployment, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(VAULT_DEPLOYMENTS_MEMORY_ID))
+    );
+    
+    // Latest fleet upgrade, kept after it finishes so its outcome can still be read
+    static UPGRADE_RUN: RefCell<StableCell<Option<UpgradeRun>, Memory>> = RefCell::new(
+        StableCell::init(memory(UPGRADE_RUN_MEMORY_ID), None).expect("failed to init upgrade run")
+    );
+    
+    static VERIFICATION_CONFIG: RefCell<StableCell<VerificationConfig, Memory>> = RefCell::new(
+        StableCell::init(memory(VERIFICATION_CONFIG_MEMORY_ID), VerificationConfig {
+            nft_registry: None,
+            require_verified_creator: false,
+            min_subscribers: None,
+            min_views: None,
+        })
+        .expect("failed to init verification config")
+    );
+    
+    // Inverted index: (term, campaign) -> weighted term frequency
+    static SEARCH_INDEX: RefCell<StableBTreeMap<IndexKey, u32, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(SEARCH_INDEX_MEMORY_ID))
+    );
+    
+    static CHANGE_LOGS: RefCell<StableBTreeMap<u64, ChangeLog, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(CHANGE_LOG_MEMORY_ID))
+    );
+    
+    static ANTI_SPAM_CONFIG: RefCell<StableCell<AntiSpamConfig, Memory>> = RefCell::new(
+        StableCell::init(memory(ANTI_SPAM_CONFIG_MEMORY_ID), AntiSpamConfig {
+            max_campaigns_per_window: 3,
+            window_seconds: 24 * 60 * 60,
+            allowlist_only: false,
+            dao_canister: None,
+            deposit_ledger: None,
+            deposit_amount: 0,
+        })
+        .expect("failed to init anti-spam config")
+    );
+    
+    static CREATION_HISTORY: RefCell<StableBTreeMap<Principal, CreationHistory, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(CREATION_HISTORY_MEMORY_ID))
+    );
+    
+    static CREATOR_ACCESS: RefCell<StableBTreeMap<Principal, CreatorAccess, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(CREATOR_ACCESS_MEMORY_ID))
+    );
+    
+    static DEPOSITS: RefCell<StableBTreeMap<u64, CreationDeposit, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(DEPOSITS_MEMORY_ID))
+    );
+    
+    static TEMPLATES: RefCell<StableBTreeMap<String, CampaignTemplate, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(TEMPLATES_MEMORY_ID))
+    );
+    
+    static PLATFORM_CANISTERS: RefCell<StableCell<PlatformCanisters, Memory>> = RefCell::new(
+        StableCell::init(memory(PLATFORM_CANISTERS_MEMORY_ID), PlatformCanisters::default())
+            .expect("failed to init platform canisters")
+    );
+    
+    static VAULT_STATS: RefCell<StableBTreeMap<u64, VaultStats, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(VAULT_STATS_MEMORY_ID))
+    );
+    
+    static PLATFORM_STATS: RefCell<StableCell<Option<PlatformStats>, Memory>> = RefCell::new(
+        StableCell::init(memory(PLATFORM_STATS_MEMORY_ID), None)
+            .expect("failed to init platform stats")
+    );
+    
+    // Keyed by day since the Unix epoch
+    static DAILY_STATS: RefCell<StableBTreeMap<u64, PlatformStats, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(DAILY_STATS_MEMORY_ID))
+    );
+    
+    // Campaigns with a provisioning attempt in flight, so the heartbeat and a manual retry don't overlap
+    static PROVISIONING_IN_FLIGHT: RefCell<HashSet<u64>> = RefCell::new(HashSet::new());
+    
+    // Scheduling state for the heartbeat; losing it on upgrade only makes every job run early
+    static JOBS: RefCell<HashMap<Job, JobState>> = RefCell::new(HashMap::new());
+}
+
+// Periodic work driven by the heartbeat
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Job {
+    DeadlineSweep,
+    CyclesCheck,
+    FleetUpgrade,
+    VerificationRefresh,
+    ProvisioningRetry,
+    StatsAggregation,
+}
+
+#[derive(Default)]
+struct JobState {
+    next_run: u64,
+    running: bool,
+}
+
+// Claims `job` if it is due and not already running. The slot is released when the guard
+// drops, which also happens when a callback traps and the call context is cleaned up.
+fn try_start_job(job: Job, now: u64, interval_nanos: u64) -> Option<JobGuard> {
+    JOBS.with(|jobs| {
+        let mut jobs = jobs.borrow_mut();
+        let state = jobs.entry(job).or_default();
+        
+        if state.running || now < state.next_run {
+            return None;
+        }
+        
+        state.next_run = now.saturating_add(interval_nanos);
+        state.running = true;
+        Some(JobGuard(job))
+    })
+}
+
+struct JobGuard(Job);
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        JOBS.with(|jobs| {
+            if let Some(state) = jobs.borrow_mut().get_mut(&self.0) {
+                state.running = false;
+            }
+        });
+    }
+}
+
+fn default_cycles_config() -> CyclesConfig {
+    CyclesConfig {
+        initial_vault_cycles: 2_000_000_000_000, // 2T
+        top_up_threshold: 500_000_000_000, // 0.5T
+        top_up_amount: 1_000_000_000_000, // 1T
+        factory_reserve: 5_000_000_000_000, // 5T
+        check_interval_seconds: 6 * 60 * 60,
+    }
+}
+
+fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|manager| manager.get(id))
+}
+
+#[init]
+fn init() {
+    STORED_SCHEMA_VERSION.with(|version| {
+        version.borrow_mut().set(SCHEMA_VERSION).expect("failed to store schema version");
+    });
+    seed_default_templates();
+    ic_cdk::println!("Campaign Factory initialized");
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    let stored = STORED_SCHEMA_VERSION.with(|version| *version.borrow().get());
+    
+    // Refuse to run against state written by a newer release
+    if stored > SCHEMA_VERSION {
+        ic_cdk::trap(format!(
+            "Stable schema version {} is newer than supported version {}",
+            stored, SCHEMA_VERSION
+        ));
+    }
+    
+    // Version 0 means the previous release kept everything on the heap, so there is nothing to migrate.
+    // Version 2 added the search index, which is built from the existing campaigns.
+    // Version 3 added campaign templates, seeded with the built-in set.
+    if stored < 2 {
+        rebuild_search_index();
+    }
+    if stored < 3 {
+        seed_default_templates();
+    }
+    
+    if stored < SCHEMA_VERSION {
+        STORED_SCHEMA_VERSION.with(|version| {
+            version.borrow_mut().set(SCHEMA_VERSION).expect("failed to store schema version");
+        });
+        ic_cdk::println!("Stable schema migrated from version {} to {}", stored, SCHEMA_VERSION);
+    }
+    
+    let campaigns = CAMPAIGNS.with(|campaigns| campaigns.borrow().len());
+    ic_cdk::println!("Campaign Factory upgraded with {} campaigns", campaigns);
+}
+
+#[query]
+fn get_schema_version() -> u32 {
+    STORED_SCHEMA_VERSION.with(|version| *version.borrow().get())
+}
+
+#[update]
+#[allow(clippy::too_many_arguments)]
+async fn create_campaign(
+    title: String,
+    description: String,
+    funding_goal: u64,
+    revenue_share_percentage: u8,
+    oracle_endpoints: Vec<String>,
+    funding_deadline: Option<u64>,
+    minimum_funding: Option<u64>,
+    details: Option<CampaignDetails>,
+) -> Result<u64, String> {
+    create_campaign_for(msg_caller(), NewCampaign {
+        title,
+        description,
+        funding_goal,
+        revenue_share_percentage,
+        oracle_endpoints,
+        funding_deadline,
+        minimum_funding,
+        details,
+        template_id: None,
+        payout_stream: None,
+        insurance: None,
+    })
+    .await
+}
+
+// Fills in a campaign from a stored template; see `TemplateOverrides` for what the creator may change
+#[update]
+async fn create_campaign_from_template(template_id: String, overrides: TemplateOverrides) -> Result<u64, String> {
+    let template = TEMPLATES
+        .with(|templates| templates.borrow().get(&template_id))
+        .ok_or_else(|| format!("Template '{}' not found", template_id))?;
+    
+    let mut details = overrides.details.unwrap_or(CampaignDetails {
+        version: CAMPAIGN_DETAILS_VERSION,
+        category: None,
+        tags: Vec::new(),
+        cover_image_hashes: Vec::new(),
+        links: Vec::new(),
+        youtube_channel_id: None,
+        milestones: Vec::new(),
+    });
+    if details.category.is_none() {
+        details.category = template.category.clone();
+    }
+    if details.tags.is_empty() {
+        details.tags = template.tags.clone();
+    }
+    
+    create_campaign_for(msg_caller(), NewCampaign {
+        title: overrides.title,
+        description: overrides.description,
+        funding_goal: overrides.funding_goal,
+        revenue_share_percentage: overrides
+            .revenue_share_percentage
+            .unwrap_or(template.revenue_share_percentage),
+        oracle_endpoints: overrides.oracle_endpoints.unwrap_or(template.oracle_endpoints),
+        funding_deadline: overrides.funding_deadline,
+        minimum_funding: overrides.minimum_funding,
+        details: Some(details),
+        template_id: Some(template.id),
+        payout_stream: Some(overrides.payout_stream.unwrap_or(template.payout_stream)),
+        insurance: Some(template.insurance),
+    })
+    .await
+}
+
+async fn create_campaign_for(caller: Principal, campaign: NewCampaign) -> Result<u64, String> {
+    let NewCampaign {
+        title,
+        description,
+        funding_goal,
+        revenue_share_percentage,
+        oracle_endpoints,
+        funding_deadline,
+        minimum_funding,
+        details,
+        template_id,
+        payout_stream,
+        insurance,
+    } = campaign;
+    
+    check_creator_access(caller)?;
+    
+    validate_campaign_terms(
+        &title,
+        &description,
+        funding_goal,
+        revenue_share_percentage,
+        funding_deadline,
+        minimum_funding,
+    )?;
+    
+    if let Some(payout_stream) = &payout_stream {
+        validate_payout_stream(payout_stream)?;
+    }
+    
+    validate_oracle_endpoints(&oracle_endpoints)?;
+    
+    let details = details.map(normalize_details).transpose()?;
+    
+    // Fail fast instead of allocating an ID for a campaign that can never get a vault
+    if VAULT_WASM.with(|wasm| wasm.borrow().get().is_none()) {
+        return Err("Vault wasm module has not been uploaded".to_string());
+    }
+    
+    // Counted before any await so concurrent calls can't slip past the limit
+    record_creation_attempt(caller)?;
+    
+    let verification = resolve_verification(caller, details.as_ref()).await?;
+    
+    // Generate unique campaign ID
+    let campaign_id = CAMPAIGN_COUNTER.with(|counter| {
+        let current = *counter.borrow().get();
+        let next = current + 1;
+        counter.borrow_mut().set(next).expect("failed to persist campaign counter");
+        next
+    });
+    
+    take_creation_deposit(campaign_id, caller).await?;
+    
+    // Create campaign metadata
+    let metadata = CampaignMetadata {
+        creator: caller,
+        title: title.clone(),
+        description,
+        funding_goal,
+        revenue_share_percentage,
+        oracle_endpoints,
+        vault_canister_id: None,
+        created_at: time(),
+        status: CampaignStatus::Draft,
+        funding_deadline,
+        minimum_funding,
+        details,
+        verification,
+        template_id,
+        payout_stream,
+        insurance,
+        provisioning: Some(Provisioning::default()),
+    };
+    
+    // Store campaign
+    CAMPAIGNS.with(|campaigns| {
+        campaigns.borrow_mut().insert(campaign_id, metadata.clone());
+    });
+    index_campaign(campaign_id, &metadata);
+    record_transition(campaign_id, None, CampaignStatus::Draft, caller);
+    
+    // Create vault canister for this campaign
+    match create_vault_canister(campaign_id, metadata).await {
+        Ok(vault_id) => {
+            // Update campaign with vault canister ID
+            CAMPAIGNS.with(|campaigns| {
+                let existing = campaigns.borrow().get(&campaign_id);
+                if let Some(mut campaign) = existing {
+                    campaign.vault_canister_id = Some(vault_id);
+                    campaigns.borrow_mut().insert(campaign_id, campaign);
+                }
+            });
             
-            campaign.status = status;
-            campaigns.borrow_mut().insert(campaign_id, campaign);
-            Ok(())
-        } else {
-            Err("Campaign not found".to_string())
+            // A failed step doesn't undo the campaign; it is retried until the vault is wired up
+            if let Err(e) = provision_campaign(campaign_id).await {
+                ic_cdk::println!("Provisioning of campaign {} incomplete: {}", campaign_id, e);
+            }
+            
+            // The campaign stays in Draft, where it can still be edited, until the creator launches it
+            ic_cdk::println!("Campaign {} created with vault {}", campaign_id, vault_id.to_text());
+            Ok(campaign_id)
+        }
+        Err(e) => {
+            // Remove campaign if vault creation failed
+            let removed = CAMPAIGNS.with(|campaigns| campaigns.borrow_mut().remove(&campaign_id));
+            if let Some(removed) = removed {
+                unindex_campaign(campaign_id, &removed);
+            }
+            STATUS_HISTORY.with(|history| {
+                history.borrow_mut().remove(&campaign_id);
+            });
+            
+            // The creator shouldn't pay for a campaign that was never created
+            if let Err(refund_err) = refund_creation_deposit(campaign_id).await {
+                ic_cdk::println!("Failed to refund deposit for campaign {}: {}", campaign_id, refund_err);
+            }
+            Err(format!("Failed to create vault canister: {}", e))
         }
+    }
+}
+
+// Checks the caller's YouTube proof in the nft-registry against the configured thresholds.
+// `verified` is false when the proof is missing, expired or below a threshold.
+async fn verify_creator(creator: Principal, channel_id: String) -> Result<CreatorVerification, String> {
+    let config = VERIFICATION_CONFIG.with(|config| config.borrow().get().clone());
+    let registry = config
+        .nft_registry
+        .ok_or_else(|| "NFT registry is not configured for creator verification".to_string())?;
+    
+    let owns_channel: bool = Call::unbounded_wait(registry, "verify_youtube_ownership")
+        .with_args(&(creator, channel_id.clone()))
+        .await
+        .map_err(|e| format!("NFT registry call failed: {:?}", e))?
+        .candid()
+        .map_err(|e| format!("Failed to decode ownership check: {:?}", e))?;
+    
+    let identity: Option<YouTubeIdentity> = Call::unbounded_wait(registry, "get_youtube_identity")
+        .with_arg(creator)
+        .await
+        .map_err(|e| format!("NFT registry call failed: {:?}", e))?
+        .candid()
+        .map_err(|e| format!("Failed to decode YouTube identity: {:?}", e))?;
+    
+    let identity = identity.filter(|identity| identity.channel_id == channel_id);
+    
+    let meets_thresholds = identity.as_ref().is_some_and(|identity| {
+        config.min_subscribers.is_none_or(|min| identity.subscriber_count.unwrap_or(0) >= min)
+            && config.min_views.is_none_or(|min| identity.view_count.unwrap_or(0) >= min)
+    });
+    
+    Ok(CreatorVerification {
+        channel_id,
+        verified: owns_channel && meets_thresholds,
+        proof_timestamp: identity.as_ref().map(|i| i.verification_timestamp).unwrap_or(0),
+        valid_until: identity.as_ref().and_then(|i| i.valid_until),
+        subscriber_count: identity.as_ref().and_then(|i| i.subscriber_count),
+        view_count: identity.as_ref().and_then(|i| i.view_count),
+        checked_at: time(),
     })
 }
-ic_cdk::export_candid!();
\ No newline at end of file
+
+// Re-checks every verified campaign whose proof has passed `valid_until`. A renewed proof
+// keeps the badge; otherwise it is cleared until the creator refreshes it.
+async fn refresh_expired_verifications(now: u64) {
+    let expired: Vec<(u64, Principal, String)> = CAMPAIGNS.with(|campaigns| {
+        campaigns
+            .borrow()
+            .iter()
+            .filter_map(|(campaign_id, campaign)| {
+                let verification = campaign.verification.as_ref()?;
+                let expired = verification.verified
+                    && verification.valid_until.is_some_and(|valid_until| valid_until <= now);
+                expired.then(|| (campaign_id, campaign.creator, verification.channel_id.clone()))
+            })
+            .collect()
+    });
+    
+    for (campaign_id, creator, channel_id) in expired {
+        match verify_creator(creator, channel_id).await {
+            Ok(verification) => store_verification(campaign_id, verification),
+            Err(e) => ic_cdk::println!("Failed to refresh verification for campaign {}: {}", campaign_id, e),
+        }
+    }
+}
+
+fn store_verification(campaign_id: u64, verification: CreatorVerification) {
+    CAMPAIGNS.with(|campaigns| {
+        let existing = campaigns.borrow().get(&campaign_id);
+        if let Some(mut campaign) = existing {
+            campaign.verification = Some(verification);
+            campaigns.borrow_mut().insert(campaign_id, campaign);
+        }
+    });
+}
+
+// Lets a creator restore the badge right after renewing their proof in the nft-registry
+#[update]
+async fn refresh_creator_verification(campaign_id: u64) -> Result<CreatorVerification, String> {
+    let caller = msg_caller();
+    
+    let campaign = CAMPAIGNS.with(|campaigns| campaigns.borrow().get(&campaign_id))
+        .ok_or_else(|| "Campaign not found".to_string())?;
+    
+    if campaign.creator != caller {
+        return Err("Only campaign creator can refresh verification".to_string());
+    }
+    
+    let channel_id = campaign
+        .verification
+        .map(|v| v.channel_id)
+        .ok_or_else(|| "Campaign does not name a YouTube channel".to_string())?;
+    
+    let verification = verify_creator(campaign.creator, channel_id).await?;
+    store_verification(campaign_id, verification.clone());
+    
+    Ok(verification)
+}
+
+#[update]
+fn set_verification_config(config: VerificationConfig) -> Result<(), String> {
+    if !is_controller(&msg_caller()) {
+        return Err("Only factory controllers can change the verification config".to_string());
+    }
+    
+    if config.require_verified_creator && config.nft_registry.is_none() {
+        return Err("An NFT registry is required to enforce creator verification".to_string());
+    }
+    
+    VERIFICATION_CONFIG.with(|cell| {
+        cell.borrow_mut().set(config).expect("failed to persist verification config");
+    });
+    
+    Ok(())
+}
+
+#[query]
+fn get_verification_config() -> VerificationConfig {
+    VERIFICATION_CONFIG.with(|config| config.borrow().get().clone())
+}
+
+fn validate_campaign_terms(
+    title: &str,
+    description: &str,
+    funding_goal: u64,
+    revenue_share_percentage: u8,
+    funding_deadline: Option<u64>,
+    minimum_funding: Option<u64>,
+) -> Result<(), String> {
+    if title.trim().is_empty() || title.len() > MAX_TITLE_LENGTH {
+        return Err(format!("Title must be between 1 and {} characters", MAX_TITLE_LENGTH));
+    }
+    
+    if description.len() > MAX_DESCRIPTION_LENGTH {
+        return Err(format!("Description cannot exceed {} characters", MAX_DESCRIPTION_LENGTH));
+    }
+    
+    if funding_goal == 0 {
+        return Err("Funding goal must be greater than 0".to_string());
+    }
+    
+    if revenue_share_percentage == 0 || revenue_share_percentage > 100 {
+        return Err("Revenue share must be between 1-100%".to_string());
+    }
+    
+    if funding_deadline.is_some_and(|deadline| deadline <= time()) {
+        return Err("Funding deadline must be in the future".to_string());
+    }
+    
+    if let Some(minimum) = minimum_funding {
+        if minimum == 0 || minimum > funding_goal {
+            return Err("Minimum funding must be between 1 and the funding goal".to_string());
+        }
+    }
+    
+    Ok(())
+}
+
+// A named channel must belong to the creator, otherwise anyone could pose as any creator
+async fn resolve_verification(
+    creator: Principal,
+    details: Option<&CampaignDetails>,
+) -> Result<Option<CreatorVerification>, String> {
+    let channel_id = details.and_then(|d| d.youtube_channel_id.clone());
+    let require_verified = VERIFICATION_CONFIG.with(|config| config.borrow().get().require_verified_creator);
+    
+    match channel_id {
+        Some(channel_id) => {
+            let verification = verify_creator(creator, channel_id).await?;
+            if !verification.verified {
+                return Err(format!(
+                    "YouTube channel {} is not verified for the caller",
+                    verification.channel_id
+                ));
+            }
+            Ok(Some(verification))
+        }
+        None if require_verified => Err("Campaigns require a verified YouTube channel".to_string()),
+        None => Ok(None),
+    }
+}
+
+// Limits keep a single campaign record well under the stable value and query response sizes
+const MAX_TITLE_LENGTH: usize = 120;
+const MAX_DESCRIPTION_LENGTH: usize = 5_000;
+const MAX_TAGS: usize = 10;
+const MAX_TAG_LENGTH: usize = 32;
+const MAX_COVER_IMAGES: usize = 5;
+const MAX_LINKS: usize = 10;
+const MAX_LINK_LABEL_LENGTH: usize = 64;
+const MAX_URL_LENGTH: usize = 2_048;
+const MAX_MILESTONES: usize = 20;
+const MAX_MILESTONE_DESCRIPTION_LENGTH: usize = 1_000;
+
+// Validates the details and returns them in their stored form
+fn normalize_details(mut details: CampaignDetails) -> Result<CampaignDetails, String> {
+    details.version = CAMPAIGN_DETAILS_VERSION;
+    
+    if details.tags.len() > MAX_TAGS {
+        return Err(format!("At most {} tags are allowed", MAX_TAGS));
+    }
+    let mut tags: Vec<String> = Vec::with_capacity(details.tags.len());
+    for tag in &details.tags {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() || tag.len() > MAX_TAG_LENGTH {
+            return Err(format!("Tags must be between 1 and {} characters", MAX_TAG_LENGTH));
+        }
+        if !tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(format!("Tag '{}' may only contain letters, digits and '-'", tag));
+        }
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    details.tags = tags;
+    
+    if details.cover_image_hashes.len() > MAX_COVER_IMAGES {
+        return Err(format!("At most {} cover images are allowed", MAX_COVER_IMAGES));
+    }
+    if details.cover_image_hashes.iter().any(|hash| hash.len() != 32) {
+        return Err("Cover image hashes must be 32-byte SHA-256 digests".to_string());
+    }
+    
+    if details.links.len() > MAX_LINKS {
+        return Err(format!("At most {} links are allowed", MAX_LINKS));
+    }
+    for link in &details.links {
+        if link.label.trim().is_empty() || link.label.len() > MAX_LINK_LABEL_LENGTH {
+            return Err(format!("Link labels must be between 1 and {} characters", MAX_LINK_LABEL_LENGTH));
+        }
+        validate_url(&link.url)?;
+    }
+    
+    if let Some(channel_id) = &details.youtube_channel_id {
+        // Channel IDs are "UC" followed by 22 URL-safe base64 characters
+        let valid = channel_id.len() == 24
+            && channel_id.starts_with("UC")
+            && channel_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(format!("'{}' is not a valid YouTube channel ID", channel_id));
+        }
+    }
+    
+    if details.milestones.len() > MAX_MILESTONES {
+        return Err(format!("At most {} milestones are allowed", MAX_MILESTONES));
+    }
+    for milestone in &details.milestones {
+        if milestone.title.trim().is_empty() || milestone.title.len() > MAX_TITLE_LENGTH {
+            return Err(format!("Milestone titles must be between 1 and {} characters", MAX_TITLE_LENGTH));
+        }
+        if milestone.description.len() > MAX_MILESTONE_DESCRIPTION_LENGTH {
+            return Err(format!(
+                "Milestone descriptions cannot exceed {} characters",
+                MAX_MILESTONE_DESCRIPTION_LENGTH
+            ));
+        }
+    }
+    if details.milestones.windows(2).any(|pair| pair[0].target_date > pair[1].target_date) {
+        return Err("Milestones must be ordered by target date".to_string());
+    }
+    
+    Ok(details)
+}
+
+fn validate_url(url: &str) -> Result<(), String> {
+    if url.len() > MAX_URL_LENGTH {
+        return Err(format!("URLs cannot exceed {} characters", MAX_URL_LENGTH));
+    }
+    
+    let host = url
+        .strip_prefix("https://")
+        .ok_or_else(|| format!("URL '{}' must use https", url))?
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or_default();
+    
+    if host.is_empty() || url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(format!("URL '{}' is not valid", url));
+    }
+    
+    Ok(())
+}
+
+async fn create_vault_canister(
+    campaign_id: u64,
+    metadata: CampaignMetadata,
+) -> Result<Principal, String> {
+    let wasm = VAULT_WASM
+        .with(|wasm| wasm.borrow().get().clone())
+        .ok_or_else(|| "Vault wasm module has not been uploaded".to_string())?;
+    
+    // The factory stays the sole controller so it can manage the vault later
+    let settings = CanisterSettings {
+        controllers: Some(vec![canister_self()]),
+        ..Default::default()
+    };
+
+    let arg = CreateCanisterArgs {
+        settings: Some(settings),
+    };
+
+    let cycles = CYCLES_CONFIG.with(|config| config.borrow().get().initial_vault_cycles);
+    
+    // Create the canister with enough cycles to run on its own for a while
+    let canister_id = match ic_cdk::management_canister::create_canister_with_extra_cycles(&arg, cycles).await {
+        Ok(record) => record.canister_id,
+        Err(e) => return Err(format!("Canister creation failed: {:?}", e)),
+    };
+    
+    // Install the vault code with the campaign as init argument
+    let init_args = VaultInitArgs {
+        campaign_id,
+        insurance: metadata.insurance.clone(),
+        payout_stream: metadata.payout_stream.clone(),
+        ledger_canister: PLATFORM_CANISTERS.with(|platform| platform.borrow().get().ledger),
+        metadata,
+        awaiting_launch: Some(true),
+    };
+    let encoded_args = candid::encode_one(&init_args)
+        .map_err(|e| format!("Failed to encode vault init args: {:?}", e))?;
+    
+    let install_args = InstallCodeArgs {
+        mode: CanisterInstallMode::Install,
+        canister_id,
+        wasm_module: wasm.module.clone(),
+        arg: encoded_args,
+    };
+    
+    if let Err(e) = ic_cdk::management_canister::install_code(&install_args).await {
+        // Roll back so we don't leave an empty canister behind
+        if let Err(rollback_err) = delete_vault_canister(canister_id).await {
+            ic_cdk::println!(
+                "Failed to delete vault {} after install error: {}",
+                canister_id.to_text(),
+                rollback_err
+            );
+        }
+        return Err(format!("Vault code installation failed: {:?}", e));
+    }
+    
+    record_deployment(campaign_id, canister_id, &wasm);
+    
+    Ok(canister_id)
+}
+
+async fn delete_vault_canister(canister_id: Principal) -> Result<(), String> {
+    ic_cdk::management_canister::stop_canister(&StopCanisterArgs { canister_id })
+        .await
+        .map_err(|e| format!("Failed to stop canister: {:?}", e))?;
+    ic_cdk::management_canister::delete_canister(&DeleteCanisterArgs { canister_id })
+        .await
+        .map_err(|e| format!("Failed to delete canister: {:?}", e))
+}
+
+#[update]
+fn set_vault_wasm(module: Vec<u8>, version: String) -> Result<Vec<u8>, String> {
+    let caller = msg_caller();
+    
+    if !is_controller(&caller) {
+        return Err("Only factory controllers can upload the vault wasm".to_string());
+    }
+    
+    if module.is_empty() {
+        return Err("Vault wasm module is empty".to_string());
+    }
+    
+    // The running upgrade targets the current module, so it can't be swapped underneath it
+    if upgrade_running() {
+        return Err("Cannot replace the vault wasm while a fleet upgrade is running".to_string());
+    }
+    
+    let hash = Sha256::digest(&module).to_vec();
+    
+    VAULT_WASM.with(|wasm| {
+        wasm.borrow_mut()
+            .set(Some(VaultWasm {
+                module,
+                hash: hash.clone(),
+                version: version.clone(),
+                uploaded_at: time(),
+            }))
+            .expect("failed to persist vault wasm");
+    });
+    
+    ic_cdk::println!("Vault wasm version {} uploaded", version);
+    Ok(hash)
+}
+
+#[query]
+fn get_vault_wasm_info() -> Option<VaultWasmInfo> {
+    VAULT_WASM.with(|wasm| {
+        wasm.borrow().get().as_ref().map(|w| VaultWasmInfo {
+            hash: w.hash.clone(),
+            version: w.version.clone(),
+            size: w.module.len() as u64,
+            uploaded_at: w.uploaded_at,
+        })
+    })
+}
+
+fn record_deployment(campaign_id: u64, vault: Principal, wasm: &VaultWasm) {
+    VAULT_DEPLOYMENTS.with(|deployments| {
+        deployments.borrow_mut().insert(campaign_id, VaultDeployment {
+            campaign_id,
+            vault,
+            wasm_hash: wasm.hash.clone(),
+            wasm_version: wasm.version.clone(),
+            installed_at: time(),
+        });
+    });
+}
+
+#[query]
+fn list_vault_deployments() -> Vec<VaultDeployment> {
+    VAULT_DEPLOYMENTS.with(|deployments| deployments.borrow().iter().map(|(_, d)| d).collect())
+}
+
+// Queues every vault not yet running the uploaded wasm. The heartbeat then upgrades
+// them `batch_size` at a time and halts the run at the first failure.
+#[update]
+fn start_vault_upgrade(batch_size: u32) -> Result<UpgradeProgress, String> {
+    if !is_controller(&msg_caller()) {
+        return Err("Only factory controllers can upgrade vaults".to_string());
+    }
+    
+    if batch_size == 0 || batch_size > MAX_UPGRADE_BATCH_SIZE {
+        return Err(format!("Batch size must be between 1 and {}", MAX_UPGRADE_BATCH_SIZE));
+    }
+    
+    if upgrade_running() {
+        return Err("A fleet upgrade is already running".to_string());
+    }
+    
+    let wasm = VAULT_WASM
+        .with(|wasm| wasm.borrow().get().clone())
+        .ok_or_else(|| "Vault wasm module has not been uploaded".to_string())?;
+    
+    // Vaults without a deployment record predate tracking, so they are always upgraded
+    let queue: Vec<u64> = CAMPAIGNS.with(|campaigns| {
+        campaigns
+            .borrow()
+            .iter()
+            .filter(|(_, campaign)| campaign.vault_canister_id.is_some())
+            .map(|(campaign_id, _)| campaign_id)
+            .filter(|campaign_id| {
+                let deployed = VAULT_DEPLOYMENTS.with(|deployments| deployments.borrow().get(campaign_id));
+                deployed.is_none_or(|deployment| deployment.wasm_hash != wasm.hash)
+            })
+            .collect()
+    });
+    
+    let now = time();
+    let run = UpgradeRun {
+        target_hash: wasm.hash,
+        target_version: wasm.version,
+        batch_size,
+        total: queue.len() as u64,
+        status: if queue.is_empty() { UpgradeStatus::Completed } else { UpgradeStatus::Running },
+        finished_at: if queue.is_empty() { Some(now) } else { None },
+        queue,
+        upgraded: 0,
+        batches_completed: 0,
+        failure: None,
+        started_at: now,
+    };
+    
+    ic_cdk::println!("Fleet upgrade to {} started for {} vaults", run.target_version, run.total);
+    Ok(save_upgrade_run(run))
+}
+
+#[update]
+fn resume_vault_upgrade() -> Result<UpgradeProgress, String> {
+    if !is_controller(&msg_caller()) {
+        return Err("Only factory controllers can upgrade vaults".to_string());
+    }
+    
+    let mut run = UPGRADE_RUN
+        .with(|cell| cell.borrow().get().clone())
+        .ok_or_else(|| "No fleet upgrade to resume".to_string())?;
+    
+    if run.status != UpgradeStatus::Halted {
+        return Err(format!("Fleet upgrade is {:?}, not halted", run.status));
+    }
+    
+    let current_hash = VAULT_WASM.with(|wasm| wasm.borrow().get().as_ref().map(|w| w.hash.clone()));
+    if current_hash.as_ref() != Some(&run.target_hash) {
+        return Err("Vault wasm changed since the upgrade started; start a new upgrade".to_string());
+    }
+    
+    // The failed vault is still at the head of the queue and is retried first
+    run.status = UpgradeStatus::Running;
+    run.failure = None;
+    
+    Ok(save_upgrade_run(run))
+}
+
+#[update]
+fn cancel_vault_upgrade() -> Result<UpgradeProgress, String> {
+    if !is_controller(&msg_caller()) {
+        return Err("Only factory controllers can upgrade vaults".to_string());
+    }
+    
+    let mut run = UPGRADE_RUN
+        .with(|cell| cell.borrow().get().clone())
+        .ok_or_else(|| "No fleet upgrade to cancel".to_string())?;
+    
+    if !matches!(run.status, UpgradeStatus::Running | UpgradeStatus::Halted) {
+        return Err(format!("Fleet upgrade is already {:?}", run.status));
+    }
+    
+    // A batch already in flight finishes its current vault and then stops
+    run.status = UpgradeStatus::Cancelled;
+    run.finished_at = Some(time());
+    
+    Ok(save_upgrade_run(run))
+}
+
+#[query]
+fn get_vault_upgrade_progress() -> Option<UpgradeProgress> {
+    UPGRADE_RUN.with(|cell| cell.borrow().get().as_ref().map(upgrade_progress))
+}
+
+fn upgrade_running() -> bool {
+    UPGRADE_RUN.with(|cell| {
+        cell.borrow().get().as_ref().is_some_and(|run| run.status == UpgradeStatus::Running)
+    })
+}
+
+fn save_upgrade_run(run: UpgradeRun) -> UpgradeProgress {
+    let progress = upgrade_progress(&run);
+    UPGRADE_RUN.with(|cell| {
+        cell.borrow_mut().set(Some(run)).expect("failed to persist upgrade run");
+    });
+    progress
+}
+
+fn upgrade_progress(run: &UpgradeRun) -> UpgradeProgress {
+    UpgradeProgress {
+        target_hash: run.target_hash.clone(),
+        target_version: run.target_version.clone(),
+        status: run.status.clone(),
+        total: run.total,
+        upgraded: run.upgraded,
+        remaining: run.queue.len() as u64,
+        batches_completed: run.batches_completed,
+        failure: run.failure.clone(),
+        started_at: run.started_at,
+        finished_at: run.finished_at,
+    }
+}
+
+// Upgrades the next batch of the running fleet upgrade. Progress is persisted after
+// every vault so a cancelled or halted run resumes exactly where it stopped.
+async fn upgrade_next_batch() {
+    let Some(run) = UPGRADE_RUN.with(|cell| cell.borrow().get().clone()) else {
+        return;
+    };
+    
+    let wasm = match VAULT_WASM.with(|wasm| wasm.borrow().get().clone()) {
+        Some(wasm) if wasm.hash == run.target_hash => wasm,
+        _ => {
+            ic_cdk::println!("Fleet upgrade target wasm is no longer available");
+            return;
+        }
+    };
+    
+    let batch: Vec<u64> = run.queue.iter().take(run.batch_size as usize).copied().collect();
+    
+    for campaign_id in batch {
+        // Stop early if the run was cancelled while this batch was in flight
+        if !upgrade_running() {
+            return;
+        }
+        
+        let vault = CAMPAIGNS.with(|campaigns| campaigns.borrow().get(&campaign_id))
+            .and_then(|campaign| campaign.vault_canister_id);
+        
+        let result = match vault {
+            Some(vault) => upgrade_vault(vault, &wasm).await.map(|_| vault),
+            None => Err("Campaign has no vault".to_string()),
+        };
+        
+        let mut run = match UPGRADE_RUN.with(|cell| cell.borrow().get().clone()) {
+            Some(run) => run,
+            None => return,
+        };
+        
+        match result {
+            Ok(vault) => {
+                record_deployment(campaign_id, vault, &wasm);
+                run.queue.retain(|id| *id != campaign_id);
+                run.upgraded += 1;
+            }
+            Err(error) => {
+                ic_cdk::println!("Fleet upgrade halted at campaign {}: {}", campaign_id, error);
+                
+                // Leave the vault at the head of the queue so resuming retries it
+                if run.status == UpgradeStatus::Running {
+                    run.status = UpgradeStatus::Halted;
+                }
+                run.failure = Some(VaultUpgradeFailure {
+                    campaign_id,
+                    vault: vault.unwrap_or(Principal::anonymous()),
+                    error,
+                    failed_at: time(),
+                });
+                save_upgrade_run(run);
+                return;
+            }
+        }
+        
+        save_upgrade_run(run);
+    }
+    
+    if let Some(mut run) = UPGRADE_RUN.with(|cell| cell.borrow().get().clone()) {
+        run.batches_completed += 1;
+        if run.queue.is_empty() && run.status == UpgradeStatus::Running {
+            run.status = UpgradeStatus::Completed;
+            run.finished_at = Some(time());
+            ic_cdk::println!("Fleet upgrade to {} completed", run.target_version);
+        }
+        save_upgrade_run(run);
+    }
+}
+
+// Stops the vault so no calls are in flight during the upgrade, and always tries to
+// restart it so a failed upgrade leaves the old code serving.
+async fn upgrade_vault(vault: Principal, wasm: &VaultWasm) -> Result<(), String> {
+    ic_cdk::management_canister::stop_canister(&StopCanisterArgs { canister_id: vault })
+        .await
+        .map_err(|e| format!("Failed to stop vault: {:?}", e))?;
+    
+    let install_args = InstallCodeArgs {
+        mode: CanisterInstallMode::Upgrade(None),
+        canister_id: vault,
+        wasm_module: wasm.module.clone(),
+        arg: candid::encode_args(()).map_err(|e| format!("Failed to encode upgrade args: {:?}", e))?,
+    };
+    let installed = ic_cdk::management_canister::install_code(&install_args)
+        .await
+        .map_err(|e| format!("Vault upgrade failed: {:?}", e));
+    
+    let started = ic_cdk::management_canister::start_canister(&StartCanisterArgs { canister_id: vault })
+        .await
+        .map_err(|e| format!("Failed to restart vault: {:?}", e));
+    
+    installed.and(started)
+}
+
+#[query]
+fn get_campaign(campaign_id: u64) -> Option<CampaignMetadata> {
+    CAMPAIGNS.with(|campaigns| campaigns.borrow().get(&campaign_id))
+}
+
+impl CampaignFilter {
+    fn matches(&self, campaign: &CampaignMetadata) -> bool {
+        self.status.as_ref().is_none_or(|status| campaign.status == *status)
+            && self.creator.is_none_or(|creator| campaign.creator == creator)
+            && self.min_funding_goal.is_none_or(|min| campaign.funding_goal >= min)
+            && self.max_funding_goal.is_none_or(|max| campaign.funding_goal <= max)
+            && self.min_revenue_share.is_none_or(|min| campaign.revenue_share_percentage >= min)
+            && self.max_revenue_share.is_none_or(|max| campaign.revenue_share_percentage <= max)
+            && self.created_after.is_none_or(|after| campaign.created_at >= after)
+            && self.created_before.is_none_or(|before| campaign.created_at <= before)
+            && self.category.as_ref().is_none_or(|category| {
+                campaign.details.as_ref().is_some_and(|d| d.category.as_ref() == Some(category))
+            })
+            && self.tag.as_ref().is_none_or(|tag| {
+                let tag = tag.to_lowercase();
+                campaign.details.as_ref().is_some_and(|d| d.tags.contains(&tag))
+            })
+            && self.verified_creator.is_none_or(|verified| {
+                campaign.verification.as_ref().is_some_and(|v| v.verified) == verified
+            })
+    }
+}
+
+fn sort_key(field: CampaignSortField, campaign_id: u64, campaign: &CampaignMetadata) -> u64 {
+    match field {
+        CampaignSortField::CampaignId => campaign_id,
+        CampaignSortField::CreatedAt => campaign.created_at,
+        CampaignSortField::FundingGoal => campaign.funding_goal,
+        CampaignSortField::RevenueShare => campaign.revenue_share_percentage as u64,
+    }
+}
+
+// Campaigns are ordered by the sort key with the campaign ID as tie-breaker, so the
+// order is total and a cursor resumes exactly after the last campaign returned.
+#[query]
+fn list_campaigns(
+    filter: CampaignFilter,
+    sort: Option<CampaignSort>,
+    cursor: Option<CampaignCursor>,
+    limit: Option<u32>,
+) -> CampaignPage {
+    let sort = sort.unwrap_or(CampaignSort {
+        field: CampaignSortField::CampaignId,
+        descending: false,
+    });
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as usize;
+    
+    let is_after_cursor = |position: &CampaignCursor| match cursor {
+        None => true,
+        Some(cursor) if sort.descending => *position < cursor,
+        Some(cursor) => *position > cursor,
+    };
+    
+    let mut matching: Vec<(CampaignCursor, CampaignMetadata)> = CAMPAIGNS.with(|campaigns| {
+        campaigns
+            .borrow()
+            .iter()
+            .filter(|(_, campaign)| filter.matches(campaign))
+            .map(|(campaign_id, campaign)| {
+                let position = CampaignCursor {
+                    sort_key: sort_key(sort.field, campaign_id, &campaign),
+                    campaign_id,
+                };
+                (position, campaign)
+            })
+            .filter(|(position, _)| is_after_cursor(position))
+            .collect()
+    });
+    
+    matching.sort_by_key(|(position, _)| *position);
+    if sort.descending {
+        matching.reverse();
+    }
+    
+    let has_more = matching.len() > limit;
+    matching.truncate(limit);
+    
+    let next_cursor = if has_more {
+        matching.last().map(|(position, _)| *position)
+    } else {
+        None
+    };
+    
+    CampaignPage {
+        campaigns: matching
+            .into_iter()
+            .map(|(position, campaign)| (position.campaign_id, campaign))
+            .collect(),
+        next_cursor,
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SearchCursor {
+    pub score: u32,
+    pub created_at: u64,
+    pub campaign_id: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SearchResult {
+    pub campaign_id: u64,
+    pub score: u32,
+    pub campaign: CampaignMetadata,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SearchPage {
+    pub results: Vec<SearchResult>,
+    pub next_cursor: Option<SearchCursor>, // None once the last page has been returned
+}
+
+// Lowercased alphanumeric words, without stop words and words outside the length limits
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(str::to_lowercase)
+        .filter(|word| (MIN_TERM_LENGTH..=MAX_TERM_LENGTH).contains(&word.len()))
+        .filter(|word| !STOP_WORDS.contains(&word.as_str()))
+}
+
+fn term_frequencies(campaign: &CampaignMetadata) -> HashMap<String, u32> {
+    let mut frequencies = HashMap::new();
+    let mut add = |text: &str, weight: u32| {
+        for term in tokenize(text) {
+            *frequencies.entry(term).or_insert(0) += weight;
+        }
+    };
+    
+    add(&campaign.title, TITLE_WEIGHT);
+    add(&campaign.description, DESCRIPTION_WEIGHT);
+    if let Some(details) = &campaign.details {
+        for tag in &details.tags {
+            add(tag, TAG_WEIGHT);
+        }
+    }
+    
+    frequencies
+}
+
+fn index_campaign(campaign_id: u64, campaign: &CampaignMetadata) {
+    SEARCH_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for (term, frequency) in term_frequencies(campaign) {
+            index.insert(IndexKey { term, campaign_id }, frequency);
+        }
+    });
+}
+
+// Must be given the campaign as it was indexed
+fn unindex_campaign(campaign_id: u64, campaign: &CampaignMetadata) {
+    SEARCH_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for term in term_frequencies(campaign).into_keys() {
+            index.remove(&IndexKey { term, campaign_id });
+        }
+    });
+}
+
+fn rebuild_search_index() {
+    let campaigns: Vec<(u64, CampaignMetadata)> = CAMPAIGNS.with(|campaigns| campaigns.borrow().iter().collect());
+    for (campaign_id, campaign) in &campaigns {
+        index_campaign(*campaign_id, campaign);
+    }
+    ic_cdk::println!("Search index built for {} campaigns", campaigns.len());
+}
+
+// Matches campaigns containing any query term. Results are ranked by the summed term
+// frequency, then by recency, with the campaign ID as final tie-breaker.
+#[query]
+fn search_campaigns(query: String, limit: Option<u32>, cursor: Option<SearchCursor>) -> SearchPage {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as usize;
+    
+    let mut terms: Vec<String> = tokenize(&query).collect();
+    terms.sort();
+    terms.dedup();
+    terms.truncate(MAX_QUERY_TERMS);
+    
+    let mut scores: HashMap<u64, u32> = HashMap::new();
+    SEARCH_INDEX.with(|index| {
+        let index = index.borrow();
+        for term in terms {
+            let start = IndexKey { term: term.clone(), campaign_id: 0 };
+            for (key, frequency) in index.range(start..) {
+                if key.term != term {
+                    break;
+                }
+                *scores.entry(key.campaign_id).or_insert(0) += frequency;
+            }
+        }
+    });
+    
+    let mut ranked: Vec<(SearchCursor, CampaignMetadata)> = CAMPAIGNS.with(|campaigns| {
+        let campaigns = campaigns.borrow();
+        scores
+            .into_iter()
+            .filter_map(|(campaign_id, score)| {
+                let campaign = campaigns.get(&campaign_id)?;
+                let position = SearchCursor { score, created_at: campaign.created_at, campaign_id };
+                Some((position, campaign))
+            })
+            .filter(|(position, _)| cursor.is_none_or(|cursor| *position < cursor))
+            .collect()
+    });
+    
+    // Best match first
+    ranked.sort_by_key(|(position, _)| std::cmp::Reverse(*position));
+    
+    let has_more = ranked.len() > limit;
+    ranked.truncate(limit);
+    
+    let next_cursor = if has_more {
+        ranked.last().map(|(position, _)| *position)
+    } else {
+        None
+    };
+    
+    SearchPage {
+        results: ranked
+            .into_iter()
+            .map(|(position, campaign)| SearchResult {
+                campaign_id: position.campaign_id,
+                score: position.score,
+                campaign,
+            })
+            .collect(),
+        next_cursor,
+    }
+}
+
+// The unbounded queries below are kept for existing clients; prefer `list_campaigns`.
+
+#[query]
+fn get_campaigns_by_creator(creator: Principal) -> Vec<(u64, CampaignMetadata)> {
+    CAMPAIGNS.with(|campaigns| {
+        campaigns
+            .borrow()
+            .iter()
+            .filter(|(_, metadata)| metadata.creator == creator)
+            .collect()
+    })
+}
+
+#[query]
+fn get_all_campaigns() -> Vec<(u64, CampaignMetadata)> {
+    CAMPAIGNS.with(|campaigns| {
+        campaigns
+            .borrow()
+            .iter()
+            .collect()
+    })
+}
+
+#[query]
+fn get_active_campaigns() -> Vec<(u64, CampaignMetadata)> {
+    CAMPAIGNS.with(|campaigns| {
+        campaigns
+            .borrow()
+            .iter()
+            .filter(|(_, metadata)| metadata.status == CampaignStatus::Active)
+            .collect()
+    })
+}
+
+#[update]
+async fn update_campaign_status(campaign_id: u64, status: CampaignStatus) -> Result<(), String> {
+    let caller = msg_caller();
+    
+    let campaign = CAMPAIGNS.with(|campaigns| campaigns.borrow().get(&campaign_id))
+        .ok_or_else(|| "Campaign not found".to_string())?;
+    
+    if campaign.creator != caller {
+        return Err("Only campaign creator can update status".to_string());
+    }
+    
+    // Only the vault knows whether the goal was actually reached
+    if status == CampaignStatus::Funded {
+        return Err("Funded status is set by the campaign vault when the funding goal is reached".to_string());
+    }
+    
+    if status == CampaignStatus::Failed {
+        return Err("Failed status is set by the factory when the funding deadline passes".to_string());
+    }
+    
+    if status == CampaignStatus::Active && campaign.status.can_transition_to(&status) {
+        if campaign.funding_deadline.is_some_and(|deadline| deadline <= time()) {
+            return Err("Funding deadline has passed; update it before launching".to_string());
+        }
+        
+        if campaign.provisioning.as_ref().is_some_and(|p| !p.is_complete()) {
+            return Err("Campaign vault is still being provisioned; see retry_provisioning".to_string());
+        }
+        
+        if campaign.vault_canister_id.is_none() {
+            return Err("Campaign has no vault".to_string());
+        }
+    }
+    
+    // Committed before the vault call so no other transition can slip in while it is in flight,
+    // and undone if the vault refuses
+    transition_campaign_status(campaign_id, status.clone(), caller)?;
+    
+    let vault_result = match (&status, campaign.vault_canister_id) {
+        // Investments open only once the vault has been launched
+        (CampaignStatus::Active, Some(vault)) => launch_vault(vault).await,
+        // Backers of a cancelled campaign must be able to get their money back
+        (CampaignStatus::Cancelled, Some(vault)) => open_vault_refunds(vault).await,
+        _ => Ok(()),
+    };
+    
+    if let Err(e) = vault_result {
+        revert_campaign_status(campaign_id, status, campaign.status, caller);
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[update]
+async fn update_campaign(campaign_id: u64, update: CampaignUpdate) -> Result<CampaignMetadata, String> {
+    let caller = msg_caller();
+    
+    let campaign = CAMPAIGNS.with(|campaigns| campaigns.borrow().get(&campaign_id))
+        .ok_or_else(|| "Campaign not found".to_string())?;
+    
+    if campaign.creator != caller {
+        return Err("Only campaign creator can update the campaign".to_string());
+    }
+    
+    check_update_allowed(&campaign.status, &update)?;
+    
+    let details = update.details.clone().map(normalize_details).transpose()?;
+    let updated = apply_update(&campaign, &update, details.clone());
+    
+    validate_campaign_terms(
+        &updated.title,
+        &updated.description,
+        updated.funding_goal,
+        updated.revenue_share_percentage,
+        update.funding_deadline, // An unchanged deadline may already have passed
+        updated.minimum_funding,
+    )?;
+    
+    if let Some(endpoints) = &update.oracle_endpoints {
+        validate_oracle_endpoints(endpoints)?;
+    }
+    
+    // A newly named channel has to be verified like it would be at creation
+    let channel_changed = details.is_some()
+        && updated.details.as_ref().and_then(|d| d.youtube_channel_id.as_ref())
+            != campaign.details.as_ref().and_then(|d| d.youtube_channel_id.as_ref());
+    let verification = if channel_changed {
+        Some(resolve_verification(caller, updated.details.as_ref()).await?)
+    } else {
+        None
+    };
+    
+    let vault_update = vault_metadata_update(&campaign, &updated);
+    if let (Some(vault), Some(vault_update)) = (campaign.vault_canister_id, vault_update) {
+        update_vault_metadata(vault, vault_update).await?;
+    }
+    
+    // Re-read after the awaits so a concurrent change isn't overwritten
+    let current = CAMPAIGNS.with(|campaigns| campaigns.borrow().get(&campaign_id))
+        .ok_or_else(|| "Campaign not found".to_string())?;
+    check_update_allowed(&current.status, &update)?;
+    
+    let mut updated = apply_update(&current, &update, details);
+    if let Some(verification) = verification {
+        updated.verification = verification;
+    }
+    
+    // The connector still polls the old endpoints until the oracle is registered again
+    let endpoints_changed = updated.oracle_endpoints != current.oracle_endpoints;
+    if endpoints_changed {
+        if let Some(provisioning) = updated.provisioning.as_mut() {
+            provisioning.oracle_registered = false;
+        }
+    }
+    
+    let changes = diff_campaigns(&current, &updated);
+    if changes.is_empty() {
+        return Ok(current);
+    }
+    
+    unindex_campaign(campaign_id, &current);
+    CAMPAIGNS.with(|campaigns| {
+        campaigns.borrow_mut().insert(campaign_id, updated.clone());
+    });
+    index_campaign(campaign_id, &updated);
+    
+    CHANGE_LOGS.with(|logs| {
+        let mut logs = logs.borrow_mut();
+        let mut log = logs.get(&campaign_id).unwrap_or_default();
+        log.entries.push(CampaignChange {
+            actor: caller,
+            changed_at: time(),
+            status: current.status.clone(),
+            changes,
+        });
+        logs.insert(campaign_id, log);
+    });
+    
+    if endpoints_changed {
+        if let Err(e) = provision_campaign(campaign_id).await {
+            ic_cdk::println!("Re-registering the oracle of campaign {} failed: {}", campaign_id, e);
+        }
+        return CAMPAIGNS.with(|campaigns| campaigns.borrow().get(&campaign_id))
+            .ok_or_else(|| "Campaign not found".to_string());
+    }
+    
+    Ok(updated)
+}
+
+#[query]
+fn get_campaign_change_log(campaign_id: u64) -> Vec<CampaignChange> {
+    CHANGE_LOGS.with(|logs| logs.borrow().get(&campaign_id).map(|log| log.entries).unwrap_or_default())
+}
+
+fn check_update_allowed(status: &CampaignStatus, update: &CampaignUpdate) -> Result<(), String> {
+    match status {
+        CampaignStatus::Draft => Ok(()),
+        CampaignStatus::Active => {
+            let description_only = update.title.is_none()
+                && update.funding_goal.is_none()
+                && update.revenue_share_percentage.is_none()
+                && update.oracle_endpoints.is_none()
+                && update.funding_deadline.is_none()
+                && update.minimum_funding.is_none()
+                && update.details.is_none();
+            if description_only {
+                Ok(())
+            } else {
+                Err("Only the description can be changed while a campaign is Active".to_string())
+            }
+        }
+        other => Err(format!("Campaign cannot be edited while {:?}", other)),
+    }
+}
+
+// `details` must already be normalized
+fn apply_update(
+    campaign: &CampaignMetadata,
+    update: &CampaignUpdate,
+    details: Option<CampaignDetails>,
+) -> CampaignMetadata {
+    let mut updated = campaign.clone();
+    
+    if let Some(title) = &update.title {
+        updated.title = title.clone();
+    }
+    if let Some(description) = &update.description {
+        updated.description = description.clone();
+    }
+    if let Some(funding_goal) = update.funding_goal {
+        updated.funding_goal = funding_goal;
+    }
+    if let Some(share) = update.revenue_share_percentage {
+        updated.revenue_share_percentage = share;
+    }
+    if let Some(endpoints) = &update.oracle_endpoints {
+        updated.oracle_endpoints = endpoints.clone();
+    }
+    if let Some(deadline) = update.funding_deadline {
+        updated.funding_deadline = Some(deadline);
+    }
+    if let Some(minimum) = update.minimum_funding {
+        updated.minimum_funding = Some(minimum);
+    }
+    if details.is_some() {
+        updated.details = details;
+    }
+    
+    updated
+}
+
+fn vault_metadata_update(old: &CampaignMetadata, new: &CampaignMetadata) -> Option<VaultMetadataUpdate> {
+    let update = VaultMetadataUpdate {
+        title: (old.title != new.title).then(|| new.title.clone()),
+        funding_goal: (old.funding_goal != new.funding_goal).then_some(new.funding_goal),
+        revenue_share_percentage: (old.revenue_share_percentage != new.revenue_share_percentage)
+            .then_some(new.revenue_share_percentage),
+        oracle_endpoints: (old.oracle_endpoints != new.oracle_endpoints).then(|| new.oracle_endpoints.clone()),
+        funding_deadline: if old.funding_deadline != new.funding_deadline { new.funding_deadline } else { None },
+    };
+    
+    let changed = update.title.is_some()
+        || update.funding_goal.is_some()
+        || update.revenue_share_percentage.is_some()
+        || update.oracle_endpoints.is_some()
+        || update.funding_deadline.is_some();
+    changed.then_some(update)
+}
+
+fn diff_campaigns(old: &CampaignMetadata, new: &CampaignMetadata) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    let mut compare = |field: &str, old_value: String, new_value: String| {
+        if old_value != new_value {
+            changes.push(FieldChange {
+                field: field.to_string(),
+                old_value,
+                new_value,
+            });
+        }
+    };
+    
+    compare("title", old.title.clone(), new.title.clone());
+    compare("description", old.description.clone(), new.description.clone());
+    compare("funding_goal", old.funding_goal.to_string(), new.funding_goal.to_string());
+    compare(
+        "revenue_share_percentage",
+        old.revenue_share_percentage.to_string(),
+        new.revenue_share_percentage.to_string(),
+    );
+    compare("oracle_endpoints", format!("{:?}", old.oracle_endpoints), format!("{:?}", new.oracle_endpoints));
+    compare("funding_deadline", format!("{:?}", old.funding_deadline), format!("{:?}", new.funding_deadline));
+    compare("minimum_funding", format!("{:?}", old.minimum_funding), format!("{:?}", new.minimum_funding));
+    compare("details", format!("{:?}", old.details), format!("{:?}", new.details));
+    
+    changes
+}
+
+// Maps an oracle endpoint URL to the platform revenue-api-connector parses it as, using the
+// connector's own platform table. None when the connector can't read revenue from the host.
+fn parse_api_endpoint(url: &str) -> Option<ApiEndpoint> {
+    let host = url
+        .trim_start_matches("https://")
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    let platform = revenue_platform_for_host(&host)?;
+    
+    Some(ApiEndpoint {
+        platform: platform.name.to_string(),
+        url: url.to_string(),
+        auth_header: None, // Campaign metadata is public, so credentials can't travel with it
+        data_path: platform.revenue_path.to_string(),
+    })
+}
+
+// Endpoints on platforms the connector can't read yet are still accepted; they are kept on
+// the campaign but not registered with the oracle
+fn validate_oracle_endpoints(endpoints: &[String]) -> Result<(), String> {
+    if endpoints.len() > MAX_ORACLE_ENDPOINTS {
+        return Err(format!("At most {} oracle endpoints are allowed", MAX_ORACLE_ENDPOINTS));
+    }
+    endpoints.iter().try_for_each(|url| validate_url(url))
+}
+
+// Runs the provisioning steps the campaign hasn't completed yet, recording progress after each
+async fn provision_campaign(campaign_id: u64) -> Result<(), String> {
+    let newly_started = PROVISIONING_IN_FLIGHT.with(|in_flight| in_flight.borrow_mut().insert(campaign_id));
+    if !newly_started {
+        return Err("Provisioning is already running for this campaign".to_string());
+    }
+    
+    let result = run_provisioning_steps(campaign_id).await;
+    
+    PROVISIONING_IN_FLIGHT.with(|in_flight| in_flight.borrow_mut().remove(&campaign_id));
+    
+    update_provisioning(campaign_id, |provisioning| {
+        provisioning.attempts += 1;
+        provisioning.last_error = result.as_ref().err().cloned();
+    });
+    
+    result
+}
+
+async fn run_provisioning_steps(campaign_id: u64) -> Result<(), String> {
+    let campaign = CAMPAIGNS.with(|campaigns| campaigns.borrow().get(&campaign_id))
+        .ok_or_else(|| "Campaign not found".to_string())?;
+    let provisioning = campaign.provisioning.clone().unwrap_or_default();
+    let vault = campaign
+        .vault_canister_id
+        .ok_or_else(|| "Campaign has no vault".to_string())?;
+    let platform = PLATFORM_CANISTERS.with(|platform| platform.borrow().get().clone());
+    
+    if !provisioning.canister_refs_set {
+        let nft_registry = VERIFICATION_CONFIG
+            .with(|config| config.borrow().get().nft_registry)
+            .ok_or_else(|| "NFT registry is not configured".to_string())?;
+        let stream = platform
+            .ipx_stream
+            .ok_or_else(|| "ipx-stream canister is not configured".to_string())?;
+        let connector = platform
+            .revenue_connector
+            .ok_or_else(|| "Revenue connector canister is not configured".to_string())?;
+        
+        Call::unbounded_wait(vault, "set_canister_refs")
+            .with_args(&(Some(nft_registry), Some(stream), Some(connector)))
+            .await
+            .map_err(|e| format!("Vault call failed: {:?}", e))?
+            .candid::<Result<(), String>>()
+            .map_err(|e| format!("Failed to decode vault response: {:?}", e))?
+            .map_err(|e| format!("Vault rejected canister refs: {}", e))?;
+        
+        update_provisioning(campaign_id, |provisioning| provisioning.canister_refs_set = true);
+    }
+    
+    if !provisioning.oracle_registered {
+        let endpoints: Vec<ApiEndpoint> = campaign
+            .oracle_endpoints
+            .iter()
+            .filter_map(|url| {
+                let endpoint = parse_api_endpoint(url);
+                if endpoint.is_none() {
+                    ic_cdk::println!("Campaign {}: revenue connector can't read '{}', not registering it", campaign_id, url);
+                }
+                endpoint
+            })
+            .collect();
+        
+        // Nothing for the oracle to poll; revenue comes from the creator's own reports
+        if !endpoints.is_empty() {
+            Call::unbounded_wait(vault, "register_revenue_oracle")
+                .with_args(&(endpoints, ORACLE_UPDATE_FREQUENCY_SECONDS))
+                .await
+                .map_err(|e| format!("Vault call failed: {:?}", e))?
+                .candid::<Result<(), String>>()
+                .map_err(|e| format!("Failed to decode vault response: {:?}", e))?
+                .map_err(|e| format!("Oracle registration failed: {}", e))?;
+        }
+        
+        update_provisioning(campaign_id, |provisioning| provisioning.oracle_registered = true);
+    }
+    
+    Ok(())
+}
+
+fn update_provisioning(campaign_id: u64, f: impl FnOnce(&mut Provisioning)) {
+    CAMPAIGNS.with(|campaigns| {
+        let existing = campaigns.borrow().get(&campaign_id);
+        if let Some(mut campaign) = existing {
+            let provisioning = campaign.provisioning.get_or_insert_with(Provisioning::default);
+            f(provisioning);
+            provisioning.updated_at = time();
+            campaigns.borrow_mut().insert(campaign_id, campaign);
+        }
+    });
+}
+
+async fn retry_incomplete_provisioning() {
+    let pending: Vec<u64> = CAMPAIGNS.with(|campaigns| {
+        campaigns
+            .borrow()
+            .iter()
+            .filter(|(_, campaign)| {
+                matches!(campaign.status, CampaignStatus::Draft | CampaignStatus::Active)
+                    && campaign.provisioning.as_ref().is_some_and(|p| {
+                        !p.is_complete() && p.attempts < MAX_PROVISIONING_ATTEMPTS
+                    })
+            })
+            .map(|(campaign_id, _)| campaign_id)
+            .collect()
+    });
+    
+    for campaign_id in pending {
+        if let Err(e) = provision_campaign(campaign_id).await {
+            ic_cdk::println!("Provisioning retry for campaign {} failed: {}", campaign_id, e);
+        }
+    }
+}
+
+#[update]
+async fn retry_provisioning(campaign_id: u64) -> Result<Provisioning, String> {
+    let caller = msg_caller();
+    let campaign = CAMPAIGNS.with(|campaigns| campaigns.borrow().get(&campaign_id))
+        .ok_or_else(|| "Campaign not found".to_string())?;
+    
+    if campaign.creator != caller && !is_controller(&caller) {
+        return Err("Only the creator or a factory controller can retry provisioning".to_string());
+    }
+    
+    if campaign.provisioning.as_ref().is_none_or(|p| p.is_complete()) {
+        return Err("Campaign has nothing left to provision".to_string());
+    }
+    
+    provision_campaign(campaign_id).await?;
+    
+    CAMPAIGNS.with(|campaigns| campaigns.borrow().get(&campaign_id))
+        .and_then(|campaign| campaign.provisioning)
+        .ok_or_else(|| "Campaign not found".to_string())
+}
+
+#[update]
+fn set_platform_canisters(canisters: PlatformCanisters) -> Result<(), String> {
+    if !is_controller(&msg_caller()) {
+        return Err("Only factory controllers can set platform canisters".to_string());
+    }
+    
+    PLATFORM_CANISTERS.with(|platform| {
+        platform.borrow_mut().set(canisters).expect("failed to persist platform canisters");
+    });
+    
+    Ok(())
+}
+
+#[query]
+fn get_platform_canisters() -> PlatformCanisters {
+    PLATFORM_CANISTERS.with(|platform| platform.borrow().get().clone())
+}
+
+// Vaults only take revenue limits and quarantine reviews from the factory, so controllers go through here
+#[update]
+async fn set_vault_revenue_limit(
+    campaign_id: u64,
+    reporter: Principal,
+    limit: Option<RevenueLimit>,
+    source: Option<String>, // Limits one of the reporter's sources instead of the reporter
+) -> Result<(), String> {
+    if !is_controller(&msg_caller()) {
+        return Err("Only factory controllers can set revenue limits".to_string());
+    }
+    
+    let vault = campaign_vault(campaign_id)?;
+    Call::unbounded_wait(vault, "set_revenue_limit")
+        .with_args(&(reporter, limit, source))
+        .await
+        .map_err(|e| format!("Vault call failed: {:?}", e))?
+        .candid::<Result<(), String>>()
+        .map_err(|e| format!("Failed to decode vault response: {:?}", e))?
+}
+
+#[update]
+async fn review_vault_revenue(campaign_id: u64, report_id: u64, accept: bool) -> Result<(), String> {
+    if !is_controller(&msg_caller()) {
+        return Err("Only factory controllers can review quarantined revenue".to_string());
+    }
+    
+    let vault = campaign_vault(campaign_id)?;
+    Call::unbounded_wait(vault, "review_quarantined_revenue")
+        .with_args(&(report_id, accept))
+        .await
+        .map_err(|e| format!("Vault call failed: {:?}", e))?
+        .candid::<Result<(), String>>()
+        .map_err(|e| format!("Failed to decode vault response: {:?}", e))?
+}
+
+// Settles a vault operation from the ledger's history when reconciliation can't: either checks
+// the given block holds the journaled transfer, or searches back to when it was made
+#[update]
+async fn resolve_vault_operation(campaign_id: u64, op_id: u64, block_index: Option<u64>) -> Result<(), String> {
+    if !is_controller(&msg_caller()) {
+        return Err("Only factory controllers can resolve vault operations".to_string());
+    }
+    
+    let vault = campaign_vault(campaign_id)?;
+    Call::unbounded_wait(vault, "resolve_pending_operation")
+        .with_args(&(op_id, block_index))
+        .await
+        .map_err(|e| format!("Vault call failed: {:?}", e))?
+        .candid::<Result<(), String>>()
+        .map_err(|e| format!("Failed to decode vault response: {:?}", e))?
+}
+
+fn campaign_vault(campaign_id: u64) -> Result<Principal, String> {
+    CAMPAIGNS.with(|campaigns| campaigns.borrow().get(&campaign_id))
+        .ok_or_else(|| "Campaign not found".to_string())?
+        .vault_canister_id
+        .ok_or_else(|| "Campaign has no vault".to_string())
+}
+
+async fn launch_vault(vault: Principal) -> Result<(), String> {
+    Call::unbounded_wait(vault, "launch")
+        .await
+        .map_err(|e| format!("Vault call failed: {:?}", e))?
+        .candid::<Result<(), String>>()
+        .map_err(|e| format!("Failed to decode vault response: {:?}", e))?
+        .map_err(|e| format!("Vault refused to launch: {}", e))
+}
+
+async fn update_vault_metadata(vault: Principal, update: VaultMetadataUpdate) -> Result<(), String> {
+    Call::unbounded_wait(vault, "update_campaign_metadata")
+        .with_arg(update)
+        .await
+        .map_err(|e| format!("Vault call failed: {:?}", e))?
+        .candid::<Result<(), String>>()
+        .map_err(|e| format!("Failed to decode vault response: {:?}", e))?
+        .map_err(|e| format!("Vault rejected the update: {}", e))
+}
+
+// Called by a campaign's vault once its funding goal has been reached
+#[update]
+fn notify_funding_goal_reached(campaign_id: u64) -> Result<(), String> {
+    let caller = msg_caller();
+    
+    let campaign = CAMPAIGNS.with(|campaigns| campaigns.borrow().get(&campaign_id))
+        .ok_or_else(|| "Campaign not found".to_string())?;
+    
+    if campaign.vault_canister_id != Some(caller) {
+        return Err("Only the campaign vault can report the funding goal".to_string());
+    }
+    
+    // The vault may retry a notification that already went through
+    if campaign.status == CampaignStatus::Funded {
+        return Ok(());
+    }
+    
+    transition_campaign_status(campaign_id, CampaignStatus::Funded, caller)
+}
+
+#[heartbeat]
+async fn heartbeat() {
+    let now = time();
+    
+    // Batches go out back to back while a fleet upgrade is running
+    if upgrade_running() {
+        if let Some(_job) = try_start_job(Job::FleetUpgrade, now, 0) {
+            upgrade_next_batch().await;
+        }
+    }
+    
+    if let Some(_job) = try_start_job(Job::DeadlineSweep, now, DEADLINE_SWEEP_INTERVAL_NANOS) {
+        settle_expired_campaigns(now).await;
+    }
+    
+    if let Some(_job) = try_start_job(Job::VerificationRefresh, now, VERIFICATION_REFRESH_INTERVAL_NANOS) {
+        refresh_expired_verifications(now).await;
+    }
+    
+    if let Some(_job) = try_start_job(Job::ProvisioningRetry, now, PROVISIONING_RETRY_INTERVAL_NANOS) {
+        retry_incomplete_provisioning().await;
+    }
+    
+    if let Some(_job) = try_start_job(Job::StatsAggregation, now, STATS_AGGREGATION_INTERVAL_NANOS) {
+        aggregate_platform_stats().await;
+    }
+    
+    let cycles_interval = CYCLES_CONFIG.with(|config| config.borrow().get().check_interval_seconds);
+    if let Some(_job) = try_start_job(Job::CyclesCheck, now, cycles_interval.saturating_mul(1_000_000_000)) {
+        check_vault_cycles().await;
+    }
+}
+
+// Settles every Active campaign whose deadline has passed. Failures are logged and
+// retried on the next sweep since the campaign stays Active.
+async fn settle_expired_campaigns(now: u64) {
+    let expired: Vec<(u64, CampaignMetadata)> = CAMPAIGNS.with(|campaigns| {
+        campaigns
+            .borrow()
+            .iter()
+            .filter(|(_, campaign)| {
+                campaign.status == CampaignStatus::Active
+                    && campaign.funding_deadline.is_some_and(|deadline| deadline <= now)
+            })
+            .collect()
+    });
+    
+    for (campaign_id, campaign) in expired {
+        if let Err(e) = settle_campaign(campaign_id, &campaign).await {
+            ic_cdk::println!("Failed to settle campaign {} at deadline: {}", campaign_id, e);
+        }
+    }
+}
+
+async fn settle_campaign(campaign_id: u64, campaign: &CampaignMetadata) -> Result<(), String> {
+    let vault = campaign
+        .vault_canister_id
+        .ok_or_else(|| "Campaign has no vault".to_string())?;
+    
+    let (raised, _, _) = Call::unbounded_wait(vault, "get_funding_progress")
+        .await
+        .map_err(|e| format!("Vault call failed: {:?}", e))?
+        .candid::<(u64, u64, f64)>()
+        .map_err(|e| format!("Failed to decode funding progress: {:?}", e))?;
+    
+    let threshold = campaign.minimum_funding.unwrap_or(campaign.funding_goal);
+    
+    if raised >= threshold {
+        transition_campaign_status(campaign_id, CampaignStatus::Funded, canister_self())?;
+        ic_cdk::println!("Campaign {} funded at deadline with {}", campaign_id, raised);
+    } else {
+        // Refunds must be open before the campaign is marked Failed so a failed call is retried
+        open_vault_refunds(vault).await?;
+        transition_campaign_status(campaign_id, CampaignStatus::Failed, canister_self())?;
+        ic_cdk::println!("Campaign {} failed at deadline with {} of {}", campaign_id, raised, threshold);
+    }
+    
+    Ok(())
+}
+
+async fn open_vault_refunds(vault: Principal) -> Result<(), String> {
+    Call::unbounded_wait(vault, "open_refunds")
+        .await
+        .map_err(|e| format!("Vault call failed: {:?}", e))?
+        .candid::<Result<(), String>>()
+        .map_err(|e| format!("Failed to decode vault response: {:?}", e))?
+        .map_err(|e| format!("Vault refused to open refunds: {}", e))
+}
+
+// Polls the cycle balance of every vault and tops up the ones below the threshold
+async fn check_vault_cycles() {
+    let config = CYCLES_CONFIG.with(|config| config.borrow().get().clone());
+    
+    let vaults: Vec<(u64, Principal)> = CAMPAIGNS.with(|campaigns| {
+        campaigns
+            .borrow()
+            .iter()
+            .filter_map(|(campaign_id, campaign)| campaign.vault_canister_id.map(|vault| (campaign_id, vault)))
+            .collect()
+    });
+    
+    for (campaign_id, vault) in vaults {
+        let (cycles, last_error) = match vault_cycle_balance(vault).await {
+            Ok(cycles) if cycles < config.top_up_threshold => match top_up_vault(campaign_id, vault, cycles, &config).await {
+                Ok(topped_up) => (topped_up, None),
+                Err(e) => (cycles, Some(e)),
+            },
+            Ok(cycles) => (cycles, None),
+            Err(e) => {
+                // Keep the last known balance so the listing still shows something useful
+                let previous = VAULT_CYCLES.with(|statuses| statuses.borrow().get(&campaign_id).map(|s| s.cycles));
+                (previous.unwrap_or(0), Some(e))
+            }
+        };
+        
+        if let Some(e) = &last_error {
+            ic_cdk::println!("Cycles check for vault {} failed: {}", vault.to_text(), e);
+        }
+        
+        VAULT_CYCLES.with(|statuses| {
+            statuses.borrow_mut().insert(campaign_id, VaultCyclesStatus {
+                campaign_id,
+                vault,
+                cycles,
+                checked_at: time(),
+                last_error,
+            });
+        });
+    }
+}
+
+async fn vault_cycle_balance(vault: Principal) -> Result<u128, String> {
+    let status = ic_cdk::management_canister::canister_status(&CanisterStatusArgs { canister_id: vault })
+        .await
+        .map_err(|e| format!("Failed to get canister status: {:?}", e))?;
+    
+    u128::try_from(status.cycles.0).map_err(|_| "Cycle balance does not fit in u128".to_string())
+}
+
+// Returns the vault's new balance
+async fn top_up_vault(
+    campaign_id: u64,
+    vault: Principal,
+    balance_before: u128,
+    config: &CyclesConfig,
+) -> Result<u128, String> {
+    if canister_cycle_balance() < config.factory_reserve.saturating_add(config.top_up_amount) {
+        return Err("Factory balance is too low to top up vaults".to_string());
+    }
+    
+    ic_cdk::management_canister::deposit_cycles(&DepositCyclesArgs { canister_id: vault }, config.top_up_amount)
+        .await
+        .map_err(|e| format!("Failed to deposit cycles: {:?}", e))?;
+    
+    TOP_UP_HISTORY.with(|history| {
+        history
+            .borrow()
+            .append(&CyclesTopUp {
+                campaign_id,
+                vault,
+                amount: config.top_up_amount,
+                balance_before,
+                timestamp: time(),
+            })
+            .expect("failed to append top-up");
+    });
+    
+    ic_cdk::println!("Topped up vault {} with {} cycles", vault.to_text(), config.top_up_amount);
+    Ok(balance_before + config.top_up_amount)
+}
+
+#[update]
+fn set_cycles_config(config: CyclesConfig) -> Result<(), String> {
+    if !is_controller(&msg_caller()) {
+        return Err("Only factory controllers can change the cycles config".to_string());
+    }
+    
+    if config.top_up_amount == 0 {
+        return Err("Top-up amount must be greater than 0".to_string());
+    }
+    
+    if config.check_interval_seconds < 60 {
+        return Err("Check interval must be at least 60 seconds".to_string());
+    }
+    
+    CYCLES_CONFIG.with(|cell| {
+        cell.borrow_mut().set(config).expect("failed to persist cycles config");
+    });
+    
+    Ok(())
+}
+
+#[query]
+fn get_cycles_config() -> CyclesConfig {
+    CYCLES_CONFIG.with(|config| config.borrow().get().clone())
+}
+
+// Balances as of the latest check, one entry per vault
+#[query]
+fn list_vault_cycles() -> Vec<VaultCyclesStatus> {
+    VAULT_CYCLES.with(|statuses| statuses.borrow().iter().map(|(_, status)| status).collect())
+}
+
+// Refreshes every vault's cached figures, then rebuilds the platform snapshot from the cache
+// so a vault that can't be reached still counts with its last known figures
+async fn aggregate_platform_stats() {
+    let vaults: Vec<(u64, Principal)> = CAMPAIGNS.with(|campaigns| {
+        campaigns
+            .borrow()
+            .iter()
+            .filter_map(|(campaign_id, campaign)| campaign.vault_canister_id.map(|vault| (campaign_id, vault)))
+            .collect()
+    });
+    
+    for (campaign_id, vault) in vaults {
+        let result = Call::unbounded_wait(vault, "get_vault_stats")
+            .await
+            .map_err(|e| format!("Vault call failed: {:?}", e))
+            .and_then(|response| {
+                response
+                    .candid::<Option<VaultStatsReply>>()
+                    .map_err(|e| format!("Failed to decode vault stats: {:?}", e))
+            })
+            .and_then(|state| state.ok_or_else(|| "Vault is not initialized".to_string()));
+        
+        let previous = VAULT_STATS.with(|stats| stats.borrow().get(&campaign_id));
+        let stats = match result {
+            Ok(state) => VaultStats {
+                campaign_id,
+                current_funding: state.current_funding,
+                backers: state.backer_count,
+                total_revenue: state.total_revenue,
+                refreshed_at: time(),
+                last_error: None,
+            },
+            Err(e) => {
+                ic_cdk::println!("Stats read for vault {} failed: {}", vault.to_text(), e);
+                VaultStats {
+                    last_error: Some(e),
+                    ..previous.unwrap_or(VaultStats {
+                        campaign_id,
+                        current_funding: 0,
+                        backers: 0,
+                        total_revenue: 0,
+                        refreshed_at: 0,
+                        last_error: None,
+                    })
+                }
+            }
+        };
+        
+        VAULT_STATS.with(|cache| {
+            cache.borrow_mut().insert(campaign_id, stats);
+        });
+    }
+    
+    let previous = PLATFORM_STATS.with(|snapshot| snapshot.borrow().get().clone());
+    let stream = PLATFORM_CANISTERS.with(|platform| platform.borrow().get().ipx_stream);
+    let stream_result = match stream {
+        Some(stream) => Call::unbounded_wait(stream, "get_stream_stats")
+            .await
+            .map_err(|e| format!("ipx-stream call failed: {:?}", e))
+            .and_then(|response| {
+                response
+                    .candid::<StreamStats>()
+                    .map_err(|e| format!("Failed to decode stream stats: {:?}", e))
+            }),
+        None => Err("ipx-stream canister is not configured".to_string()),
+    };
+    let (stream_stats, stream_stats_error) = match stream_result {
+        Ok(stats) => (stats, None),
+        Err(e) => {
+            let stats = previous.as_ref().map(|p| StreamStats {
+                total_streams: 0,
+                active_streams: p.active_streams,
+                total_volume: p.payouts_streamed,
+                claimed_volume: p.payouts_claimed,
+            });
+            (stats.unwrap_or_default(), Some(e))
+        }
+    };
+    
+    let mut by_status: Vec<(CampaignStatus, u64)> = Vec::new();
+    CAMPAIGNS.with(|campaigns| {
+        for (_, campaign) in campaigns.borrow().iter() {
+            match by_status.iter_mut().find(|(status, _)| *status == campaign.status) {
+                Some((_, count)) => *count += 1,
+                None => by_status.push((campaign.status, 1)),
+            }
+        }
+    });
+    
+    let mut snapshot = PlatformStats {
+        taken_at: time(),
+        campaigns: by_status.iter().map(|(_, count)| count).sum(),
+        campaigns_by_status: by_status,
+        total_raised: 0,
+        total_backers: 0,
+        total_revenue_reported: 0,
+        payouts_streamed: stream_stats.total_volume,
+        payouts_claimed: stream_stats.claimed_volume,
+        active_streams: stream_stats.active_streams,
+        stale_vaults: 0,
+        stream_stats_error,
+    };
+    VAULT_STATS.with(|cache| {
+        for (_, stats) in cache.borrow().iter() {
+            snapshot.total_raised = snapshot.total_raised.saturating_add(stats.current_funding);
+            snapshot.total_backers += stats.backers;
+            snapshot.total_revenue_reported = snapshot.total_revenue_reported.saturating_add(stats.total_revenue);
+            snapshot.stale_vaults += u64::from(stats.last_error.is_some());
+        }
+    });
+    
+    DAILY_STATS.with(|daily| {
+        daily.borrow_mut().insert(snapshot.taken_at / NANOS_PER_DAY, snapshot.clone());
+    });
+    PLATFORM_STATS.with(|cell| {
+        cell.borrow_mut().set(Some(snapshot)).expect("failed to persist platform stats");
+    });
+}
+
+#[update]
+async fn refresh_platform_stats() -> Result<PlatformStats, String> {
+    if !is_controller(&msg_caller()) {
+        return Err("Only factory controllers can refresh platform stats".to_string());
+    }
+    
+    // Takes the heartbeat job slot so the two never overlap; this counts as the scheduled run
+    let now = time();
+    JOBS.with(|jobs| {
+        if let Some(state) = jobs.borrow_mut().get_mut(&Job::StatsAggregation) {
+            state.next_run = state.next_run.min(now);
+        }
+    });
+    let job = try_start_job(Job::StatsAggregation, now, STATS_AGGREGATION_INTERVAL_NANOS)
+        .ok_or_else(|| "Stats aggregation is already running".to_string())?;
+    aggregate_platform_stats().await;
+    drop(job);
+    
+    get_platform_stats().ok_or_else(|| "No platform stats recorded".to_string())
+}
+
+#[query]
+fn get_platform_stats() -> Option<PlatformStats> {
+    PLATFORM_STATS.with(|snapshot| snapshot.borrow().get().clone())
+}
+
+// One entry per day for the most recent `days` days that have a snapshot, oldest first
+#[query]
+fn get_platform_stats_history(days: Option<u32>) -> Vec<DailyPlatformStats> {
+    let days = days.unwrap_or(DEFAULT_STATS_HISTORY_DAYS).clamp(1, MAX_STATS_HISTORY_DAYS);
+    let first_day = (time() / NANOS_PER_DAY).saturating_sub(u64::from(days) - 1);
+    
+    DAILY_STATS.with(|daily| {
+        daily
+            .borrow()
+            .range(first_day..)
+            .map(|(day, stats)| DailyPlatformStats { day, stats })
+            .collect()
+    })
+}
+
+#[query]
+fn list_vault_stats() -> Vec<VaultStats> {
+    VAULT_STATS.with(|cache| cache.borrow().iter().map(|(_, stats)| stats).collect())
+}
+
+#[query]
+fn get_top_up_history(campaign_id: Option<u64>) -> Vec<CyclesTopUp> {
+    TOP_UP_HISTORY.with(|history| {
+        history
+            .borrow()
+            .iter()
+            .filter(|top_up| campaign_id.is_none_or(|id| top_up.campaign_id == id))
+            .collect()
+    })
+}
+
+#[query]
+fn get_status_history(campaign_id: u64) -> Vec<StatusTransition> {
+    STATUS_HISTORY.with(|history| {
+        history.borrow().get(&campaign_id).map(|h| h.transitions).unwrap_or_default()
+    })
+}
+
+fn transition_campaign_status(
+    campaign_id: u64,
+    status: CampaignStatus,
+    actor: Principal,
+) -> Result<(), String> {
+    let from = CAMPAIGNS.with(|campaigns| {
+        let existing = campaigns.borrow().get(&campaign_id);
+        let mut campaign = existing.ok_or_else(|| "Campaign not found".to_string())?;
+        
+        if !campaign.status.can_transition_to(&status) {
+            return Err(format!(
+                "Invalid status transition from {:?} to {:?}",
+                campaign.status, status
+            ));
+        }
+        
+        let from = std::mem::replace(&mut campaign.status, status.clone());
+        campaigns.borrow_mut().insert(campaign_id, campaign);
+        Ok(from)
+    })?;
+    
+    // The creation deposit comes back once the raise succeeds and is kept otherwise
+    match status {
+        CampaignStatus::Funded => ic_cdk::futures::spawn(async move {
+            if let Err(e) = refund_creation_deposit(campaign_id).await {
+                ic_cdk::println!("Failed to refund deposit for campaign {}: {}", campaign_id, e);
+            }
+        }),
+        CampaignStatus::Failed | CampaignStatus::Cancelled => forfeit_creation_deposit(campaign_id),
+        _ => {}
+    }
+    
+    record_transition(campaign_id, Some(from), status, actor);
+    Ok(())
+}
+
+// Puts a campaign back in `previous` after the vault refused the move to `status`, unless
+// something else has moved it on since
+fn revert_campaign_status(campaign_id: u64, status: CampaignStatus, previous: CampaignStatus, actor: Principal) {
+    let reverted = CAMPAIGNS.with(|campaigns| {
+        let existing = campaigns.borrow().get(&campaign_id);
+        match existing {
+            Some(mut campaign) if campaign.status == status => {
+                campaign.status = previous.clone();
+                campaigns.borrow_mut().insert(campaign_id, campaign);
+                true
+            }
+            _ => false,
+        }
+    });
+    if !reverted {
+        return;
+    }
+    
+    // A Draft or Active campaign still had its deposit, so cancelling is what forfeited it
+    if status == CampaignStatus::Cancelled {
+        DEPOSITS.with(|deposits| {
+            let existing = deposits.borrow().get(&campaign_id);
+            if let Some(mut deposit) = existing.filter(|d| d.status == DepositStatus::Forfeited) {
+                deposit.status = DepositStatus::Held;
+                deposit.updated_at = time();
+                deposits.borrow_mut().insert(campaign_id, deposit);
+            }
+        });
+    }
+    
+    record_transition(campaign_id, Some(status), previous, actor);
+}
+
+fn check_creator_access(creator: Principal) -> Result<(), String> {
+    if creator == Principal::anonymous() {
+        return Err("Anonymous principals cannot create campaigns".to_string());
+    }
+    
+    let access = CREATOR_ACCESS.with(|access| access.borrow().get(&creator));
+    let allowlist_only = ANTI_SPAM_CONFIG.with(|config| config.borrow().get().allowlist_only);
+    
+    match access {
+        Some(CreatorAccess::Denied) => Err("Principal is not allowed to create campaigns".to_string()),
+        Some(CreatorAccess::Allowed) => Ok(()),
+        None if allowlist_only => Err("Campaign creation is limited to allowlisted principals".to_string()),
+        None => Ok(()),
+    }
+}
+
+fn record_creation_attempt(creator: Principal) -> Result<(), String> {
+    let config = ANTI_SPAM_CONFIG.with(|config| config.borrow().get().clone());
+    let now = time();
+    let window_start = now.saturating_sub(config.window_seconds.saturating_mul(1_000_000_000));
+    
+    CREATION_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let mut entry = history.get(&creator).unwrap_or_default();
+        entry.timestamps.retain(|timestamp| *timestamp > window_start);
+        
+        if entry.timestamps.len() >= config.max_campaigns_per_window as usize {
+            return Err(format!(
+                "Rate limit reached: at most {} campaigns per {} seconds",
+                config.max_campaigns_per_window, config.window_seconds
+            ));
+        }
+        
+        entry.timestamps.push(now);
+        history.insert(creator, entry);
+        Ok(())
+    })
+}
+
+fn is_rate_limited(creator: Principal) -> bool {
+    let config = ANTI_SPAM_CONFIG.with(|config| config.borrow().get().clone());
+    let window_start = time().saturating_sub(config.window_seconds.saturating_mul(1_000_000_000));
+    
+    CREATION_HISTORY.with(|history| {
+        history.borrow().get(&creator).is_some_and(|entry| {
+            let recent = entry.timestamps.iter().filter(|timestamp| **timestamp > window_start).count();
+            recent >= config.max_campaigns_per_window as usize
+        })
+    })
+}
+
+// Pulls the configured deposit from the creator; they must have approved the factory first
+async fn take_creation_deposit(campaign_id: u64, creator: Principal) -> Result<(), String> {
+    let config = ANTI_SPAM_CONFIG.with(|config| config.borrow().get().clone());
+    
+    if config.deposit_amount == 0 {
+        return Ok(());
+    }
+    
+    let ledger = config
+        .deposit_ledger
+        .ok_or_else(|| "Deposit ledger is not configured".to_string())?;
+    
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from: Account { owner: creator, subaccount: None },
+        to: Account { owner: canister_self(), subaccount: None },
+        amount: Nat::from(config.deposit_amount),
+        fee: None,
+        memo: Some(campaign_id.to_be_bytes().to_vec()),
+        created_at_time: None,
+    };
+    
+    let result: Result<Nat, TransferFromError> = Call::unbounded_wait(ledger, "icrc2_transfer_from")
+        .with_arg(args)
+        .await
+        .map_err(|e| format!("Failed to call ledger: {:?}", e))?
+        .candid()
+        .map_err(|e| format!("Failed to decode ledger response: {:?}", e))?;
+    
+    let block_index = result.map_err(|e| format!("Creation deposit transfer failed: {:?}", e))?;
+    
+    DEPOSITS.with(|deposits| {
+        deposits.borrow_mut().insert(campaign_id, CreationDeposit {
+            creator,
+            ledger,
+            amount: config.deposit_amount,
+            block_index: nat_to_u64(block_index)?,
+            status: DepositStatus::Held,
+            updated_at: time(),
+            refund_created_at_time: None,
+            refund_fee: None,
+        });
+        Ok(())
+    })
+}
+
+// Returns a held deposit to the creator minus the ledger fee. Succeeds without a
+// transfer when the campaign took no deposit. A refund left `Refunding` by a failed or
+// interrupted attempt is retried with the same transfer, which the ledger deduplicates.
+async fn refund_creation_deposit(campaign_id: u64) -> Result<(), String> {
+    let Some(mut deposit) = DEPOSITS.with(|deposits| deposits.borrow().get(&campaign_id)) else {
+        return Ok(());
+    };
+    
+    match deposit.status {
+        DepositStatus::Held => {
+            deposit.status = DepositStatus::Refunding;
+            deposit.refund_created_at_time = Some(time());
+            deposit.refund_fee = None;
+            deposit.updated_at = time();
+            DEPOSITS.with(|deposits| deposits.borrow_mut().insert(campaign_id, deposit.clone()));
+        }
+        // Deposits refunding from before attempts were recorded get their transfer fixed now
+        DepositStatus::Refunding if deposit.refund_created_at_time.is_none() => {
+            deposit.refund_created_at_time = Some(time());
+            DEPOSITS.with(|deposits| deposits.borrow_mut().insert(campaign_id, deposit.clone()));
+        }
+        DepositStatus::Refunding => {}
+        _ => return Err(format!("Deposit is {:?}", deposit.status)),
+    }
+    
+    match transfer_deposit_back(campaign_id).await {
+        Ok(block_index) => {
+            set_deposit_status(campaign_id, DepositStatus::Refunded { block_index });
+            Ok(())
+        }
+        // The ledger turned the transfer down, so it can be fixed afresh on the next attempt
+        Err(DepositRefundError::Rejected(message)) => {
+            DEPOSITS.with(|deposits| {
+                let mut deposits = deposits.borrow_mut();
+                if let Some(mut deposit) = deposits.get(&campaign_id).filter(|d| d.status == DepositStatus::Refunding) {
+                    deposit.status = DepositStatus::Held;
+                    deposit.refund_created_at_time = None;
+                    deposit.refund_fee = None;
+                    deposit.updated_at = time();
+                    deposits.insert(campaign_id, deposit);
+                }
+            });
+            Err(message)
+        }
+        // The ledger may have paid; the deposit stays Refunding until a retry settles it
+        Err(DepositRefundError::Unknown(message)) => Err(message),
+    }
+}
+
+enum DepositRefundError {
+    Rejected(String),
+    Unknown(String),
+}
+
+fn set_deposit_status(campaign_id: u64, status: DepositStatus) {
+    DEPOSITS.with(|deposits| {
+        let mut deposits = deposits.borrow_mut();
+        if let Some(mut deposit) = deposits.get(&campaign_id) {
+            deposit.status = status;
+            deposit.updated_at = time();
+            deposits.insert(campaign_id, deposit);
+        }
+    });
+}
+
+async fn transfer_deposit_back(campaign_id: u64) -> Result<u64, DepositRefundError> {
+    let deposit = DEPOSITS.with(|deposits| deposits.borrow().get(&campaign_id))
+        .ok_or_else(|| DepositRefundError::Unknown("Deposit not found".to_string()))?;
+    let created_at_time = deposit.refund_created_at_time
+        .ok_or_else(|| DepositRefundError::Unknown("Deposit refund was not started".to_string()))?;
+    
+    let fee = match deposit.refund_fee {
+        Some(fee) => fee,
+        None => {
+            let fee: Nat = Call::unbounded_wait(deposit.ledger, "icrc1_fee")
+                .await
+                .map_err(|e| format!("Failed to call ledger: {:?}", e))
+                .and_then(|response| response.candid().map_err(|e| format!("Failed to decode ledger response: {:?}", e)))
+                .map_err(DepositRefundError::Unknown)?;
+            let fee = nat_to_u64(fee).map_err(DepositRefundError::Unknown)?;
+            // A concurrent attempt may have fixed the fee first; every attempt must use the same one
+            DEPOSITS.with(|deposits| {
+                let mut deposits = deposits.borrow_mut();
+                let mut current = deposits.get(&campaign_id)
+                    .ok_or_else(|| DepositRefundError::Unknown("Deposit not found".to_string()))?;
+                if current.refund_created_at_time != Some(created_at_time) {
+                    return Err(DepositRefundError::Unknown("Another refund attempt replaced this one".to_string()));
+                }
+                let fee = *current.refund_fee.get_or_insert(fee);
+                deposits.insert(campaign_id, current);
+                Ok(fee)
+            })?
+        }
+    };
+    
+    let amount = deposit.amount.saturating_sub(fee);
+    if amount == 0 {
+        return Err(DepositRefundError::Rejected("Deposit does not cover the ledger fee".to_string()));
+    }
+    
+    let args = TransferArg {
+        from_subaccount: None,
+        to: Account { owner: deposit.creator, subaccount: None },
+        amount: Nat::from(amount),
+        fee: Some(Nat::from(fee)),
+        memo: Some(campaign_id.to_be_bytes().to_vec()),
+        created_at_time: Some(created_at_time),
+    };
+    
+    let result: Result<Nat, TransferError> = Call::unbounded_wait(deposit.ledger, "icrc1_transfer")
+        .with_arg(args)
+        .await
+        .map_err(|e| format!("Failed to call ledger: {:?}", e))
+        .and_then(|response| response.candid().map_err(|e| format!("Failed to decode ledger response: {:?}", e)))
+        .map_err(DepositRefundError::Unknown)?;
+    
+    match result {
+        // A retry of a refund that already went through
+        Ok(block_index) | Err(TransferError::Duplicate { duplicate_of: block_index }) => {
+            nat_to_u64(block_index).map_err(DepositRefundError::Unknown)
+        }
+        // Past the ledger's dedup window an earlier attempt can't be told apart from none
+        Err(TransferError::TooOld) => Err(DepositRefundError::Unknown(
+            "Deposit refund is too old for the ledger to deduplicate; it stays Refunding".to_string(),
+        )),
+        Err(e) => Err(DepositRefundError::Rejected(format!("Deposit refund transfer failed: {:?}", e))),
+    }
+}
+
+fn forfeit_creation_deposit(campaign_id: u64) {
+    DEPOSITS.with(|deposits| {
+        let existing = deposits.borrow().get(&campaign_id);
+        if let Some(mut deposit) = existing.filter(|d| d.status == DepositStatus::Held) {
+            deposit.status = DepositStatus::Forfeited;
+            deposit.updated_at = time();
+            deposits.borrow_mut().insert(campaign_id, deposit);
+        }
+    });
+}
+
+// Retries a refund that failed or was interrupted when the campaign was funded, or when its
+// creation failed
+#[update]
+async fn claim_creation_deposit(campaign_id: u64) -> Result<(), String> {
+    let deposit = DEPOSITS.with(|deposits| deposits.borrow().get(&campaign_id))
+        .ok_or_else(|| "No deposit recorded for this campaign".to_string())?;
+    
+    if deposit.creator != msg_caller() {
+        return Err("Only the creator can claim the deposit".to_string());
+    }
+    
+    let status = CAMPAIGNS.with(|campaigns| campaigns.borrow().get(&campaign_id).map(|c| c.status));
+    let refundable = matches!(status, None | Some(CampaignStatus::Funded) | Some(CampaignStatus::Completed));
+    if !refundable {
+        return Err("Deposits are refunded once the campaign is funded".to_string());
+    }
+    
+    refund_creation_deposit(campaign_id).await
+}
+
+#[query]
+fn get_creation_deposit(campaign_id: u64) -> Option<CreationDeposit> {
+    DEPOSITS.with(|deposits| deposits.borrow().get(&campaign_id))
+}
+
+// Managed by ipx-dao proposals; None clears the entry
+#[update]
+fn set_creator_access(principal: Principal, access: Option<CreatorAccess>) -> Result<(), String> {
+    let dao = ANTI_SPAM_CONFIG.with(|config| config.borrow().get().dao_canister);
+    
+    if dao != Some(msg_caller()) {
+        return Err("Only the DAO can manage creator access".to_string());
+    }
+    
+    CREATOR_ACCESS.with(|entries| {
+        let mut entries = entries.borrow_mut();
+        match access {
+            Some(access) => entries.insert(principal, access),
+            None => entries.remove(&principal),
+        };
+    });
+    
+    Ok(())
+}
+
+#[query]
+fn list_creator_access() -> Vec<(Principal, CreatorAccess)> {
+    CREATOR_ACCESS.with(|entries| entries.borrow().iter().collect())
+}
+
+#[update]
+fn set_anti_spam_config(config: AntiSpamConfig) -> Result<(), String> {
+    if !is_controller(&msg_caller()) {
+        return Err("Only factory controllers can change the anti-spam config".to_string());
+    }
+    
+    if config.max_campaigns_per_window == 0 || config.window_seconds == 0 {
+        return Err("Rate limit must allow at least one campaign per window".to_string());
+    }
+    
+    if config.deposit_amount > 0 && config.deposit_ledger.is_none() {
+        return Err("A deposit ledger is required when a deposit is charged".to_string());
+    }
+    
+    ANTI_SPAM_CONFIG.with(|cell| {
+        cell.borrow_mut().set(config).expect("failed to persist anti-spam config");
+    });
+    
+    Ok(())
+}
+
+#[query]
+fn get_anti_spam_config() -> AntiSpamConfig {
+    ANTI_SPAM_CONFIG.with(|config| config.borrow().get().clone())
+}
+
+const MAX_TEMPLATE_ID_LENGTH: usize = 64;
+const MAX_ORACLE_ENDPOINTS: usize = 10;
+const MAX_STREAM_DURATION_SECONDS: u64 = 5 * 365 * 24 * 60 * 60;
+
+const THIRTY_DAYS_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+fn default_slashing_conditions() -> SlashingConditions {
+    SlashingConditions {
+        missed_revenue_reports_threshold: 3,
+        revenue_decline_threshold_percentage: 70,
+        minimum_active_period_days: 30,
+        governance_votes_required: 51,
+    }
+}
+
+// Built-in templates, written on install and by the version 3 migration. Existing
+// templates with the same ID are left alone so controller edits survive.
+fn seed_default_templates() {
+    TEMPLATES.with(|templates| {
+        let mut templates = templates.borrow_mut();
+        for template in default_templates(time()) {
+            if !templates.contains_key(&template.id) {
+                templates.insert(template.id.clone(), template);
+            }
+        }
+    });
+}
+
+fn default_templates(now: u64) -> Vec<CampaignTemplate> {
+    vec![
+        CampaignTemplate {
+            id: "music-royalty".to_string(),
+            name: "Music royalty".to_string(),
+            description: "Backers share streaming and publishing royalties, paid out once per quarter".to_string(),
+            revenue_share_percentage: 30,
+            // Distributors have no common API; creators supply their own endpoints as overrides
+            oracle_endpoints: Vec::new(),
+            payout_stream: PayoutStreamSettings {
+                stream_type: StreamType::Cliff,
+                duration_seconds: 3 * THIRTY_DAYS_SECONDS,
+            },
+            insurance: InsuranceSettings {
+                fee_percentage: 3,
+                coverage_ratio: 70,
+                slashing_conditions: SlashingConditions {
+                    // Royalty statements arrive quarterly and swing with releases
+                    missed_revenue_reports_threshold: 2,
+                    revenue_decline_threshold_percentage: 80,
+                    minimum_active_period_days: 90,
+                    governance_votes_required: 51,
+                },
+            },
+            category: Some(CampaignCategory::Music),
+            tags: vec!["music".to_string(), "royalties".to_string()],
+            updated_at: now,
+        },
+        CampaignTemplate {
+            id: "youtube-ad-revenue".to_string(),
+            name: "YouTube ad revenue".to_string(),
+            description: "Backers share the channel's estimated ad revenue, streamed monthly".to_string(),
+            revenue_share_percentage: 20,
+            oracle_endpoints: vec![
+                "https://youtubeanalytics.googleapis.com/v2/reports?ids=channel==MINE&metrics=estimatedRevenue"
+                    .to_string(),
+            ],
+            payout_stream: PayoutStreamSettings {
+                stream_type: StreamType::Linear,
+                duration_seconds: THIRTY_DAYS_SECONDS,
+            },
+            insurance: InsuranceSettings {
+                fee_percentage: 2,
+                coverage_ratio: 80,
+                slashing_conditions: default_slashing_conditions(),
+            },
+            category: Some(CampaignCategory::Entertainment),
+            tags: vec!["youtube".to_string(), "ad-revenue".to_string()],
+            updated_at: now,
+        },
+        CampaignTemplate {
+            id: "newsletter-subscriptions".to_string(),
+            name: "Newsletter subscriptions".to_string(),
+            description: "Backers share paid subscription revenue, streamed monthly".to_string(),
+            revenue_share_percentage: 15,
+            oracle_endpoints: vec!["https://substack.com/api/v1/stats".to_string()],
+            payout_stream: PayoutStreamSettings {
+                stream_type: StreamType::Linear,
+                duration_seconds: THIRTY_DAYS_SECONDS,
+            },
+            insurance: InsuranceSettings {
+                fee_percentage: 2,
+                coverage_ratio: 80,
+                slashing_conditions: default_slashing_conditions(),
+            },
+            category: Some(CampaignCategory::Education),
+            tags: vec!["newsletter".to_string(), "subscriptions".to_string()],
+            updated_at: now,
+        },
+    ]
+}
+
+fn validate_payout_stream(settings: &PayoutStreamSettings) -> Result<(), String> {
+    if settings.duration_seconds == 0 || settings.duration_seconds > MAX_STREAM_DURATION_SECONDS {
+        return Err(format!(
+            "Payout stream duration must be between 1 and {} seconds",
+            MAX_STREAM_DURATION_SECONDS
+        ));
+    }
+    Ok(())
+}
+
+// Returns the template in its stored form
+fn normalize_template(mut template: CampaignTemplate) -> Result<CampaignTemplate, String> {
+    let valid_id = !template.id.is_empty()
+        && template.id.len() <= MAX_TEMPLATE_ID_LENGTH
+        && template.id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !valid_id {
+        return Err(format!(
+            "Template ID must be 1 to {} lowercase letters, digits or '-'",
+            MAX_TEMPLATE_ID_LENGTH
+        ));
+    }
+    
+    if template.name.trim().is_empty() || template.name.len() > MAX_TITLE_LENGTH {
+        return Err(format!("Template name must be between 1 and {} characters", MAX_TITLE_LENGTH));
+    }
+    
+    if template.description.len() > MAX_DESCRIPTION_LENGTH {
+        return Err(format!("Description cannot exceed {} characters", MAX_DESCRIPTION_LENGTH));
+    }
+    
+    if template.revenue_share_percentage == 0 || template.revenue_share_percentage > 100 {
+        return Err("Revenue share must be between 1-100%".to_string());
+    }
+    
+    if template.oracle_endpoints.len() > MAX_ORACLE_ENDPOINTS {
+        return Err(format!("At most {} oracle endpoints are allowed", MAX_ORACLE_ENDPOINTS));
+    }
+    validate_oracle_endpoints(&template.oracle_endpoints)?;
+    
+    validate_payout_stream(&template.payout_stream)?;
+    
+    // Same bounds the vault enforces at init
+    if template.insurance.fee_percentage > 20 {
+        return Err("Insurance fee cannot exceed 20%".to_string());
+    }
+    if template.insurance.coverage_ratio > 100 {
+        return Err("Coverage ratio cannot exceed 100%".to_string());
+    }
+    
+    // Reuse the campaign tag rules so template tags are stored the same way
+    let details = normalize_details(CampaignDetails {
+        version: CAMPAIGN_DETAILS_VERSION,
+        category: None,
+        tags: template.tags,
+        cover_image_hashes: Vec::new(),
+        links: Vec::new(),
+        youtube_channel_id: None,
+        milestones: Vec::new(),
+    })?;
+    template.tags = details.tags;
+    template.updated_at = time();
+    
+    Ok(template)
+}
+
+// Adds a template or replaces the one with the same ID. Campaigns already created from it keep their terms.
+#[update]
+fn upsert_campaign_template(template: CampaignTemplate) -> Result<CampaignTemplate, String> {
+    if !is_controller(&msg_caller()) {
+        return Err("Only factory controllers can manage templates".to_string());
+    }
+    
+    let template = normalize_template(template)?;
+    TEMPLATES.with(|templates| {
+        templates.borrow_mut().insert(template.id.clone(), template.clone());
+    });
+    
+    Ok(template)
+}
+
+#[update]
+fn remove_campaign_template(template_id: String) -> Result<(), String> {
+    if !is_controller(&msg_caller()) {
+        return Err("Only factory controllers can manage templates".to_string());
+    }
+    
+    TEMPLATES
+        .with(|templates| templates.borrow_mut().remove(&template_id))
+        .map(|_| ())
+        .ok_or_else(|| format!("Template '{}' not found", template_id))
+}
+
+#[query]
+fn get_campaign_template(template_id: String) -> Option<CampaignTemplate> {
+    TEMPLATES.with(|templates| templates.borrow().get(&template_id))
+}
+
+#[query]
+fn list_campaign_templates() -> Vec<CampaignTemplate> {
+    TEMPLATES.with(|templates| templates.borrow().iter().map(|(_, template)| template).collect())
+}
+
+// Ingress calls that would be rejected anyway are dropped here, before they are charged
+// for execution. Methods only canisters call are never valid as ingress.
+#[inspect_message]
+fn inspect_message() {
+    let caller = msg_caller();
+    let method = ic_cdk::api::msg_method_name();
+    
+    if caller == Principal::anonymous() {
+        return;
+    }
+    
+    let accept = match method.as_str() {
+        "create_campaign" => {
+            check_creator_access(caller).is_ok() && !is_rate_limited(caller) && create_args_valid()
+        }
+        "create_campaign_from_template" => check_creator_access(caller).is_ok() && !is_rate_limited(caller),
+        "set_vault_wasm" | "set_cycles_config" | "set_verification_config" | "set_anti_spam_config"
+        | "upsert_campaign_template" | "remove_campaign_template" | "set_platform_canisters" | "refresh_platform_stats"
+        | "start_vault_upgrade" | "resume_vault_upgrade" | "cancel_vault_upgrade" | "set_vault_revenue_limit"
+        | "review_vault_revenue" | "resolve_vault_operation" => is_controller(&caller),
+        "notify_funding_goal_reached" | "set_creator_access" => false,
+        _ => true,
+    };
+    
+    if accept {
+        ic_cdk::api::accept_message();
+    }
+}
+
+type CreateCampaignArgs = (
+    String,
+    String,
+    u64,
+    u8,
+    Vec<String>,
+    Option<u64>,
+    Option<u64>,
+    Option<CampaignDetails>,
+);
+
+fn create_args_valid() -> bool {
+    let bytes = ic_cdk::api::msg_arg_data();
+    let Ok((title, description, funding_goal, share, endpoints, deadline, minimum, details)) =
+        candid::decode_args::<CreateCampaignArgs>(&bytes)
+    else {
+        return false;
+    };
+    
+    validate_campaign_terms(&title, &description, funding_goal, share, deadline, minimum).is_ok()
+        && validate_oracle_endpoints(&endpoints).is_ok()
+        && details.is_none_or(|details| normalize_details(details).is_ok())
+}
+
+fn record_transition(
+    campaign_id: u64,
+    from: Option<CampaignStatus>,
+    to: CampaignStatus,
+    actor: Principal,
+) {
+    STATUS_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let mut entry = history.get(&campaign_id).unwrap_or_default();
+        entry.transitions.push(StatusTransition {
+            from,
+            to,
+            actor,
+            timestamp: time(),
+        });
+        history.insert(campaign_id, entry);
+    });
+}
+
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn campaign(created_at: u64, funding_goal: u64) -> CampaignMetadata {
+        CampaignMetadata {
+            creator: Principal::anonymous(),
+            title: format!("Campaign {}", created_at),
+            description: String::new(),
+            funding_goal,
+            revenue_share_percentage: 10,
+            oracle_endpoints: vec![],
+            vault_canister_id: None,
+            created_at,
+            status: CampaignStatus::Active,
+            funding_deadline: None,
+            minimum_funding: None,
+            details: None,
+            verification: None,
+            template_id: None,
+            payout_stream: None,
+            insurance: None,
+            provisioning: None,
+        }
+    }
+    
+    fn insert_campaigns(campaigns: &[(u64, CampaignMetadata)]) {
+        CAMPAIGNS.with(|stored| {
+            let mut stored = stored.borrow_mut();
+            for (campaign_id, campaign) in campaigns {
+                stored.insert(*campaign_id, campaign.clone());
+            }
+        });
+    }
+    
+    // Follows `next_cursor` until the last page and returns the IDs in the order served
+    fn collect_pages(filter: CampaignFilter, sort: Option<CampaignSort>, limit: u32) -> Vec<u64> {
+        let mut ids = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = list_campaigns(filter.clone(), sort, cursor, Some(limit));
+            assert!(page.campaigns.len() <= limit as usize);
+            ids.extend(page.campaigns.iter().map(|(campaign_id, _)| *campaign_id));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return ids,
+            }
+        }
+    }
+    
+    #[test]
+    fn list_campaigns_pages_by_campaign_id() {
+        insert_campaigns(&(1..=7).map(|id| (id, campaign(id * 10, 100))).collect::<Vec<_>>());
+        
+        let first = list_campaigns(CampaignFilter::default(), None, None, Some(3));
+        assert_eq!(first.campaigns.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(first.next_cursor, Some(CampaignCursor { sort_key: 3, campaign_id: 3 }));
+        
+        assert_eq!(collect_pages(CampaignFilter::default(), None, 3), vec![1, 2, 3, 4, 5, 6, 7]);
+    }
+    
+    #[test]
+    fn list_campaigns_last_full_page_has_no_cursor() {
+        insert_campaigns(&(1..=4).map(|id| (id, campaign(id, 100))).collect::<Vec<_>>());
+        
+        let first = list_campaigns(CampaignFilter::default(), None, None, Some(2));
+        let second = list_campaigns(CampaignFilter::default(), None, first.next_cursor, Some(2));
+        assert_eq!(second.campaigns.len(), 2);
+        assert_eq!(second.next_cursor, None);
+    }
+    
+    #[test]
+    fn list_campaigns_breaks_sort_key_ties_by_campaign_id() {
+        // Equal funding goals straddle the page boundary
+        insert_campaigns(&[
+            (1, campaign(1, 500)),
+            (2, campaign(2, 100)),
+            (3, campaign(3, 500)),
+            (4, campaign(4, 500)),
+            (5, campaign(5, 300)),
+        ]);
+        let ascending = CampaignSort { field: CampaignSortField::FundingGoal, descending: false };
+        let descending = CampaignSort { field: CampaignSortField::FundingGoal, descending: true };
+        
+        assert_eq!(collect_pages(CampaignFilter::default(), Some(ascending), 2), vec![2, 5, 1, 3, 4]);
+        assert_eq!(collect_pages(CampaignFilter::default(), Some(descending), 2), vec![4, 3, 1, 5, 2]);
+    }
+    
+    #[test]
+    fn list_campaigns_cursor_survives_inserts_before_it() {
+        insert_campaigns(&(1..=4).map(|id| (id, campaign(id * 10, 100))).collect::<Vec<_>>());
+        let sort = Some(CampaignSort { field: CampaignSortField::CreatedAt, descending: false });
+        
+        let first = list_campaigns(CampaignFilter::default(), sort, None, Some(2));
+        // Sorts before the cursor, so it must not appear on later pages
+        insert_campaigns(&[(5, campaign(5, 100))]);
+        let second = list_campaigns(CampaignFilter::default(), sort, first.next_cursor, Some(2));
+        
+        assert_eq!(second.campaigns.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![3, 4]);
+        assert_eq!(second.next_cursor, None);
+    }
+    
+    #[test]
+    fn list_campaigns_pages_only_matching_campaigns() {
+        let mut campaigns: Vec<(u64, CampaignMetadata)> =
+            (1..=6).map(|id| (id, campaign(id, id * 100))).collect();
+        campaigns[1].1.status = CampaignStatus::Draft;
+        campaigns[4].1.status = CampaignStatus::Draft;
+        insert_campaigns(&campaigns);
+        let filter = CampaignFilter { status: Some(CampaignStatus::Active), ..Default::default() };
+        
+        assert_eq!(collect_pages(filter, None, 2), vec![1, 3, 4, 6]);
+    }
+    
+    #[test]
+    fn list_campaigns_clamps_the_page_size() {
+        insert_campaigns(&(1..=3).map(|id| (id, campaign(id, 100))).collect::<Vec<_>>());
+        
+        let page = list_campaigns(CampaignFilter::default(), None, None, Some(0));
+        assert_eq!(page.campaigns.len(), 1);
+        assert!(page.next_cursor.is_some());
+    }
+    
+    #[test]
+    fn status_transition_table() {
+        use CampaignStatus::*;
+        let all = [Draft, Active, Funded, Completed, Cancelled, Failed];
+        let allowed = [
+            (Draft, Active),
+            (Active, Funded),
+            (Funded, Completed),
+            (Draft, Cancelled),
+            (Active, Cancelled),
+            (Active, Failed),
+        ];
+        
+        for from in &all {
+            for to in &all {
+                let expected = allowed.contains(&(from.clone(), to.clone()));
+                assert_eq!(from.can_transition_to(to), expected, "{:?} -> {:?}", from, to);
+            }
+        }
+    }
+    
+    #[test]
+    fn terminal_statuses_have_no_transitions() {
+        use CampaignStatus::*;
+        let all = [Draft, Active, Funded, Completed, Cancelled, Failed];
+        
+        for terminal in [Completed, Cancelled, Failed] {
+            assert!(all.iter().all(|next| !terminal.can_transition_to(next)), "{:?}", terminal);
+        }
+    }
+    
+    #[test]
+    fn tokenize_lowercases_and_splits_on_punctuation() {
+        let terms: Vec<String> = tokenize("Indie-Game DEVLOG: season_2!").collect();
+        assert_eq!(terms, vec!["indie", "game", "devlog", "season"]);
+    }
+    
+    #[test]
+    fn tokenize_drops_stop_words_and_out_of_range_lengths() {
+        let long_word = "x".repeat(MAX_TERM_LENGTH + 1);
+        let text = format!("a podcast for the makers of {} q 42", long_word);
+        let terms: Vec<String> = tokenize(&text).collect();
+        assert_eq!(terms, vec!["podcast", "makers", "42"]);
+    }
+    
+    #[test]
+    fn tokenize_keeps_unicode_letters() {
+        let terms: Vec<String> = tokenize("Café Müller").collect();
+        assert_eq!(terms, vec!["café", "müller"]);
+    }
+    
+    #[test]
+    fn term_frequencies_weight_title_over_tags_over_description() {
+        let mut metadata = campaign(1, 100);
+        metadata.title = "Cooking show".to_string();
+        metadata.description = "A weekly cooking show with bread".to_string();
+        metadata.details = Some(CampaignDetails {
+            version: 1,
+            category: None,
+            tags: vec!["bread".to_string(), "cooking".to_string()],
+            cover_image_hashes: vec![],
+            links: vec![],
+            youtube_channel_id: None,
+            milestones: vec![],
+        });
+        
+        let frequencies = term_frequencies(&metadata);
+        assert_eq!(frequencies["cooking"], TITLE_WEIGHT + DESCRIPTION_WEIGHT + TAG_WEIGHT);
+        assert_eq!(frequencies["show"], TITLE_WEIGHT + DESCRIPTION_WEIGHT);
+        assert_eq!(frequencies["bread"], DESCRIPTION_WEIGHT + TAG_WEIGHT);
+        assert_eq!(frequencies["weekly"], DESCRIPTION_WEIGHT);
+        assert!(!frequencies.contains_key("with"));
+    }
+    
+    #[test]
+    fn job_slot_is_released_when_the_guard_drops() {
+        let job = try_start_job(Job::DeadlineSweep, 100, 0).expect("job should start");
+        assert!(try_start_job(Job::DeadlineSweep, 100, 0).is_none());
+        
+        drop(job);
+        assert!(try_start_job(Job::DeadlineSweep, 100, 0).is_some());
+    }
+    
+    #[test]
+    fn job_interval_saturates_instead_of_overflowing() {
+        drop(try_start_job(Job::CyclesCheck, 100, u64::MAX));
+        assert!(try_start_job(Job::CyclesCheck, u64::MAX - 1, 0).is_none());
+    }
+    
+    #[test]
+    fn parse_api_endpoint_uses_the_connector_platforms() {
+        let youtube = parse_api_endpoint("https://youtubeanalytics.googleapis.com/v2/reports?ids=channel==MINE")
+            .expect("youtube is supported");
+        assert_eq!(youtube.platform, "youtube");
+        assert_eq!(youtube.data_path, "/reports/0/data/totals/0/values/0");
+        
+        let substack = parse_api_endpoint("https://creator.substack.com/api/v1/stats").expect("substack is supported");
+        assert_eq!(substack.platform, "substack");
+        assert_eq!(substack.data_path, "/stats/revenue/total");
+    }
+    
+    #[test]
+    fn parse_api_endpoint_skips_hosts_the_connector_cannot_read() {
+        assert!(parse_api_endpoint("https://api.beehiiv.com/v2/publications").is_none());
+        assert!(parse_api_endpoint("https://notspotify.com/revenue").is_none());
+        assert!(validate_oracle_endpoints(&["https://api.beehiiv.com/v2/publications".to_string()]).is_ok());
+    }
+    
+    #[test]
+    fn default_templates_only_use_endpoints_the_connector_can_read() {
+        for template in default_templates(0) {
+            for url in &template.oracle_endpoints {
+                assert!(parse_api_endpoint(url).is_some(), "{}: {}", template.id, url);
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use ic_cdk::api::{msg_caller, time};
+use ic_cdk::api::{is_controller, msg_caller, time};
+use ic_cdk::call::Call;
 use ic_cdk_macros::{init, query, update};
 use std::cell::RefCell;
 use std::collections::HashMap;
@@ -10,6 +11,20 @@ pub enum ProposalType {
     ParameterChange,
     CodeUpgrade,
     Treasury,
+    CreatorAccess, // Allow or deny a principal creating campaigns on the factory
+}
+
+// Mirrors the factory's creator access list entry
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum CreatorAccess {
+    Allowed,
+    Denied,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CreatorAccessChange {
+    pub creator: Principal,
+    pub access: Option<CreatorAccess>, // None removes the principal from the list
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -18,6 +33,7 @@ pub struct ProposalData {
     pub description: String,
     pub proposal_type: ProposalType,
     pub voting_period: u64, // Duration in nanoseconds
+    pub creator_access: Option<CreatorAccessChange>, // Required for CreatorAccess proposals
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -44,11 +60,12 @@ pub struct GovernanceStats {
 // Storage
 thread_local! {
     static PROPOSALS: RefCell<HashMap<u64, Proposal>> = RefCell::new(HashMap::new());
-    static PROPOSAL_COUNTER: RefCell<u64> = RefCell::new(0);
+    static PROPOSAL_COUNTER: RefCell<u64> = const { RefCell::new(0) };
     static MEMBER_VOTES: RefCell<HashMap<Principal, u64>> = RefCell::new(HashMap::new()); // Voting power
-    static TREASURY_BALANCE: RefCell<u64> = RefCell::new(1000000); // Initial treasury
-    static READY_FOR_UPGRADE: RefCell<bool> = RefCell::new(false); // Flag for code upgrade
-    static DEFAULT_VOTING_PERIOD: RefCell<u64> = RefCell::new(7 * 24 * 60 * 60 * 1_000_000_000); // 7 days in nanoseconds
+    static TREASURY_BALANCE: RefCell<u64> = const { RefCell::new(1000000) }; // Initial treasury
+    static READY_FOR_UPGRADE: RefCell<bool> = const { RefCell::new(false) }; // Flag for code upgrade
+    static DEFAULT_VOTING_PERIOD: RefCell<u64> = const { RefCell::new(7 * 24 * 60 * 60 * 1_000_000_000) }; // 7 days in nanoseconds
+    static CAMPAIGN_FACTORY: RefCell<Option<Principal>> = const { RefCell::new(None) }; // Target of CreatorAccess proposals
 }
 
 #[init]
@@ -73,6 +90,10 @@ fn create_proposal(data: ProposalData) -> Result<u64, String> {
         return Err("No voting power".to_string());
     }
     
+    if matches!(data.proposal_type, ProposalType::CreatorAccess) && data.creator_access.is_none() {
+        return Err("Creator access proposals must name the principal".to_string());
+    }
+    
     let proposal_id = PROPOSAL_COUNTER.with(|counter| {
         let mut counter = counter.borrow_mut();
         *counter += 1;
@@ -145,10 +166,10 @@ fn vote(proposal_id: u64, support: bool) -> Result<String, String> {
 }
 
 #[update]
-fn execute_proposal(proposal_id: u64) -> Result<String, String> {
+async fn execute_proposal(proposal_id: u64) -> Result<String, String> {
     let current_time = time();
     
-    PROPOSALS.with(|proposals| {
+    let result = PROPOSALS.with(|proposals| {
         let mut proposals = proposals.borrow_mut();
         
         if let Some(proposal) = proposals.get_mut(&proposal_id) {
@@ -194,16 +215,68 @@ fn execute_proposal(proposal_id: u64) -> Result<String, String> {
                         ic_cdk::println!("Code upgrade proposal executed. Upgrade flag set.");
                        
                     },
+                    ProposalType::CreatorAccess => {
+                        // Applied on the factory below, outside the borrow
+                        return Ok((true, proposal.data.creator_access.clone()));
+                    },
                 }
                 
-                Ok("Proposal executed successfully".to_string())
+                Ok((true, None))
             } else {
-                Ok("Proposal failed to pass".to_string())
+                Ok((false, None))
             }
         } else {
             Err("Proposal not found".to_string())
         }
-    })
+    });
+    
+    let (passed, change) = result?;
+    
+    if !passed {
+        return Ok("Proposal failed to pass".to_string());
+    }
+    
+    if let Some(change) = change {
+        if let Err(e) = apply_creator_access(change).await {
+            // Leave the proposal executable so it can be retried
+            PROPOSALS.with(|proposals| {
+                if let Some(proposal) = proposals.borrow_mut().get_mut(&proposal_id) {
+                    proposal.executed = false;
+                }
+            });
+            return Err(e);
+        }
+    }
+    
+    Ok("Proposal executed successfully".to_string())
+}
+
+async fn apply_creator_access(change: CreatorAccessChange) -> Result<(), String> {
+    let factory = CAMPAIGN_FACTORY.with(|factory| *factory.borrow())
+        .ok_or_else(|| "Campaign factory is not configured".to_string())?;
+    
+    let result: Result<(), String> = Call::unbounded_wait(factory, "set_creator_access")
+        .with_args(&(change.creator, change.access))
+        .await
+        .map_err(|e| format!("Failed to call factory: {:?}", e))?
+        .candid()
+        .map_err(|e| format!("Failed to decode factory response: {:?}", e))?;
+    
+    result
+}
+
+// The factory decides which campaigns the DAO acts on, so only controllers can repoint it
+#[update]
+fn set_campaign_factory(factory: Principal) -> Result<String, String> {
+    if !is_controller(&msg_caller()) {
+        return Err("Only DAO controllers can set the campaign factory".to_string());
+    }
+    
+    CAMPAIGN_FACTORY.with(|current| {
+        *current.borrow_mut() = Some(factory);
+    });
+    
+    Ok("Campaign factory set".to_string())
 }
 
 #[update]
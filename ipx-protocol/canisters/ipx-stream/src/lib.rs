@@ -29,6 +29,14 @@ pub enum StreamType {
     Exponential, // Decreasing rate over time
 }
 
+// Payout terms a vault picks for the streams it opens; the vault's campaign template decides them
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct StreamSettings {
+    pub stream_type: StreamType,
+    pub duration_seconds: u64,
+    pub campaign_id: u64,
+}
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct ClaimResult {
     pub stream_id: StreamId,
@@ -37,6 +45,38 @@ pub struct ClaimResult {
     pub next_claim_time: u64,
 }
 
+// A claim whose payout the vault hasn't confirmed. Its amount stays in the stream's
+// `claimed_amount` until `settle_pending_claims` learns from the vault whether it was paid.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PendingClaim {
+    pub payout_ref: u64,
+    pub stream_id: StreamId,
+    pub vault_canister: Principal,
+    pub amount: u64,
+    pub claimed_at: u64,
+    pub last_error: String,
+    pub deactivated_stream: bool, // The claim ended the stream, so undoing it reopens the stream
+}
+
+// The parts of the vault's payout record this canister reads
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct VaultPayout {
+    status: VaultPayoutStatus,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+enum VaultPayoutStatus {
+    Pending,
+    Paid { block_index: u64 },
+    Failed { reason: String },
+}
+
+enum ClaimOutcome {
+    Paid,
+    Released,
+    Pending,
+}
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct StreamStats {
     pub total_streams: u64,
@@ -48,7 +88,9 @@ pub struct StreamStats {
 thread_local! {
     static STREAMS: RefCell<HashMap<StreamId, Stream>> = RefCell::new(HashMap::new());
     static USER_STREAMS: RefCell<HashMap<Principal, Vec<StreamId>>> = RefCell::new(HashMap::new());
-    static STREAM_COUNTER: RefCell<StreamId> = RefCell::new(0);
+    static STREAM_COUNTER: RefCell<StreamId> = const { RefCell::new(0) };
+    static PENDING_CLAIMS: RefCell<HashMap<u64, PendingClaim>> = RefCell::new(HashMap::new());
+    static LAST_PAYOUT_REF: RefCell<u64> = const { RefCell::new(0) };
 }
 
 #[init]
@@ -123,18 +165,27 @@ fn create_stream(
 }
 
 #[update]
-fn create_streams(payouts: Vec<(Principal, u64)>) -> Result<Vec<StreamId>, String> {
+fn create_streams(
+    payouts: Vec<(Principal, u64)>,
+    settings: Option<StreamSettings>, // Omitted by vaults predating campaign templates
+) -> Result<Vec<StreamId>, String> {
     let caller = msg_caller();
     let mut stream_ids = Vec::new();
     
+    let settings = settings.unwrap_or(StreamSettings {
+        stream_type: StreamType::Linear,
+        duration_seconds: 2592000, // 30 days default duration
+        campaign_id: 0,
+    });
+    
     for (recipient, amount) in payouts {
         let stream_id = create_stream(
             recipient,
             amount,
-            2592000, // 30 days default duration
-            0, // TODO: Get campaign_id from caller context
+            settings.duration_seconds,
+            settings.campaign_id,
             caller, // Assume caller is vault canister
-            StreamType::Linear,
+            settings.stream_type.clone(),
         )?;
         stream_ids.push(stream_id);
     }
@@ -163,7 +214,7 @@ async fn claim_stream(stream_id: StreamId) -> Result<ClaimResult, String> {
                 return Err("Stream has not started yet".to_string());
             }
             
-            let claimable = calculate_claimable_amount(&stream, current_time);
+            let claimable = calculate_claimable_amount(stream, current_time);
             
             if claimable == 0 {
                 return Err("No claimable amount available".to_string());
@@ -173,7 +224,8 @@ async fn claim_stream(stream_id: StreamId) -> Result<ClaimResult, String> {
             stream.claimed_amount += claimable;
             
             // Check if stream is complete
-            if stream.claimed_amount >= stream.total_amount || current_time >= stream.end_time {
+            let deactivated_stream = stream.claimed_amount >= stream.total_amount || current_time >= stream.end_time;
+            if deactivated_stream {
                 stream.is_active = false;
             }
             
@@ -188,7 +240,7 @@ async fn claim_stream(stream_id: StreamId) -> Result<ClaimResult, String> {
             
             
             // Can't use await within STREAMS.with, so we'll just prepare the data
-            return Ok((stream.vault_canister, stream.recipient.clone(), claimable, remaining, next_claim_time));
+            return Ok((stream.vault_canister, stream.recipient, claimable, remaining, next_claim_time, deactivated_stream));
         }
         
         // Return an error if stream not found
@@ -196,26 +248,124 @@ async fn claim_stream(stream_id: StreamId) -> Result<ClaimResult, String> {
     });
     
     // Extract data from our stream
-    let (vault_canister, recipient, claimable_amount, remaining, next_claim_time) = stream_data?;
-    
-    // Now we can use await outside the closure - using the deprecated API but we can fix this later
-    let res: Result<(), _> = ic_cdk::api::call::call(
-        vault_canister, 
-        "transfer",
-        (recipient, claimable_amount)
-    ).await;
+    let (vault_canister, recipient, claimable_amount, remaining, next_claim_time, deactivated_stream) = stream_data?;
     
-    res.map_err(|e| format!("Transfer failed: {:?}", e))?;
+    // Now we can use await outside the closure; the vault pays out through its ledger
+    let payout_ref = next_payout_ref();
+    let res = ic_cdk::call::Call::unbounded_wait(vault_canister, "transfer")
+        .with_args(&(recipient, claimable_amount, Some(payout_ref)))
+        .await
+        .map_err(|e| format!("{:?}", e))
+        .and_then(|response| {
+            response.candid::<Result<u64, String>>().map_err(|e| format!("{:?}", e))
+        })
+        .and_then(|result| result);
     
-    // Return the result
-    Ok(ClaimResult {
+    let result = ClaimResult {
         stream_id,
         claimed_amount: claimable_amount,
         remaining_amount: remaining,
         next_claim_time,
+    };
+    
+    let Err(e) = res else {
+        return Ok(result);
+    };
+    
+    // The vault may have paid even though the call failed, so ask it before undoing the claim
+    let claim = PendingClaim {
+        payout_ref,
+        stream_id,
+        vault_canister,
+        amount: claimable_amount,
+        claimed_at: current_time,
+        last_error: e.clone(),
+        deactivated_stream,
+    };
+    PENDING_CLAIMS.with(|claims| claims.borrow_mut().insert(payout_ref, claim.clone()));
+    
+    match settle_claim(claim).await {
+        ClaimOutcome::Paid => Ok(result),
+        ClaimOutcome::Released => Err(format!("Transfer failed: {}", e)),
+        ClaimOutcome::Pending => Err(format!(
+            "Transfer outcome unknown ({}); the claim stays reserved until the vault confirms payout {}",
+            e, payout_ref
+        )),
+    }
+}
+
+// Refs never repeat, even after an upgrade clears the heap: each is above the last one and
+// no lower than the current time
+fn next_payout_ref() -> u64 {
+    LAST_PAYOUT_REF.with(|last| {
+        let mut last = last.borrow_mut();
+        *last = (*last + 1).max(time());
+        *last
     })
 }
 
+// Reads the vault's record of the claim's payout. A failed payout, or one the vault never
+// recorded, is undone so the recipient can claim again; a pending one stays reserved.
+async fn settle_claim(claim: PendingClaim) -> ClaimOutcome {
+    let payout = ic_cdk::call::Call::unbounded_wait(claim.vault_canister, "get_payout")
+        .with_arg(claim.payout_ref)
+        .await
+        .map_err(|e| format!("{:?}", e))
+        .and_then(|response| {
+            response.candid::<Option<VaultPayout>>().map_err(|e| format!("{:?}", e))
+        });
+    
+    let paid = match payout {
+        Ok(Some(VaultPayout { status: VaultPayoutStatus::Paid { .. } })) => true,
+        Ok(Some(VaultPayout { status: VaultPayoutStatus::Failed { .. } })) | Ok(None) => false,
+        Ok(Some(VaultPayout { status: VaultPayoutStatus::Pending })) => return ClaimOutcome::Pending,
+        Err(e) => {
+            PENDING_CLAIMS.with(|claims| {
+                if let Some(pending) = claims.borrow_mut().get_mut(&claim.payout_ref) {
+                    pending.last_error = e;
+                }
+            });
+            return ClaimOutcome::Pending;
+        }
+    };
+    
+    // Another settlement may have finished this claim while the vault was answering
+    let settled_here = PENDING_CLAIMS.with(|claims| claims.borrow_mut().remove(&claim.payout_ref)).is_some();
+    if paid {
+        return ClaimOutcome::Paid;
+    }
+    
+    // Undo the claim so the recipient can retry once the vault can pay. A stream paused in the
+    // meantime stays paused.
+    if settled_here {
+        STREAMS.with(|streams| {
+            if let Some(stream) = streams.borrow_mut().get_mut(&claim.stream_id) {
+                stream.claimed_amount -= claim.amount;
+                if claim.deactivated_stream {
+                    stream.is_active = true;
+                }
+            }
+        });
+    }
+    ClaimOutcome::Released
+}
+
+// Asks the vaults about claims whose payout was left unconfirmed. Anyone may call this: a
+// claim is only finalized or undone on the vault's word. Returns the claims still pending.
+#[update]
+async fn settle_pending_claims() -> Vec<PendingClaim> {
+    let pending: Vec<PendingClaim> = PENDING_CLAIMS.with(|claims| claims.borrow().values().cloned().collect());
+    for claim in pending {
+        settle_claim(claim).await;
+    }
+    get_pending_claims()
+}
+
+#[query]
+fn get_pending_claims() -> Vec<PendingClaim> {
+    PENDING_CLAIMS.with(|claims| claims.borrow().values().cloned().collect())
+}
+
 fn calculate_claimable_amount(stream: &Stream, current_time: u64) -> u64 {
     if current_time < stream.start_time {
         return 0;
@@ -255,11 +405,7 @@ fn calculate_claimable_amount(stream: &Stream, current_time: u64) -> u64 {
     };
     
     // Return claimable amount (total vested minus already claimed)
-    if total_vested > stream.claimed_amount {
-        total_vested - stream.claimed_amount
-    } else {
-        0
-    }
+    total_vested.saturating_sub(stream.claimed_amount)
 }
 
 #[query]
@@ -288,7 +434,7 @@ fn get_claimable_amount(stream_id: StreamId) -> u64 {
     
     STREAMS.with(|streams| {
         if let Some(stream) = streams.borrow().get(&stream_id) {
-            calculate_claimable_amount(&stream, current_time)
+            calculate_claimable_amount(stream, current_time)
         } else {
             0
         }
@@ -298,7 +444,7 @@ fn get_claimable_amount(stream_id: StreamId) -> u64 {
 #[query]
 fn get_stream_stats() -> StreamStats {
     STREAMS.with(|streams| {
-        let all_streams: Vec<Stream> = streams.borrow().iter().map(|(_, stream)| stream.clone()).collect();
+        let all_streams: Vec<Stream> = streams.borrow().values().cloned().collect();
         
         let total_streams = all_streams.len() as u64;
         let active_streams = all_streams.iter().filter(|s| s.is_active).count() as u64;
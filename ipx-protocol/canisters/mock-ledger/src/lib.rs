@@ -0,0 +1,437 @@
+// Minimal ICRC-1/ICRC-2 ledger for local testing of vault custody flows.
+// Balances live on the heap and anyone can mint, so never deploy this to mainnet.
+use ic_cdk::api::{msg_caller, time};
+use candid::{CandidType, Nat, Principal};
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::cell::RefCell;
+
+const DEFAULT_SUBACCOUNT: [u8; 32] = [0; 32];
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ApproveArgs {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub spender: Account,
+    pub amount: Nat,
+    pub expected_allowance: Option<Nat>,
+    pub expires_at: Option<u64>,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AllowanceArgs {
+    pub account: Account,
+    pub spender: Account,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Allowance {
+    pub allowance: Nat,
+    pub expires_at: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum ApproveError {
+    BadFee { expected_fee: Nat },
+    InsufficientFunds { balance: Nat },
+    AllowanceChanged { current_allowance: Nat },
+    Expired { ledger_time: u64 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+// Blocks follow the ICRC-1 ledger's `get_transactions` shape so the vault can read them back
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Transaction {
+    pub kind: String,
+    pub mint: Option<Mint>,
+    pub transfer: Option<Transfer>,
+    pub approve: Option<Approve>,
+    pub timestamp: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Mint {
+    pub to: Account,
+    pub amount: Nat,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Transfer {
+    pub from: Account,
+    pub to: Account,
+    pub spender: Option<Account>,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Approve {
+    pub from: Account,
+    pub spender: Account,
+    pub amount: Nat,
+    pub expected_allowance: Option<Nat>,
+    pub expires_at: Option<u64>,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct GetTransactionsRequest {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TransactionRange {
+    pub transactions: Vec<Transaction>,
+}
+
+// The mock never archives, so `archived_transactions` is always empty
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchivedRange {
+    pub start: Nat,
+    pub length: Nat,
+    pub callback: QueryArchiveFn,
+}
+
+candid::define_function!(pub QueryArchiveFn : (GetTransactionsRequest) -> (TransactionRange) query);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetTransactionsResponse {
+    pub log_length: Nat,
+    pub first_index: Nat,
+    pub transactions: Vec<Transaction>,
+    pub archived_transactions: Vec<ArchivedRange>,
+}
+
+// Balances and allowances are keyed by the normalized account (owner, 32-byte subaccount)
+type AccountKey = (Principal, [u8; 32]);
+
+thread_local! {
+    static BALANCES: RefCell<HashMap<AccountKey, u64>> = RefCell::new(HashMap::new());
+    static ALLOWANCES: RefCell<HashMap<(AccountKey, AccountKey), u64>> = RefCell::new(HashMap::new());
+    static TRANSACTIONS: RefCell<Vec<Transaction>> = const { RefCell::new(Vec::new()) };
+    static TRANSFER_FEE: RefCell<u64> = const { RefCell::new(0) };
+}
+
+#[init]
+fn init(fee: Option<u64>) {
+    TRANSFER_FEE.with(|f| *f.borrow_mut() = fee.unwrap_or(0));
+    ic_cdk::println!("Mock ICRC ledger initialized");
+}
+
+fn account_key(account: &Account) -> Result<AccountKey, String> {
+    match &account.subaccount {
+        None => Ok((account.owner, DEFAULT_SUBACCOUNT)),
+        Some(bytes) => {
+            let subaccount: [u8; 32] = bytes.as_slice()
+                .try_into()
+                .map_err(|_| "Subaccount must be 32 bytes".to_string())?;
+            Ok((account.owner, subaccount))
+        }
+    }
+}
+
+fn nat_to_u64(value: &Nat) -> Result<u64, String> {
+    u64::try_from(value.0.clone()).map_err(|_| "Amount does not fit in u64".to_string())
+}
+
+fn generic_error(message: String) -> TransferError {
+    TransferError::GenericError { error_code: Nat::from(0u8), message }
+}
+
+fn balance(key: &AccountKey) -> u64 {
+    BALANCES.with(|b| b.borrow().get(key).copied().unwrap_or(0))
+}
+
+fn fee() -> u64 {
+    TRANSFER_FEE.with(|f| *f.borrow())
+}
+
+fn record(transaction: Transaction) -> Nat {
+    TRANSACTIONS.with(|t| {
+        let mut transactions = t.borrow_mut();
+        transactions.push(transaction);
+        Nat::from(transactions.len() as u64 - 1)
+    })
+}
+
+// Moves `amount` and burns the fee; callers must have checked the balance
+fn move_funds(from: &AccountKey, to: &AccountKey, amount: u64, fee: u64) {
+    BALANCES.with(|b| {
+        let mut balances = b.borrow_mut();
+        *balances.entry(*from).or_insert(0) -= amount + fee;
+        *balances.entry(*to).or_insert(0) += amount;
+    });
+}
+
+#[query]
+fn icrc1_name() -> String {
+    "Mock Ledger".to_string()
+}
+
+#[query]
+fn icrc1_symbol() -> String {
+    "MOCK".to_string()
+}
+
+#[query]
+fn icrc1_decimals() -> u8 {
+    8
+}
+
+#[query]
+fn icrc1_fee() -> Nat {
+    Nat::from(fee())
+}
+
+#[query]
+fn icrc1_balance_of(account: Account) -> Nat {
+    match account_key(&account) {
+        Ok(key) => Nat::from(balance(&key)),
+        Err(_) => Nat::from(0u8),
+    }
+}
+
+#[update]
+fn icrc1_transfer(args: TransferArg) -> Result<Nat, TransferError> {
+    let caller = msg_caller();
+    let from = account_key(&Account { owner: caller, subaccount: args.from_subaccount.clone() })
+        .map_err(generic_error)?;
+    let to = account_key(&args.to).map_err(generic_error)?;
+    let amount = nat_to_u64(&args.amount).map_err(generic_error)?;
+    let fee = fee();
+    
+    if let Some(requested_fee) = &args.fee {
+        if nat_to_u64(requested_fee) != Ok(fee) {
+            return Err(TransferError::BadFee { expected_fee: Nat::from(fee) });
+        }
+    }
+    
+    let from_balance = balance(&from);
+    if from_balance < amount + fee {
+        return Err(TransferError::InsufficientFunds { balance: Nat::from(from_balance) });
+    }
+    
+    move_funds(&from, &to, amount, fee);
+    
+    Ok(record(Transaction {
+        kind: "transfer".to_string(),
+        mint: None,
+        transfer: Some(Transfer {
+            from: Account { owner: caller, subaccount: args.from_subaccount },
+            to: args.to,
+            spender: None,
+            amount: args.amount,
+            fee: args.fee,
+            memo: args.memo,
+            created_at_time: args.created_at_time,
+        }),
+        approve: None,
+        timestamp: time(),
+    }))
+}
+
+#[update]
+fn icrc2_approve(args: ApproveArgs) -> Result<Nat, ApproveError> {
+    let caller = msg_caller();
+    let to_generic = |message: String| ApproveError::GenericError { error_code: Nat::from(0u8), message };
+    let from = account_key(&Account { owner: caller, subaccount: args.from_subaccount.clone() })
+        .map_err(to_generic)?;
+    let spender = account_key(&args.spender).map_err(to_generic)?;
+    let amount = nat_to_u64(&args.amount).map_err(to_generic)?;
+    
+    let current = ALLOWANCES.with(|a| a.borrow().get(&(from, spender)).copied().unwrap_or(0));
+    if let Some(expected) = &args.expected_allowance {
+        if nat_to_u64(expected) != Ok(current) {
+            return Err(ApproveError::AllowanceChanged { current_allowance: Nat::from(current) });
+        }
+    }
+    
+    ALLOWANCES.with(|a| {
+        a.borrow_mut().insert((from, spender), amount);
+    });
+    
+    Ok(record(Transaction {
+        kind: "approve".to_string(),
+        mint: None,
+        transfer: None,
+        approve: Some(Approve {
+            from: Account { owner: caller, subaccount: args.from_subaccount },
+            spender: args.spender,
+            amount: args.amount,
+            expected_allowance: args.expected_allowance,
+            expires_at: args.expires_at,
+            fee: args.fee,
+            memo: args.memo,
+            created_at_time: args.created_at_time,
+        }),
+        timestamp: time(),
+    }))
+}
+
+#[query]
+fn icrc2_allowance(args: AllowanceArgs) -> Allowance {
+    let allowance = match (account_key(&args.account), account_key(&args.spender)) {
+        (Ok(account), Ok(spender)) => {
+            ALLOWANCES.with(|a| a.borrow().get(&(account, spender)).copied().unwrap_or(0))
+        }
+        _ => 0,
+    };
+    
+    Allowance {
+        allowance: Nat::from(allowance),
+        expires_at: None,
+    }
+}
+
+#[update]
+fn icrc2_transfer_from(args: TransferFromArgs) -> Result<Nat, TransferFromError> {
+    let caller = msg_caller();
+    let to_generic = |message: String| TransferFromError::GenericError { error_code: Nat::from(0u8), message };
+    let spender = account_key(&Account { owner: caller, subaccount: args.spender_subaccount.clone() })
+        .map_err(to_generic)?;
+    let from = account_key(&args.from).map_err(to_generic)?;
+    let to = account_key(&args.to).map_err(to_generic)?;
+    let amount = nat_to_u64(&args.amount).map_err(to_generic)?;
+    let fee = fee();
+    
+    if let Some(requested_fee) = &args.fee {
+        if nat_to_u64(requested_fee) != Ok(fee) {
+            return Err(TransferFromError::BadFee { expected_fee: Nat::from(fee) });
+        }
+    }
+    
+    let allowance = ALLOWANCES.with(|a| a.borrow().get(&(from, spender)).copied().unwrap_or(0));
+    if allowance < amount + fee {
+        return Err(TransferFromError::InsufficientAllowance { allowance: Nat::from(allowance) });
+    }
+    
+    let from_balance = balance(&from);
+    if from_balance < amount + fee {
+        return Err(TransferFromError::InsufficientFunds { balance: Nat::from(from_balance) });
+    }
+    
+    move_funds(&from, &to, amount, fee);
+    ALLOWANCES.with(|a| {
+        a.borrow_mut().insert((from, spender), allowance - amount - fee);
+    });
+    
+    Ok(record(Transaction {
+        kind: "transfer".to_string(),
+        mint: None,
+        transfer: Some(Transfer {
+            from: args.from,
+            to: args.to,
+            spender: Some(Account { owner: caller, subaccount: args.spender_subaccount }),
+            amount: args.amount,
+            fee: args.fee,
+            memo: args.memo,
+            created_at_time: args.created_at_time,
+        }),
+        approve: None,
+        timestamp: time(),
+    }))
+}
+
+// Test helper: credits an account out of thin air
+#[update]
+fn mint(to: Account, amount: u64) -> Result<Nat, String> {
+    let key = account_key(&to)?;
+    
+    BALANCES.with(|b| {
+        *b.borrow_mut().entry(key).or_insert(0) += amount;
+    });
+    
+    Ok(record(Transaction {
+        kind: "mint".to_string(),
+        mint: Some(Mint { to, amount: Nat::from(amount), memo: None, created_at_time: None }),
+        transfer: None,
+        approve: None,
+        timestamp: time(),
+    }))
+}
+
+#[query]
+fn get_transactions(request: GetTransactionsRequest) -> GetTransactionsResponse {
+    TRANSACTIONS.with(|t| {
+        let transactions = t.borrow();
+        let log_length = transactions.len();
+        let start = usize::try_from(request.start.0).unwrap_or(usize::MAX).min(log_length);
+        let length = usize::try_from(request.length.0).unwrap_or(usize::MAX);
+        let end = start.saturating_add(length).min(log_length);
+        
+        GetTransactionsResponse {
+            log_length: Nat::from(log_length as u64),
+            first_index: Nat::from(start as u64),
+            transactions: transactions[start..end].to_vec(),
+            archived_transactions: Vec::new(),
+        }
+    })
+}
+
+ic_cdk::export_candid!();
@@ -4,17 +4,20 @@ use ic_cdk_macros::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::cell::RefCell;
-use ark_bn254::{Bn254, Fr, G1Affine, G2Affine};
-use ark_ff::{FromBytes, PrimeField};
+use ark_bn254::{Bn254, Fr};
+use ark_ff::FromBytes;
 use ark_groth16::{prepare_verifying_key, verify_proof, Proof, VerifyingKey};
-use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_serialize::CanonicalDeserialize;
 use sha2::{Sha256, Digest};
-use hex;
+use ipx_types::MintRequest;
 
 
 
 type TokenId = u64;
 
+// Most tokens `icrc7_owners_of` answers for in one call
+const MAX_OWNER_QUERY_BATCH: usize = 1_000;
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct TokenMetadata {
     pub token_id: TokenId,
@@ -25,6 +28,9 @@ pub struct TokenMetadata {
     pub share_percentage: f64,
     pub metadata_json: String,
     pub created_at: u64,
+    pub tranche_id: Option<u64>, // Set for position NFTs minted through `mint_position`
+    pub share_units: Option<u64>,
+    pub voided_at: Option<u64>, // Set when the vault refunded the position; the token can't move after
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -89,7 +95,9 @@ thread_local! {
     static TOKENS: RefCell<HashMap<TokenId, TokenMetadata>> = RefCell::new(HashMap::new());
     static TOKEN_APPROVALS: RefCell<HashMap<TokenId, Principal>> = RefCell::new(HashMap::new());
     static OPERATOR_APPROVALS: RefCell<HashMap<(Principal, Principal), bool>> = RefCell::new(HashMap::new());
-    static TOKEN_COUNTER: RefCell<TokenId> = RefCell::new(0);
+    static TOKEN_COUNTER: RefCell<TokenId> = const { RefCell::new(0) };
+    // Position NFTs by (vault_canister, tranche_id); derived from TOKENS and rebuilt on upgrade
+    static POSITION_INDEX: RefCell<HashMap<(Principal, u64), TokenId>> = RefCell::new(HashMap::new());
     static COLLECTION_METADATA: RefCell<CollectionMetadata> = RefCell::new(
         CollectionMetadata {
             name: "IPX Campaign NFTs".to_string(),
@@ -107,7 +115,7 @@ thread_local! {
     static VERIFIER_KEYS: RefCell<HashMap<ProofType, Vec<u8>>> = RefCell::new(HashMap::new());
     
     // Legacy verifier key for backward compatibility
-    static VERIFIER_KEY: RefCell<Option<Vec<u8>>> = RefCell::new(None);
+    static VERIFIER_KEY: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
     
     // YouTube metrics storage
     static YOUTUBE_METRICS: RefCell<HashMap<String, YouTubeMetrics>> = RefCell::new(HashMap::new());
@@ -151,6 +159,19 @@ fn icrc7_owner_of(token_id: TokenId) -> Option<Principal> {
     })
 }
 
+// Batch form of `icrc7_owner_of`, shaped like ICRC-7's: one entry per requested token, in order
+#[query]
+fn icrc7_owners_of(token_ids: Vec<TokenId>) -> Vec<Option<Principal>> {
+    if token_ids.len() > MAX_OWNER_QUERY_BATCH {
+        ic_cdk::trap(format!("At most {} tokens can be looked up per call", MAX_OWNER_QUERY_BATCH));
+    }
+    
+    TOKENS.with(|tokens| {
+        let tokens = tokens.borrow();
+        token_ids.iter().map(|token_id| tokens.get(token_id).map(|token| token.owner)).collect()
+    })
+}
+
 #[query]
 fn icrc7_balance_of(owner: Principal) -> u64 {
     TOKENS.with(|tokens| {
@@ -192,6 +213,10 @@ fn icrc7_transfer(args: TransferArgs) -> Result<TokenId, String> {
                 return Err("From address doesn't match token owner".to_string());
             }
             
+            if token_data.voided_at.is_some() {
+                return Err("Token is void: its position was refunded".to_string());
+            }
+            
             // Update ownership
             token_data.owner = args.to;
             
@@ -216,7 +241,7 @@ fn icrc7_transfer(args: TransferArgs) -> Result<TokenId, String> {
 // Helper function to check if a caller is approved for a token
 fn is_approved(token_id: TokenId, caller: Principal) -> bool {
     TOKEN_APPROVALS.with(|approvals| {
-        approvals.borrow().get(&token_id).map_or(false, |approved| *approved == caller)
+        approvals.borrow().get(&token_id).is_some_and(|approved| *approved == caller)
     })
 }
 
@@ -295,6 +320,9 @@ fn mint(
         share_percentage,
         metadata_json,
         created_at: time(),
+        tranche_id: None,
+        share_units: None,
+        voided_at: None,
     };
     
     TOKENS.with(|tokens| {
@@ -313,6 +341,85 @@ fn mint(
 
 
 
+// Mints the NFT for one vault tranche. Only the vault itself may mint its positions, and a
+// repeated request returns the existing token, so a retried call can't mint twice.
+#[update]
+fn mint_position(request: MintRequest) -> Result<TokenId, String> {
+    if msg_caller() != request.vault_canister {
+        return Err("Only the vault can mint its position NFTs".to_string());
+    }
+    
+    let position = (request.vault_canister, request.tranche_id);
+    let existing = POSITION_INDEX.with(|index| index.borrow().get(&position).copied());
+    if let Some(token_id) = existing {
+        return Ok(token_id);
+    }
+    
+    let token_id = TOKEN_COUNTER.with(|counter| {
+        let next = *counter.borrow() + 1;
+        *counter.borrow_mut() = next;
+        next
+    });
+    
+    TOKENS.with(|tokens| {
+        tokens.borrow_mut().insert(token_id, TokenMetadata {
+            token_id,
+            owner: request.to,
+            campaign_id: request.campaign_id,
+            vault_canister: request.vault_canister,
+            investment_amount: request.investment_amount,
+            share_percentage: request.share_percentage,
+            metadata_json: request.metadata_json,
+            created_at: time(),
+            tranche_id: Some(request.tranche_id),
+            share_units: Some(request.share_units),
+            voided_at: None,
+        });
+    });
+    POSITION_INDEX.with(|index| {
+        index.borrow_mut().insert(position, token_id);
+    });
+    
+    COLLECTION_METADATA.with(|metadata| {
+        metadata.borrow_mut().total_supply += 1;
+    });
+    
+    ic_cdk::println!(
+        "Position NFT {} minted for {} (campaign {}, tranche {})",
+        token_id, request.to.to_text(), request.campaign_id, request.tranche_id
+    );
+    
+    Ok(token_id)
+}
+
+// Marks refunded positions void. Only the vault that minted a position can void it, and
+// voiding a token again is a no-op, so the vault can retry a partly failed call.
+#[update]
+fn void_positions(token_ids: Vec<TokenId>) -> Result<(), String> {
+    let caller = msg_caller();
+    
+    TOKENS.with(|tokens| {
+        let mut tokens = tokens.borrow_mut();
+        for token_id in &token_ids {
+            let token = tokens.get(token_id).ok_or_else(|| format!("Token {} not found", token_id))?;
+            if token.vault_canister != caller || token.tranche_id.is_none() {
+                return Err(format!("Token {} is not a position minted by the caller", token_id));
+            }
+        }
+        
+        let now = time();
+        for token_id in &token_ids {
+            if let Some(token) = tokens.get_mut(token_id) {
+                token.voided_at.get_or_insert(now);
+            }
+        }
+        Ok(())
+    })?;
+    
+    ic_cdk::println!("Voided {} position NFTs for vault {}", token_ids.len(), caller.to_text());
+    Ok(())
+}
+
 #[update]
 fn set_youtube_verifier_key(key: Vec<u8>, proof_type: ProofType) -> Result<bool, String> {
     let caller = msg_caller();
@@ -374,7 +481,7 @@ fn verify_zk_proof(
     let mut public_inputs_fr = Vec::with_capacity(public_inputs.len());
     for input in public_inputs {
         // Remove "0x" prefix if present
-        let input_str = if input.starts_with("0x") { &input[2..] } else { input };
+        let input_str = input.strip_prefix("0x").unwrap_or(input);
         
         // Convert hex string to bytes
         let bytes = hex::decode(input_str)
@@ -394,20 +501,14 @@ fn verify_zk_proof(
     if let Some(proof_type) = proof_type {
         // For subscriber count and view count proofs, we need to validate the public inputs format
         match proof_type {
-            ProofType::SubscriberCount => {
-                if public_inputs.len() < 2 {
-                    return Err("SubscriberCount proof requires at least 2 public inputs".to_string());
-                }
+            ProofType::SubscriberCount if public_inputs.len() < 2 => {
+                return Err("SubscriberCount proof requires at least 2 public inputs".to_string());
             },
-            ProofType::ViewCount => {
-                if public_inputs.len() < 2 {
-                    return Err("ViewCount proof requires at least 2 public inputs".to_string());
-                }
+            ProofType::ViewCount if public_inputs.len() < 2 => {
+                return Err("ViewCount proof requires at least 2 public inputs".to_string());
             },
-            ProofType::VideoEngagement => {
-                if public_inputs.len() < 3 {
-                    return Err("VideoEngagement proof requires at least 3 public inputs".to_string());
-                }
+            ProofType::VideoEngagement if public_inputs.len() < 3 => {
+                return Err("VideoEngagement proof requires at least 3 public inputs".to_string());
             },
             _ => {}
         }
@@ -431,6 +532,7 @@ fn verify_zk_proof(
 }
 
 #[update]
+#[allow(clippy::too_many_arguments)]
 fn store_youtube_zk_proof(
     proof_bytes: Vec<u8>,
     public_inputs: Vec<String>,
@@ -462,7 +564,7 @@ fn store_youtube_zk_proof(
     };
     
     // Verify the ZK proof
-    let is_valid = verify_zk_proof(&proof_bytes, &public_inputs, &key, Some(proof_type.clone()))
+    let is_valid = verify_zk_proof(&proof_bytes, &public_inputs, &key, Some(proof_type))
         .map_err(|e| format!("Failed to verify ZK proof: {}", e))?;
     
     if !is_valid {
@@ -704,6 +806,7 @@ fn get_principal_by_youtube_channel(channel_id: String) -> Option<Principal> {
 
 // Enhanced NFT minting with YouTube identity verification
 #[update]
+#[allow(clippy::too_many_arguments)]
 fn mint_nft_with_youtube_verification(
     to: Principal,
     campaign_id: u64, 
@@ -743,14 +846,10 @@ fn mint_nft_with_youtube_verification(
     // Get YouTube metrics if available to include in metadata
     let youtube_metrics_json = if let Some(channel_id) = youtube_channel_id {
         YOUTUBE_METRICS.with(|m| {
-            if let Some(metrics) = m.borrow().get(&channel_id) {
-                Some(format!(
-                    r#", "youtube_metrics": {{"subscribers": {}, "views": {}, "videos": {}, "verified_at": {}}}"#,
-                    metrics.subscriber_count, metrics.view_count, metrics.video_count, metrics.verified_at
-                ))
-            } else {
-                None
-            }
+            m.borrow().get(&channel_id).map(|metrics| format!(
+                r#", "youtube_metrics": {{"subscribers": {}, "views": {}, "videos": {}, "verified_at": {}}}"#,
+                metrics.subscriber_count, metrics.view_count, metrics.video_count, metrics.verified_at
+            ))
         })
     } else {
         None
@@ -784,6 +883,9 @@ fn mint_nft_with_youtube_verification(
         share_percentage,
         metadata_json: enhanced_metadata,
         created_at: time(),
+        tranche_id: None,
+        share_units: None,
+        voided_at: None,
     };
     
     TOKENS.with(|tokens| {
@@ -857,7 +959,7 @@ fn pre_upgrade() {
     
     match ic_cdk::storage::stable_save((state,)) {
         Ok(_) => (),
-        Err(e) => ic_cdk::trap(&format!("Failed to save state: {:?}", e)),
+        Err(e) => ic_cdk::trap(format!("Failed to save state: {:?}", e)),
     }
 }
 
@@ -960,8 +1062,22 @@ fn post_upgrade() {
         }
     }
     
+    
+    rebuild_position_index();
    
     ic_cdk::println!("NFT Registry restored with YouTube ZK proof verifier");
 }
+
+fn rebuild_position_index() {
+    let index: HashMap<(Principal, u64), TokenId> = TOKENS.with(|tokens| {
+        tokens
+            .borrow()
+            .values()
+            .filter_map(|token| token.tranche_id.map(|tranche_id| ((token.vault_canister, tranche_id), token.token_id)))
+            .collect()
+    });
+    ic_cdk::println!("Position index built for {} tokens", index.len());
+    POSITION_INDEX.with(|position_index| *position_index.borrow_mut() = index);
+}
 ic_cdk::export_candid!();
 
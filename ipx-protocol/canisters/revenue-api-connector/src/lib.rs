@@ -7,6 +7,7 @@ use ic_cdk::api::management_canister::http_request::{
     http_request,
 };
 use serde::{Deserialize, Serialize};
+use ipx_types::revenue_platform;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::cell::RefCell;
@@ -191,10 +192,12 @@ fn parse_revenue_from_response(
         .map_err(|_| "Invalid JSON response")?;
     
     // Extract revenue based on platform-specific data paths
-    let amount = match endpoint.platform.as_str() {
-        "youtube" => extract_youtube_revenue(&json)?,
-        "spotify" => extract_spotify_revenue(&json)?,
-        "substack" => extract_substack_revenue(&json)?,
+    let platform = revenue_platform(&endpoint.platform)
+        .ok_or_else(|| "Unsupported platform".to_string())?;
+    let amount = match platform.name {
+        "youtube" => extract_youtube_revenue(&json, platform.revenue_path)?,
+        "spotify" => extract_spotify_revenue(&json, platform.revenue_path)?,
+        "substack" => extract_substack_revenue(&json, platform.revenue_path)?,
         _ => return Err("Unsupported platform".to_string()),
     };
     
@@ -209,26 +212,26 @@ fn parse_revenue_from_response(
     })
 }
 
-fn extract_youtube_revenue(json: &Value) -> Result<u64, String> {
+fn extract_youtube_revenue(json: &Value, revenue_path: &str) -> Result<u64, String> {
     // YouTube Analytics API response parsing
-    json.pointer("/reports/0/data/totals/0/values/0")
+    json.pointer(revenue_path)
         .and_then(|v| v.as_str())
         .and_then(|s| s.parse::<f64>().ok())
         .map(|f| (f * 100.0) as u64) // Convert to cents
         .ok_or_else(|| "Failed to extract YouTube revenue".to_string())
 }
 
-fn extract_spotify_revenue(json: &Value) -> Result<u64, String> {
+fn extract_spotify_revenue(json: &Value, revenue_path: &str) -> Result<u64, String> {
     // Spotify for Artists API response parsing
-    json.pointer("/revenue/total")
+    json.pointer(revenue_path)
         .and_then(|v| v.as_f64())
         .map(|f| (f * 100.0) as u64) // Convert to cents
         .ok_or_else(|| "Failed to extract Spotify revenue".to_string())
 }
 
-fn extract_substack_revenue(json: &Value) -> Result<u64, String> {
+fn extract_substack_revenue(json: &Value, revenue_path: &str) -> Result<u64, String> {
     // Substack API response parsing
-    json.pointer("/stats/revenue/total")
+    json.pointer(revenue_path)
         .and_then(|v| v.as_f64())
         .map(|f| (f * 100.0) as u64) // Convert to cents
         .ok_or_else(|| "Failed to extract Substack revenue".to_string())
@@ -1,17 +1,22 @@
 use ic_cdk::api::{msg_caller, time, canister_self};
-use candid::{CandidType, Principal};
+use candid::{CandidType, Decode, Encode, Nat, Principal};
 use ic_cdk_macros::*;
-use ic_cdk::api::call::{call, CallResult};
+use ic_cdk::call::Call;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use ipx_types::{
+    impl_candid_storable, nat_to_u64, Account, GetTransactionsRequest, GetTransactionsResponse, LedgerTransaction,
+    MintRequest, RevenueLimit, TransactionRange, TransferArg, TransferError, TransferFromArgs, TransferFromError,
+};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use ic_stable_structures::{
     memory_manager::{MemoryId, MemoryManager, VirtualMemory},
-    DefaultMemoryImpl, StableBTreeMap,
+    DefaultMemoryImpl, StableBTreeMap, StableCell, StableLog,
 };
-use std::cmp;
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
+// Full view of the vault returned by `get_vault_state`, assembled from the stable regions
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct VaultState {
     pub campaign_id: u64,
@@ -25,6 +30,7 @@ pub struct VaultState {
     pub nft_registry_canister: Option<Principal>,
     pub stream_canister: Option<Principal>,
     pub oracle_canister: Option<Principal>,
+    pub ledger_canister: Option<Principal>,
     pub backers: HashMap<Principal, BackerInfo>,
     pub revenue_history: Vec<RevenueUpdate>,
     pub created_at: u64,
@@ -35,15 +41,156 @@ pub struct VaultState {
     pub insurance_claims: Vec<InsuranceClaim>,
     pub slashing_conditions: SlashingConditions,
     pub slashed_creators: Vec<SlashEvent>,
+    pub ledger_transfers: Vec<LedgerTransfer>,
+    pub funding_deadline: Option<u64>,
+    pub refunds_opened_at: Option<u64>,
+    pub payout_stream: Option<PayoutStreamSettings>,
+}
+
+// Headline figures for the factory's stats job, without the per-backer and history records
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VaultStats {
+    pub current_funding: u64,
+    pub funding_goal: u64,
+    pub backer_count: u64,
+    pub total_revenue: u64,
+}
+
+// Scalar vault settings and counters, kept in a single stable cell
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VaultConfig {
+    pub campaign_id: u64,
+    pub creator: Principal,
+    pub title: String,
+    pub funding_goal: u64,
+    pub current_funding: u64,
+    pub revenue_share_percentage: u8,
+    pub total_revenue: u64,
+    pub oracle_endpoints: Vec<String>,
+    pub nft_registry_canister: Option<Principal>,
+    pub stream_canister: Option<Principal>,
+    pub oracle_canister: Option<Principal>,
+    pub ledger_canister: Option<Principal>,
+    pub factory_canister: Option<Principal>, // Installer of the vault, notified when the goal is reached
+    pub funding_deadline: Option<u64>,
+    pub refunds_opened_at: Option<u64>, // Set once the raise failed or was cancelled
+    pub awaiting_launch: Option<bool>, // Investments are rejected while this is Some(true)
+    pub payout_stream: Option<PayoutStreamSettings>, // ipx-stream defaults apply when None
+    pub reporter_limits: Option<Vec<(Principal, RevenueLimit)>>, // Reporters without one get `default_revenue_limit`
+    pub source_limits: Option<Vec<(Principal, String, RevenueLimit)>>, // Apply on top of the reporter's limit
+    pub created_at: u64,
+    pub insurance_pool_balance: u64,
+    pub insurance_fee_percentage: u8,
+    pub insurance_coverage_ratio: u8,
+    pub slashing_conditions: SlashingConditions,
 }
 
+// A backer's position: the running total of all their tranches
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct BackerInfo {
     pub amount_invested: u64,
+    pub nft_token_id: Option<u64>, // Token of the first tranche; every tranche has its own
+    pub share_percentage: f64, // Display only; payouts use share_units
+    pub total_claimed: u64, // Revenue claimed by this principal
+    pub investment_timestamp: u64, // First investment
+    pub refunded_at: Option<u64>,
+    pub last_investment_at: Option<u64>,
+    pub tranche_count: Option<u32>,
+    pub share_units: Option<u64>, // Out of SHARE_SCALE; filled in on upgrade for older positions
+}
+
+// One investment by a backer
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Tranche {
+    pub tranche_id: u64,
+    pub backer: Principal,
+    pub amount_invested: u64, // Including the insurance fee
+    pub insurance_fee: u64,
+    pub share_percentage: f64, // Share bought by this tranche alone; display only
+    pub block_index: Option<u64>, // Deposit block; None for positions recorded before tranches
+    pub invested_at: u64,
     pub nft_token_id: Option<u64>,
-    pub share_percentage: f64,
-    pub total_claimed: u64,
-    pub investment_timestamp: u64,
+    pub share_units: Option<u64>,
+    pub paid_out: Option<u64>, // Revenue streamed for this tranche before distribution epochs
+}
+
+// A tranche whose NFT couldn't be minted during `invest`; retried by `retry_pending_mints`
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PendingMint {
+    pub tranche_id: u64,
+    pub attempts: u32,
+    pub last_error: String,
+    pub queued_at: u64,
+    pub last_attempt_at: u64,
+}
+
+// Shares are integer units of the funding goal; SHARE_SCALE units are 100% (basis points x 1e6)
+const SHARE_SCALE: u64 = 10_000_000_000;
+
+// Matches the nft-registry's limit for `icrc7_owners_of`
+const OWNER_LOOKUP_BATCH_SIZE: usize = 1_000;
+
+fn share_units_for(amount_after_fee: u64, funding_goal: u64) -> u64 {
+    (amount_after_fee as u128 * SHARE_SCALE as u128 / funding_goal.max(1) as u128) as u64
+}
+
+fn units_to_percentage(units: u64) -> f64 {
+    units as f64 * 100.0 / SHARE_SCALE as f64
+}
+
+// Splits `pool` across holders by their share units using largest-remainder rounding, so
+// allocations never drift from the pool. Shares that were never sold take part like a holder
+// and their part is returned as `dust`; it stays in the vault. Ties go to the lower key.
+fn allocate_pool<K: Ord + Copy>(pool: u64, holders: &[(K, u64)]) -> Result<(Vec<(K, u64)>, u64), String> {
+    let issued: u128 = holders.iter().map(|(_, units)| *units as u128).sum();
+    if issued > SHARE_SCALE as u128 {
+        return Err(format!("Issued share units {} exceed the supply of {}", issued, SHARE_SCALE));
+    }
+    
+    let scale = SHARE_SCALE as u128;
+    let exact = |units: u128| (pool as u128 * units / scale, pool as u128 * units % scale);
+    
+    let mut allocations: Vec<(Option<K>, u128, u128)> = holders
+        .iter()
+        .map(|(holder, units)| {
+            let (floor, remainder) = exact(*units as u128);
+            (Some(*holder), floor, remainder)
+        })
+        .collect();
+    let (unissued_floor, unissued_remainder) = exact(scale - issued);
+    allocations.push((None, unissued_floor, unissued_remainder));
+    
+    let floors: u128 = allocations.iter().map(|(_, floor, _)| floor).sum();
+    let leftover = pool as u128 - floors; // Fewer units than there are entries
+    
+    let mut order: Vec<usize> = (0..allocations.len()).collect();
+    order.sort_by(|&a, &b| {
+        let (holder_a, _, remainder_a) = &allocations[a];
+        let (holder_b, _, remainder_b) = &allocations[b];
+        remainder_b
+            .cmp(remainder_a)
+            .then_with(|| holder_a.is_none().cmp(&holder_b.is_none()))
+            .then_with(|| holder_a.cmp(holder_b))
+    });
+    for &index in order.iter().take(leftover as usize) {
+        allocations[index].1 += 1;
+    }
+    
+    let dust = allocations.pop().map_or(0, |(_, amount, _)| amount as u64);
+    let allocations: Vec<(K, u64)> = allocations
+        .into_iter()
+        .filter_map(|(holder, amount, _)| holder.map(|holder| (holder, amount as u64)))
+        .collect();
+    
+    let distributed: u64 = allocations.iter().map(|(_, amount)| amount).sum();
+    if distributed + dust != pool {
+        return Err(format!(
+            "Payout invariant violated: distributed {} + dust {} != pool {}",
+            distributed, dust, pool
+        ));
+    }
+    
+    Ok((allocations, dust))
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -52,14 +199,159 @@ pub struct RevenueUpdate {
     pub source: String,
     pub timestamp: u64,
     pub oracle_verification: bool,
+    pub reporter: Option<Principal>, // None for entries recorded before reporters were checked
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum QuarantineReason {
+    UnauthorizedReporter, // Only on older reports; unauthorized reporters are now rejected outright
+    ExceedsReportLimit,
+    ExceedsWindowLimit,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum QuarantineStatus {
+    Pending,
+    Accepted { reviewed_at: u64 },
+    Rejected { reviewed_at: u64 },
+}
+
+// Running totals for one authorized reporter, or for one of their sources that has its own limit,
+// so limits are checked without scanning the history. The window restarts with the first report
+// after the previous one ended.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ReporterActivity {
+    pub window_started_at: u64,
+    pub reported_in_window: u64,
+    pub pending_quarantine: u64, // Reports from this reporter awaiting review; kept per reporter only
+}
+
+// A revenue report that wasn't counted, kept until the factory accepts or rejects it
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct QuarantinedRevenue {
+    pub report_id: u64,
+    pub reporter: Principal,
+    pub amount: u64,
+    pub source: String,
+    pub reason: QuarantineReason,
+    pub reported_at: u64,
+    pub status: QuarantineStatus,
 }
 
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum EpochStatus {
+    Open,
+    Closing, // Next epoch is already open; holders are being resolved
+    Closed,
+}
+
+// A period of revenue. Closing it snapshots who holds which tranches and fixes what each
+// holder may claim for it; revenue reported meanwhile goes to the next epoch.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DistributionEpoch {
+    pub epoch_id: u64,
+    pub status: EpochStatus,
+    pub opened_at: u64,
+    pub closed_at: Option<u64>,
+    pub revenue: u64,
+    pub investor_pool: u64, // Set on close, including any slashed creator share
+    pub entitled: u64, // Sum of the holder entitlements
+    pub dust: u64, // Part of the pool for unsold shares or rounding; stays in the vault
+    pub holder_count: u32,
+    pub includes_legacy_revenue: bool, // Revenue from before epochs; tranche `paid_out` is deducted
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum EntitlementStatus {
+    Unclaimed,
+    Claiming,
+    Claimed { claimed_at: u64, stream_id: Option<u64> },
+}
+
+// What one holder may claim for one closed epoch
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EpochEntitlement {
+    pub epoch_id: u64,
+    pub holder: Principal,
+    pub tranche_ids: Vec<u64>, // Tranches the holder held when the epoch closed
+    pub share_units: u64,
+    pub amount: u64,
+    pub status: EntitlementStatus,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum OperationKind {
+    Invest { backer: Principal, amount: u64, created_at_time: u64 },
+    Refund { backer: Principal, amount: u64, fee: u64, created_at_time: u64 },
+    // `amount` reaches the recipient and `fee` goes to the ledger; payouts journaled before the
+    // fee was taken out of the payout have no `fee`
+    Payout { to: Principal, amount: u64, created_at_time: u64, payout_ref: Option<u64>, fee: Option<u64> },
+    Mint { tranche_id: u64 },
+    CloseEpoch { epoch_id: u64 },
+    EpochPayout { epoch_id: u64, holder: Principal, amount: u64 },
+    InsurancePayout { claim_id: u64, claimer: Principal, amount: u64 },
+}
+
+// An async flow that has made an inter-canister call but not yet recorded the outcome. If the
+// callback traps, the entry outlives the call and `reconcile_pending_operations` settles it.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PendingOperation {
+    pub op_id: u64,
+    pub kind: OperationKind,
+    pub started_at: u64,
+    pub reconcile_attempts: u32,
+    pub last_error: Option<String>,
+}
+
+// Pending reports past this are rejected outright so a reporter can't use the quarantine to fill memory
+const MAX_PENDING_QUARANTINE_PER_REPORTER: u64 = 100;
+
+// Reporters without an explicit limit may report up to the funding goal per report and per window
+const DEFAULT_REVENUE_WINDOW_SECONDS: u64 = 30 * 24 * 60 * 60;
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct InvestmentResult {
     pub success: bool,
     pub nft_token_id: Option<u64>,
     pub share_percentage: f64,
+    pub block_index: Option<u64>,
     pub message: String,
+    pub tranche_id: Option<u64>,
+    pub share_units: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TransferKind {
+    Deposit,
+    Payout,
+    Refund,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LedgerTransfer {
+    pub kind: TransferKind,
+    pub counterparty: Principal,
+    pub amount: u64,
+    pub block_index: u64,
+    pub timestamp: u64,
+}
+
+// A payout the stream canister asked for under its own reference. The stream canister keeps the
+// claimed amount reserved until the record says whether the ledger paid it.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum PayoutStatus {
+    Pending,
+    Paid { block_index: u64 },
+    Failed { reason: String },
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PayoutRecord {
+    pub payout_ref: u64,
+    pub to: Principal,
+    pub amount: u64,
+    pub requested_at: u64,
+    pub status: PayoutStatus,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -119,535 +411,2643 @@ pub struct CampaignMetadata {
     pub funding_goal: u64,
     pub revenue_share_percentage: u8,
     pub oracle_endpoints: Vec<String>,
+    pub funding_deadline: Option<u64>,
+}
+
+// Mirrors ipx-stream's stream types
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum StreamType {
+    Linear,
+    Cliff,
+    Exponential,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PayoutStreamSettings {
+    pub stream_type: StreamType,
+    pub duration_seconds: u64,
+}
+
+// The fields of an ipx-stream `Stream` that reconciliation matches on
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct StreamRecord {
+    stream_id: u64,
+    total_amount: u64,
+    start_time: u64,
+    vault_canister: Principal,
+}
+
+// Revenue source in the shape revenue-api-connector's `register_campaign_oracle` takes
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ApiEndpoint {
+    pub platform: String,
+    pub url: String,
+    pub auth_header: Option<String>,
+    pub data_path: String,
+}
+
+// Argument to ipx-stream's `create_streams`
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct StreamSettings {
+    pub stream_type: StreamType,
+    pub duration_seconds: u64,
+    pub campaign_id: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct InsuranceSettings {
+    pub fee_percentage: u8,
+    pub coverage_ratio: u8,
+    pub slashing_conditions: SlashingConditions,
+}
+
+// Init argument supplied by the campaign factory when it installs the vault
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VaultInitArgs {
+    pub campaign_id: u64,
+    pub metadata: CampaignMetadata,
+    pub insurance: Option<InsuranceSettings>, // Defaults apply when omitted
+    pub nft_registry_canister: Option<Principal>,
+    pub stream_canister: Option<Principal>,
+    pub oracle_canister: Option<Principal>,
+    pub ledger_canister: Option<Principal>, // ICRC-1/ICRC-2 ledger holding campaign funds
+    pub awaiting_launch: Option<bool>, // Set by the factory so investments wait for the campaign launch
+    pub payout_stream: Option<PayoutStreamSettings>, // Chosen by the campaign template
+}
+
+// Campaign fields the factory may change while the campaign is still a Draft
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VaultMetadataUpdate {
+    pub title: Option<String>,
+    pub funding_goal: Option<u64>,
+    pub revenue_share_percentage: Option<u8>,
+    pub oracle_endpoints: Option<Vec<String>>,
+    pub funding_deadline: Option<u64>,
 }
 
+impl_candid_storable!(VaultConfig, BackerInfo, RevenueUpdate, InsuranceClaim, SlashEvent, LedgerTransfer, Tranche, PendingMint, QuarantinedRevenue, DistributionEpoch, EpochEntitlement, PendingOperation, ReporterActivity, PayoutRecord);
+
+// Stable memory layout. Every region lives in stable memory directly, so upgrades
+// keep all state without pre/post upgrade hooks. Never reuse or renumber an ID.
+const CONFIG_MEMORY_ID: MemoryId = MemoryId::new(0);
+const BACKERS_MEMORY_ID: MemoryId = MemoryId::new(1);
+const REVENUE_INDEX_MEMORY_ID: MemoryId = MemoryId::new(2);
+const REVENUE_DATA_MEMORY_ID: MemoryId = MemoryId::new(3);
+const CLAIMS_MEMORY_ID: MemoryId = MemoryId::new(4);
+const SLASH_INDEX_MEMORY_ID: MemoryId = MemoryId::new(5);
+const SLASH_DATA_MEMORY_ID: MemoryId = MemoryId::new(6);
+const TRANSFERS_INDEX_MEMORY_ID: MemoryId = MemoryId::new(7);
+const TRANSFERS_DATA_MEMORY_ID: MemoryId = MemoryId::new(8);
+const TRANCHES_MEMORY_ID: MemoryId = MemoryId::new(9);
+const PENDING_MINTS_MEMORY_ID: MemoryId = MemoryId::new(10);
+const QUARANTINE_MEMORY_ID: MemoryId = MemoryId::new(11);
+const EPOCHS_MEMORY_ID: MemoryId = MemoryId::new(12);
+const ENTITLEMENTS_MEMORY_ID: MemoryId = MemoryId::new(13);
+const OPERATIONS_MEMORY_ID: MemoryId = MemoryId::new(14);
+const REPORTER_ACTIVITY_MEMORY_ID: MemoryId = MemoryId::new(15);
+const PAYOUTS_MEMORY_ID: MemoryId = MemoryId::new(16);
+const STREAM_ALLOWANCES_MEMORY_ID: MemoryId = MemoryId::new(17);
+const SOURCE_ACTIVITY_MEMORY_ID: MemoryId = MemoryId::new(18);
+
 thread_local! {
     static MEMORY_MANAGER: MemoryManager<DefaultMemoryImpl> = MemoryManager::init(DefaultMemoryImpl::default());
     
-    static VAULT_STATE: std::cell::RefCell<Option<VaultState>> = std::cell::RefCell::new(None);
-}
-
-#[init]
-fn init() {
-    // Initialize with default state - can be configured later via update calls
-    let vault_state = VaultState {
-        campaign_id: 0,
-        creator: Principal::anonymous(),
-        title: "Default Vault".to_string(),
-        funding_goal: 1000000, // 1M default goal
-        current_funding: 0,
-        revenue_share_percentage: 10, // Default 10%
-        total_revenue: 0,
-        oracle_endpoints: Vec::new(), // Empty by default
-        nft_registry_canister: None,
-        stream_canister: None,
-        oracle_canister: None,
-        backers: HashMap::new(),
-        revenue_history: Vec::new(),
-        created_at: time(),
-        // Insurance pool defaults
-        insurance_pool_balance: 0,
-        insurance_fee_percentage: 2, // Default 2% insurance fee
-        insurance_coverage_ratio: 80, // Default 80% coverage of investment
-        insurance_claims: Vec::new(),
-        slashing_conditions: SlashingConditions {
-            missed_revenue_reports_threshold: 3, // 3 missed reports
-            revenue_decline_threshold_percentage: 70, // 70% decline triggers review
-            minimum_active_period_days: 30, // Must be active for 30 days
-            governance_votes_required: 51, // 51% votes required for slashing
-        },
-        slashed_creators: Vec::new(),
-    };
+    static CONFIG: RefCell<StableCell<Option<VaultConfig>, Memory>> = RefCell::new(
+        StableCell::init(memory(CONFIG_MEMORY_ID), None).expect("failed to init vault config")
+    );
     
-    VAULT_STATE.with(|state| {
-        *state.borrow_mut() = Some(vault_state);
-    });
+    static BACKERS: RefCell<StableBTreeMap<Principal, BackerInfo, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(BACKERS_MEMORY_ID))
+    );
+    
+    static REVENUE_HISTORY: RefCell<StableLog<RevenueUpdate, Memory, Memory>> = RefCell::new(
+        StableLog::init(memory(REVENUE_INDEX_MEMORY_ID), memory(REVENUE_DATA_MEMORY_ID))
+            .expect("failed to init revenue history")
+    );
+    
+    static INSURANCE_CLAIMS: RefCell<StableBTreeMap<u64, InsuranceClaim, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(CLAIMS_MEMORY_ID))
+    );
     
-    ic_cdk::println!("Vault initialized with default settings including insurance pool");
+    static SLASH_EVENTS: RefCell<StableLog<SlashEvent, Memory, Memory>> = RefCell::new(
+        StableLog::init(memory(SLASH_INDEX_MEMORY_ID), memory(SLASH_DATA_MEMORY_ID))
+            .expect("failed to init slash events")
+    );
+    
+    static LEDGER_TRANSFERS: RefCell<StableLog<LedgerTransfer, Memory, Memory>> = RefCell::new(
+        StableLog::init(memory(TRANSFERS_INDEX_MEMORY_ID), memory(TRANSFERS_DATA_MEMORY_ID))
+            .expect("failed to init ledger transfers")
+    );
+    
+    static TRANCHES: RefCell<StableBTreeMap<u64, Tranche, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(TRANCHES_MEMORY_ID))
+    );
+    
+    static PENDING_MINTS: RefCell<StableBTreeMap<u64, PendingMint, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(PENDING_MINTS_MEMORY_ID))
+    );
+    
+    static QUARANTINED_REVENUE: RefCell<StableBTreeMap<u64, QuarantinedRevenue, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(QUARANTINE_MEMORY_ID))
+    );
+    
+    static EPOCHS: RefCell<StableBTreeMap<u64, DistributionEpoch, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(EPOCHS_MEMORY_ID))
+    );
+    
+    static ENTITLEMENTS: RefCell<StableBTreeMap<(u64, Principal), EpochEntitlement, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(ENTITLEMENTS_MEMORY_ID))
+    );
+    
+    static PENDING_OPERATIONS: RefCell<StableBTreeMap<u64, PendingOperation, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(OPERATIONS_MEMORY_ID))
+    );
+    
+    static REPORTER_ACTIVITY: RefCell<StableBTreeMap<Principal, ReporterActivity, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(REPORTER_ACTIVITY_MEMORY_ID))
+    );
+    
+    static SOURCE_ACTIVITY: RefCell<StableBTreeMap<(Principal, String), ReporterActivity, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(SOURCE_ACTIVITY_MEMORY_ID))
+    );
+    
+    static PAYOUTS: RefCell<StableBTreeMap<u64, PayoutRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(PAYOUTS_MEMORY_ID))
+    );
+    
+    // What each recipient's payout streams still entitle them to; `transfer` pays no more
+    static STREAM_ALLOWANCES: RefCell<StableBTreeMap<Principal, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(STREAM_ALLOWANCES_MEMORY_ID))
+    );
+    
+    // Heap only: locks and journal entries held by calls in flight. Upgrades stop the canister
+    // first, so nothing is in flight across one.
+    static LOCKS: RefCell<HashSet<LockKey>> = RefCell::new(HashSet::new());
+    static OPERATIONS_IN_FLIGHT: RefCell<HashSet<u64>> = RefCell::new(HashSet::new());
 }
 
-#[update]
-async fn invest(amount: u64) -> InvestmentResult {
-    let caller = msg_caller();
-    
-    VAULT_STATE.with(|state_ref| {
-        let mut state_opt = state_ref.borrow_mut();
-        if let Some(ref mut state) = *state_opt {
-           
-            if state.current_funding >= state.funding_goal {
-                return InvestmentResult {
-                    success: false,
-                    nft_token_id: None,
-                    share_percentage: 0.0,
-                    message: "Campaign already fully funded".to_string(),
-                };
-            }
-           
-            let remaining_funding = state.funding_goal - state.current_funding;
-            let actual_investment = amount.min(remaining_funding);
-            
-            // Calculate insurance fee
-            let insurance_fee = (actual_investment * state.insurance_fee_percentage as u64) / 100;
-            let investment_after_fee = actual_investment - insurance_fee;
-            
-            // Add to insurance pool
-            state.insurance_pool_balance += insurance_fee;
-            
-            // Calculate share percentage based on investment after fee
-            let share_percentage = (investment_after_fee as f64 / state.funding_goal as f64) * 100.0;
-            
-            // Update total funding with investment after fee
-            state.current_funding += investment_after_fee;
-            
-            let backer_info = BackerInfo {
-                amount_invested: actual_investment, // Track full amount including insurance fee
-                nft_token_id: None,
-                share_percentage,
-                total_claimed: 0,
-                investment_timestamp: time(),
-            };
-            
-            state.backers.insert(caller, backer_info.clone());
-            
-            InvestmentResult {
-                success: true,
-                nft_token_id: None, 
-                share_percentage,
-                message: format!(
-                    "Investment successful: {} contributed ({} to campaign, {} to insurance pool)", 
-                    actual_investment, 
-                    investment_after_fee, 
-                    insurance_fee
-                ),
-            }
-        } else {
-            InvestmentResult {
-                success: false,
-                nft_token_id: None,
-                share_percentage: 0.0,
-                message: "Vault not initialized".to_string(),
-            }
-        }
-    })
+fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|manager| manager.get(id))
 }
 
-#[update]
-async fn mint_nft_for_backer(backer: Principal) -> Result<u64, String> {
-   
-    let backer_info = VAULT_STATE.with(|state_ref| {
-        let state_opt = state_ref.borrow();
-        if let Some(ref state) = *state_opt {
-            state.backers.get(&backer).cloned()
-        } else {
-            None
-        }
-    });
-    
-    if let Some(info) = backer_info {
-        
-        if let Some(nft_registry) = get_nft_registry_canister() {
-            let metadata = format!(
-                "{{\"campaign_id\":{},\"investment\":{},\"share\":{:.2}}}",
-                get_campaign_id(),
-                info.amount_invested,
-                info.share_percentage
-            );
-            
-            let result: CallResult<(Result<u64, String>,)> = call(
-                nft_registry,
-                "mint",
-                (backer, metadata),
-            ).await;
-            
-            match result {
-                Ok((Ok(token_id),)) => {
-            
-                    VAULT_STATE.with(|state_ref| {
-                        let mut state_opt = state_ref.borrow_mut();
-                        if let Some(ref mut state) = *state_opt {
-                            if let Some(ref mut backer_info) = state.backers.get_mut(&backer) {
-                                backer_info.nft_token_id = Some(token_id);
-                            }
-                        }
-                    });
-                    Ok(token_id)
-                },
-                Ok((Err(e),)) => Err(e),
-                Err(e) => Err(format!("Failed to call NFT registry: {:?}", e)),
-            }
-        } else {
-            Err("NFT registry not configured".to_string())
-        }
-    } else {
-        Err("Backer not found".to_string())
-    }
+fn read_config<R>(f: impl FnOnce(&VaultConfig) -> R) -> Result<R, String> {
+    CONFIG.with(|cell| {
+        cell.borrow().get().as_ref().map(f).ok_or_else(|| "Vault not initialized".to_string())
+    })
 }
 
-#[update]
-fn update_revenue(amount: u64, source: String, verified: bool) -> Result<(), String> {
-    
-    let caller = msg_caller();
-    
-    VAULT_STATE.with(|state_ref| {
-        let mut state_opt = state_ref.borrow_mut();
-        if let Some(ref mut state) = *state_opt {
-            let source_clone = source.clone();
-            
-            let revenue_update = RevenueUpdate {
-                amount,
-                source,
-                timestamp: time(),
-                oracle_verification: verified,
-            };
-            
-            state.total_revenue += amount;
-            state.revenue_history.push(revenue_update);
-            
-            ic_cdk::println!("Revenue updated: {} from {}", amount, source_clone);
-            Ok(())
-        } else {
-            Err("Vault not initialized".to_string())
-        }
+// Applies `f` to a copy of the config and persists it only if `f` succeeds
+fn mutate_config<R>(f: impl FnOnce(&mut VaultConfig) -> Result<R, String>) -> Result<R, String> {
+    CONFIG.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let mut config = cell.get().clone().ok_or_else(|| "Vault not initialized".to_string())?;
+        let result = f(&mut config)?;
+        cell.set(Some(config)).expect("failed to persist vault config");
+        Ok(result)
     })
 }
 
-#[update]
-async fn distribute_payouts() -> Result<Vec<(Principal, u64)>, String> {
-    let mut payouts = Vec::new();
-    let mut creator_slashed = false;
-    let mut creator_slash_percentage = 0;
-    
-    VAULT_STATE.with(|state_ref| {
-        let state_opt = state_ref.borrow();
-        if let Some(ref state) = *state_opt {
-            // Check if creator has been slashed
-            if !state.slashed_creators.is_empty() {
-                creator_slashed = true;
-               
-                creator_slash_percentage = 50;
-            }
-            
-            // Calculate the investor share from revenue
-            let mut investor_share = (state.total_revenue * state.revenue_share_percentage as u64) / 100;
-            
-            // If creator was slashed, add that portion to investor share
-            if creator_slashed {
-                let creator_share = (state.total_revenue * (100 - state.revenue_share_percentage) as u64) / 100;
-                let slashed_amount = (creator_share * creator_slash_percentage) / 100;
-                investor_share += slashed_amount;
-            }
-            
-            // Distribute to backers according to their share percentage
-            for (backer, info) in &state.backers {
-                let backer_share = (investor_share as f64 * info.share_percentage / 100.0) as u64;
-                let claimable = backer_share - info.total_claimed;
-                
-                if claimable > 0 {
-                    payouts.push((*backer, claimable));
-                }
-            }
-            
-            // If we have any approved insurance claims that haven't been paid yet, add those
-            for claim in &state.insurance_claims {
-                if matches!(claim.status, ClaimStatus::Approved) {
-                    // Check if this backer is already getting a payout
-                    let existing_payout = payouts.iter_mut()
-                        .find(|(principal, _)| *principal == claim.claimer);
-                    
-                    if let Some((_, amount)) = existing_payout {
-                        // Add to existing payout
-                        *amount += claim.amount;
-                    } else {
-                        // Create new payout
-                        payouts.push((claim.claimer, claim.amount));
-                    }
-                }
-            }
-        }
+fn record_transfer(transfer: LedgerTransfer) {
+    LEDGER_TRANSFERS.with(|log| {
+        log.borrow().append(&transfer).expect("failed to append ledger transfer");
     });
-    
-    
-    if let Some(stream_canister) = get_stream_canister() {
-        let result: CallResult<(Result<(), String>,)> = call(
-            stream_canister,
-            "create_streams",
-            (payouts.clone(),),
-        ).await;
-        
-        match result {
-            Ok((Ok(()),)) => {
-                
-                VAULT_STATE.with(|state_ref| {
-                    let mut state_opt = state_ref.borrow_mut();
-                    if let Some(ref mut state) = *state_opt {
-                        for (backer, amount) in &payouts {
-                            if let Some(ref mut info) = state.backers.get_mut(backer) {
-                                info.total_claimed += amount;
-                            }
-                        }
-                    }
-                });
-                Ok(payouts)
-            },
-            Ok((Err(e),)) => Err(e),
-            Err(e) => Err(format!("Failed to create streams: {:?}", e)),
+}
+
+// What an async flow locks while it awaits. One principal's investments, refunds and payout
+// claims run one at a time, as do mints of one tranche and epoch closes.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum LockKey {
+    Backer(Principal),
+    Tranche(u64),
+    EpochClose,
+}
+
+impl OperationKind {
+    fn lock_key(&self) -> Option<LockKey> {
+        match self {
+            OperationKind::Invest { backer, .. } | OperationKind::Refund { backer, .. } => Some(LockKey::Backer(*backer)),
+            OperationKind::EpochPayout { holder, .. } => Some(LockKey::Backer(*holder)),
+            OperationKind::InsurancePayout { claimer, .. } => Some(LockKey::Backer(*claimer)),
+            OperationKind::Mint { tranche_id } => Some(LockKey::Tranche(*tranche_id)),
+            OperationKind::CloseEpoch { .. } => Some(LockKey::EpochClose),
+            OperationKind::Payout { .. } => None,
         }
-    } else {
-        Err("Stream canister not configured".to_string())
     }
 }
 
+// Released on drop, which also happens when a callback traps and the call is cleaned up
+struct OperationLock(LockKey);
 
-fn get_campaign_id() -> u64 {
-    VAULT_STATE.with(|state_ref| {
-        state_ref.borrow().as_ref().map(|s| s.campaign_id).unwrap_or(0)
-    })
+impl OperationLock {
+    fn acquire(key: LockKey) -> Result<Self, String> {
+        LOCKS.with(|locks| {
+            if !locks.borrow_mut().insert(key) {
+                return Err(match key {
+                    LockKey::Backer(_) => "Another operation for this principal is in progress".to_string(),
+                    LockKey::Tranche(_) => "This tranche is already being minted".to_string(),
+                    LockKey::EpochClose => "An epoch is already being closed".to_string(),
+                });
+            }
+            Ok(OperationLock(key))
+        })
+    }
 }
 
-fn get_nft_registry_canister() -> Option<Principal> {
-    VAULT_STATE.with(|state_ref| {
-        state_ref.borrow().as_ref().and_then(|s| s.nft_registry_canister)
-    })
+impl Drop for OperationLock {
+    fn drop(&mut self) {
+        LOCKS.with(|locks| locks.borrow_mut().remove(&self.0));
+    }
 }
 
-fn get_stream_canister() -> Option<Principal> {
-    VAULT_STATE.with(|state_ref| {
-        state_ref.borrow().as_ref().and_then(|s| s.stream_canister)
-    })
+// A journal entry owned by a call in flight. `complete` removes the entry together with the
+// state writes that record the outcome. If the callback traps, those writes roll back, the
+// entry stays, and dropping the guard marks it as interrupted.
+struct JournalGuard(u64);
+
+impl JournalGuard {
+    fn complete(self) {
+        PENDING_OPERATIONS.with(|operations| operations.borrow_mut().remove(&self.0));
+    }
 }
 
-#[query]
-fn get_vault_state() -> Option<VaultState> {
-    VAULT_STATE.with(|state_ref| {
-        state_ref.borrow().clone()
-    })
+impl Drop for JournalGuard {
+    fn drop(&mut self) {
+        OPERATIONS_IN_FLIGHT.with(|in_flight| in_flight.borrow_mut().remove(&self.0));
+    }
 }
 
-#[query]
-fn get_backer_info(backer: Principal) -> Option<BackerInfo> {
-    VAULT_STATE.with(|state_ref| {
-        state_ref.borrow().as_ref().and_then(|s| s.backers.get(&backer).cloned())
-    })
+fn is_interrupted(op_id: u64) -> bool {
+    !OPERATIONS_IN_FLIGHT.with(|in_flight| in_flight.borrow().contains(&op_id))
 }
 
-#[query]
-fn get_funding_progress() -> (u64, u64, f64) {
-    VAULT_STATE.with(|state_ref| {
-        if let Some(ref state) = *state_ref.borrow() {
-            let percentage = (state.current_funding as f64 / state.funding_goal as f64) * 100.0;
-            (state.current_funding, state.funding_goal, percentage)
-        } else {
-            (0, 0, 0.0)
+// Journals `kind` before its inter-canister call. Refused while an interrupted operation on
+// the same lock is unreconciled, since its outcome could change what the new one should do.
+fn begin_operation(kind: OperationKind) -> Result<JournalGuard, String> {
+    let lock_key = kind.lock_key();
+    PENDING_OPERATIONS.with(|operations| {
+        let mut operations = operations.borrow_mut();
+        let blocked = lock_key.is_some() && operations.values().any(|operation| {
+            operation.kind.lock_key() == lock_key && is_interrupted(operation.op_id)
+        });
+        if blocked {
+            return Err("An interrupted operation must be settled with reconcile_pending_operations first".to_string());
         }
+        
+        let op_id = operations.last_key_value().map_or(1, |(op_id, _)| op_id + 1);
+        operations.insert(op_id, PendingOperation {
+            op_id,
+            kind,
+            started_at: time(),
+            reconcile_attempts: 0,
+            last_error: None,
+        });
+        OPERATIONS_IN_FLIGHT.with(|in_flight| in_flight.borrow_mut().insert(op_id));
+        Ok(JournalGuard(op_id))
     })
 }
 
-// Insurance-related functions
+// Funding promised to investments whose transfer hasn't been recorded yet
+// Funding that investments in flight will add once they land, after the insurance fee
+fn reserved_funding(fee_percentage: u8) -> u64 {
+    PENDING_OPERATIONS.with(|operations| {
+        operations.borrow().values()
+            .filter_map(|operation| match operation.kind {
+                OperationKind::Invest { amount, .. } => Some(net_of_fee(amount, fee_percentage)),
+                _ => None,
+            })
+            .sum()
+    })
+}
 
-#[query]
-fn get_insurance_pool_info() -> (u64, u8, u8) {
-    VAULT_STATE.with(|state_ref| {
-        if let Some(ref state) = *state_ref.borrow() {
-            (
-                state.insurance_pool_balance,
-                state.insurance_fee_percentage,
-                state.insurance_coverage_ratio
-            )
-        } else {
-            (0, 0, 0)
+// What an investment adds to the funding; the fee is rounded down as in `apply_investment`
+fn net_of_fee(amount: u64, fee_percentage: u8) -> u64 {
+    amount - amount * fee_percentage as u64 / 100
+}
+
+// Smallest investment whose amount after the fee covers `net`
+fn gross_for_net(net: u64, fee_percentage: u8) -> u64 {
+    if net == 0 {
+        return 0;
+    }
+    let fee_percentage = fee_percentage.min(99) as u128;
+    let gross = (net as u128 - 1) * 100 / (100 - fee_percentage) + 1;
+    gross.min(u64::MAX as u128) as u64
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    backfill_tranches();
+    backfill_share_units();
+    backfill_tranche_payouts();
+    queue_unminted_tranches();
+    open_legacy_epoch();
+    backfill_stream_allowances();
+}
+
+// Allowances start from the payout streams already opened, less what `transfer` already paid.
+// Entries are kept at zero once used up, so this only runs on the first upgrade.
+fn backfill_stream_allowances() {
+    if !STREAM_ALLOWANCES.with(|allowances| allowances.borrow().is_empty()) {
+        return;
+    }
+    
+    let mut allowances: HashMap<Principal, u64> = HashMap::new();
+    ENTITLEMENTS.with(|stored| {
+        for entitlement in stored.borrow().values() {
+            if matches!(entitlement.status, EntitlementStatus::Claimed { .. }) {
+                *allowances.entry(entitlement.holder).or_default() += entitlement.amount;
+            }
+        }
+    });
+    INSURANCE_CLAIMS.with(|claims| {
+        for claim in claims.borrow().values() {
+            if matches!(claim.status, ClaimStatus::Paid) {
+                *allowances.entry(claim.claimer).or_default() += claim.amount;
+            }
+        }
+    });
+    LEDGER_TRANSFERS.with(|log| {
+        for transfer in log.borrow().iter() {
+            if transfer.kind == TransferKind::Payout {
+                let allowance = allowances.entry(transfer.counterparty).or_default();
+                *allowance = allowance.saturating_sub(transfer.amount);
+            }
+        }
+    });
+    
+    STREAM_ALLOWANCES.with(|stored| {
+        let mut stored = stored.borrow_mut();
+        for (recipient, allowance) in allowances {
+            stored.insert(recipient, allowance);
+        }
+    });
+}
+
+// Revenue recorded before epochs existed goes into the first epoch. What the tranches were
+// already paid is deducted when it closes.
+fn open_legacy_epoch() {
+    let total_revenue = read_config(|config| config.total_revenue).unwrap_or(0);
+    if total_revenue == 0 || !EPOCHS.with(|epochs| epochs.borrow().is_empty()) {
+        return;
+    }
+    
+    EPOCHS.with(|epochs| {
+        epochs.borrow_mut().insert(1, DistributionEpoch {
+            includes_legacy_revenue: true,
+            revenue: total_revenue,
+            ..new_epoch(1)
+        });
+    });
+}
+
+// Payouts used to be tracked per backer; split what each backer was already paid across their
+// tranches by share units so nothing is paid twice once payouts follow the tranche tokens
+fn backfill_tranche_payouts() {
+    let backfill: Vec<Tranche> = TRANCHES.with(|tranches| {
+        tranches.borrow().values().filter(|tranche| tranche.paid_out.is_none()).collect()
+    });
+    
+    let mut by_backer: HashMap<Principal, Vec<Tranche>> = HashMap::new();
+    for tranche in backfill {
+        by_backer.entry(tranche.backer).or_default().push(tranche);
+    }
+    
+    TRANCHES.with(|tranches| {
+        let mut tranches = tranches.borrow_mut();
+        for (backer, backer_tranches) in by_backer {
+            let claimed = BACKERS.with(|backers| backers.borrow().get(&backer)).map_or(0, |info| info.total_claimed);
+            let units: u128 = backer_tranches.iter().map(|tranche| tranche.share_units.unwrap_or(0) as u128).sum();
+            let mut remaining = claimed;
+            let count = backer_tranches.len();
+            for (index, mut tranche) in backer_tranches.into_iter().enumerate() {
+                let paid = if index + 1 == count {
+                    remaining
+                } else {
+                    (claimed as u128 * tranche.share_units.unwrap_or(0) as u128 / units.max(1)) as u64
+                };
+                remaining -= paid;
+                tranche.paid_out = Some(paid);
+                tranches.insert(tranche.tranche_id, tranche);
+            }
+        }
+    });
+}
+
+// Tranches recorded before minting moved into `invest` are picked up by `retry_pending_mints`
+fn queue_unminted_tranches() {
+    let unminted: Vec<u64> = TRANCHES.with(|tranches| {
+        tranches.borrow().iter()
+            .filter(|(_, tranche)| tranche.nft_token_id.is_none())
+            .map(|(tranche_id, _)| tranche_id)
+            .collect()
+    });
+    let now = time();
+    PENDING_MINTS.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        for tranche_id in unminted {
+            if !pending.contains_key(&tranche_id) {
+                pending.insert(tranche_id, PendingMint {
+                    tranche_id,
+                    attempts: 0,
+                    last_error: "Recorded before automatic minting".to_string(),
+                    queued_at: now,
+                    last_attempt_at: now,
+                });
+            }
+        }
+    });
+}
+
+// Vaults that recorded one investment per backer get one tranche per existing position
+fn backfill_tranches() {
+    let needs_backfill = TRANCHES.with(|tranches| tranches.borrow().is_empty())
+        && !BACKERS.with(|backers| backers.borrow().is_empty());
+    if !needs_backfill {
+        return;
+    }
+    
+    let fee_percentage = read_config(|config| config.insurance_fee_percentage as u64).unwrap_or(0);
+    BACKERS.with(|backers| {
+        TRANCHES.with(|tranches| {
+            let mut tranches = tranches.borrow_mut();
+            for (tranche_id, (backer, info)) in (1..).zip(backers.borrow().iter()) {
+                tranches.insert(tranche_id, Tranche {
+                    tranche_id,
+                    backer,
+                    amount_invested: info.amount_invested,
+                    insurance_fee: info.amount_invested * fee_percentage / 100,
+                    share_percentage: info.share_percentage,
+                    block_index: None,
+                    invested_at: info.investment_timestamp,
+                    nft_token_id: info.nft_token_id,
+                    share_units: None,
+                    paid_out: None,
+                });
+            }
+        });
+    });
+}
+
+// Positions recorded with f64 percentages get integer units derived from the amounts invested.
+// A backer's units are always the sum of their tranches' units.
+fn backfill_share_units() {
+    let Ok(funding_goal) = read_config(|config| config.funding_goal) else {
+        return;
+    };
+    
+    let mut units_by_backer: HashMap<Principal, u64> = HashMap::new();
+    TRANCHES.with(|tranches| {
+        let mut tranches = tranches.borrow_mut();
+        let all: Vec<Tranche> = tranches.iter().map(|(_, tranche)| tranche).collect();
+        for mut tranche in all {
+            let units = match tranche.share_units {
+                Some(units) => units,
+                None => {
+                    let units = share_units_for(tranche.amount_invested - tranche.insurance_fee, funding_goal);
+                    tranche.share_units = Some(units);
+                    tranches.insert(tranche.tranche_id, tranche.clone());
+                    units
+                }
+            };
+            *units_by_backer.entry(tranche.backer).or_default() += units;
+        }
+    });
+    
+    BACKERS.with(|backers| {
+        let mut backers = backers.borrow_mut();
+        for (backer, units) in units_by_backer {
+            if let Some(mut info) = backers.get(&backer).filter(|info| info.share_units.is_none()) {
+                info.share_units = Some(units);
+                info.share_percentage = units_to_percentage(units);
+                backers.insert(backer, info);
+            }
+        }
+    });
+}
+
+#[init]
+fn init(args: VaultInitArgs) {
+    if let Err(e) = validate_init_args(&args) {
+        ic_cdk::trap(format!("Invalid vault init args: {}", e));
+    }
+    
+    let insurance = args.insurance.unwrap_or_else(default_insurance_settings);
+    let metadata = args.metadata;
+    
+    let config = VaultConfig {
+        campaign_id: args.campaign_id,
+        creator: metadata.creator,
+        title: metadata.title,
+        funding_goal: metadata.funding_goal,
+        current_funding: 0,
+        revenue_share_percentage: metadata.revenue_share_percentage,
+        total_revenue: 0,
+        oracle_endpoints: metadata.oracle_endpoints,
+        nft_registry_canister: args.nft_registry_canister,
+        stream_canister: args.stream_canister,
+        oracle_canister: args.oracle_canister,
+        ledger_canister: args.ledger_canister,
+        factory_canister: Some(msg_caller()),
+        funding_deadline: metadata.funding_deadline,
+        refunds_opened_at: None,
+        awaiting_launch: args.awaiting_launch,
+        payout_stream: args.payout_stream,
+        reporter_limits: None,
+        source_limits: None,
+        created_at: time(),
+        insurance_pool_balance: 0,
+        insurance_fee_percentage: insurance.fee_percentage,
+        insurance_coverage_ratio: insurance.coverage_ratio,
+        slashing_conditions: insurance.slashing_conditions,
+    };
+    
+    CONFIG.with(|cell| {
+        cell.borrow_mut().set(Some(config)).expect("failed to persist vault config");
+    });
+    
+    ic_cdk::println!("Vault initialized for campaign {}", args.campaign_id);
+}
+
+fn default_insurance_settings() -> InsuranceSettings {
+    InsuranceSettings {
+        fee_percentage: 2, // Default 2% insurance fee
+        coverage_ratio: 80, // Default 80% coverage of investment
+        slashing_conditions: SlashingConditions {
+            missed_revenue_reports_threshold: 3, // 3 missed reports
+            revenue_decline_threshold_percentage: 70, // 70% decline triggers review
+            minimum_active_period_days: 30, // Must be active for 30 days
+            governance_votes_required: 51, // 51% votes required for slashing
+        },
+    }
+}
+
+fn validate_init_args(args: &VaultInitArgs) -> Result<(), String> {
+    let metadata = &args.metadata;
+    
+    if metadata.creator == Principal::anonymous() {
+        return Err("creator cannot be the anonymous principal".to_string());
+    }
+    if metadata.title.trim().is_empty() {
+        return Err("title cannot be empty".to_string());
+    }
+    if metadata.funding_goal == 0 {
+        return Err("funding goal must be greater than 0".to_string());
+    }
+    if metadata.revenue_share_percentage == 0 || metadata.revenue_share_percentage > 100 {
+        return Err("revenue share must be between 1-100%".to_string());
+    }
+    
+    if let Some(insurance) = &args.insurance {
+        if insurance.fee_percentage > 20 {
+            return Err("insurance fee cannot exceed 20%".to_string());
+        }
+        if insurance.coverage_ratio > 100 {
+            return Err("coverage ratio cannot exceed 100%".to_string());
+        }
+    }
+    
+    if args.payout_stream.as_ref().is_some_and(|stream| stream.duration_seconds == 0) {
+        return Err("payout stream duration must be greater than 0".to_string());
+    }
+    
+    Ok(())
+}
+
+#[update]
+async fn invest(amount: u64) -> InvestmentResult {
+    let caller = msg_caller();
+    let lock = match OperationLock::acquire(LockKey::Backer(caller)) {
+        Ok(lock) => lock,
+        Err(message) => return failed_investment(message),
+    };
+    
+    // Work out how much can be accepted before any funds move. Investments still in flight
+    // hold their amount, so concurrent investors can't overfund the campaign.
+    let prepared = read_config(|config| {
+        if config.awaiting_launch == Some(true) {
+            return Err("Campaign has not been launched yet".to_string());
+        }
+        
+        if config.refunds_opened_at.is_some() {
+            return Err("Campaign is refunding and no longer accepts investments".to_string());
+        }
+        
+        if config.funding_deadline.is_some_and(|deadline| time() >= deadline) {
+            return Err("Funding deadline has passed".to_string());
+        }
+        
+        if config.current_funding >= config.funding_goal {
+            return Err("Campaign already fully funded".to_string());
+        }
+        
+        let ledger = config.ledger_canister
+            .ok_or_else(|| "Ledger canister not configured".to_string())?;
+        
+        let reserved = reserved_funding(config.insurance_fee_percentage);
+        let remaining_funding = (config.funding_goal - config.current_funding).saturating_sub(reserved);
+        if remaining_funding == 0 {
+            return Err("Remaining funding is reserved by investments in progress".to_string());
+        }
+        // Funding counts amounts after the insurance fee, so clamp on the gross that fills the gap
+        let needed = gross_for_net(remaining_funding, config.insurance_fee_percentage);
+        Ok((ledger, config.campaign_id, amount.min(needed)))
+    }).and_then(|prepared| prepared);
+    
+    let (ledger, campaign_id, actual_investment) = match prepared {
+        Ok(prepared) => prepared,
+        Err(message) => return failed_investment(message),
+    };
+    
+    if actual_investment == 0 {
+        return failed_investment("Investment amount must be greater than 0".to_string());
+    }
+    
+    let created_at_time = time();
+    let journal = match begin_operation(OperationKind::Invest { backer: caller, amount: actual_investment, created_at_time }) {
+        Ok(journal) => journal,
+        Err(message) => return failed_investment(message),
+    };
+    
+    // Pull the funds into the campaign subaccount; the backer must have approved the vault first
+    let transfer_args = investment_transfer_args(caller, actual_investment, campaign_id, created_at_time);
+    // Only a rejection from the ledger proves nothing moved. Any other failure leaves the
+    // operation interrupted, for reconciliation to look up in the ledger's history.
+    let block_index = match call_icrc2_transfer_from(ledger, transfer_args).await {
+        Ok(Ok(block_index)) => nat_to_u64(block_index),
+        Ok(Err(e)) => {
+            journal.complete();
+            return failed_investment(format!("Investment transfer failed: Ledger rejected transfer_from: {:?}", e));
+        }
+        Err(e) => Err(e),
+    };
+    let block_index = match block_index {
+        Ok(block_index) => block_index,
+        Err(e) => return failed_investment(format!(
+            "Investment outcome unknown ({}); it is settled by reconcile_pending_operations", e
+        )),
+    };
+    
+    let applied = apply_investment(caller, actual_investment, block_index);
+    journal.complete();
+    drop(lock);
+    
+    let applied = match applied {
+        Ok(applied) => applied,
+        Err(message) => return failed_investment(message),
+    };
+    
+    // The investment stands even if the registry can't be reached; the tranche is queued for a retry
+    let nft_token_id = match mint_nft_for_tranche(applied.tranche_id).await {
+        Ok(token_id) => Some(token_id),
+        Err(e) => {
+            queue_pending_mint(applied.tranche_id, e);
+            None
+        }
+    };
+    
+    // The investment stands even if the factory can't be reached; it can be re-sent later
+    if applied.goal_reached {
+        if let Err(e) = report_funding_goal_reached().await {
+            ic_cdk::println!("Failed to notify factory of funded campaign {}: {}", campaign_id, e);
+        }
+    }
+    
+    InvestmentResult {
+        success: true,
+        nft_token_id,
+        share_percentage: units_to_percentage(applied.share_units),
+        block_index: Some(block_index),
+        message: format!(
+            "Investment successful: {} contributed ({} to campaign, {} to insurance pool)", 
+            actual_investment, 
+            actual_investment - applied.insurance_fee, 
+            applied.insurance_fee
+        ),
+        tranche_id: Some(applied.tranche_id),
+        share_units: Some(applied.share_units),
+    }
+}
+
+fn investment_transfer_args(backer: Principal, amount: u64, campaign_id: u64, created_at_time: u64) -> TransferFromArgs {
+    TransferFromArgs {
+        spender_subaccount: None,
+        from: Account { owner: backer, subaccount: None },
+        to: vault_account(campaign_id),
+        amount: Nat::from(amount),
+        fee: None,
+        memo: Some(campaign_id.to_be_bytes().to_vec()),
+        created_at_time: Some(created_at_time), // Lets reconciliation find the block in the ledger's history
+    }
+}
+
+struct AppliedInvestment {
+    tranche_id: u64,
+    insurance_fee: u64,
+    share_units: u64,
+    goal_reached: bool,
+}
+
+// Records a deposit that has reached the vault as a new tranche of the backer's position
+fn apply_investment(backer: Principal, amount: u64, block_index: u64) -> Result<AppliedInvestment, String> {
+    // The funds have moved, so record the deposit before touching the rest of the state
+    record_transfer(LedgerTransfer {
+        kind: TransferKind::Deposit,
+        counterparty: backer,
+        amount,
+        block_index,
+        timestamp: time(),
+    });
+    
+    let (insurance_fee, share_units, goal_reached) = mutate_config(|config| {
+        // Calculate insurance fee
+        let insurance_fee = (amount * config.insurance_fee_percentage as u64) / 100;
+        let investment_after_fee = amount - insurance_fee;
+        
+        // Add to insurance pool
+        config.insurance_pool_balance += insurance_fee;
+        
+        // Shares are based on the investment after fee
+        let share_units = share_units_for(investment_after_fee, config.funding_goal);
+        
+        // Update total funding with investment after fee
+        config.current_funding += investment_after_fee;
+        let goal_reached = config.current_funding >= config.funding_goal;
+        
+        Ok((insurance_fee, share_units, goal_reached))
+    })?;
+    
+    let share_percentage = units_to_percentage(share_units);
+    let now = time();
+    let tranche_id = TRANCHES.with(|tranches| {
+        let mut tranches = tranches.borrow_mut();
+        let tranche_id = tranches.last_key_value().map_or(1, |(id, _)| id + 1);
+        tranches.insert(tranche_id, Tranche {
+            tranche_id,
+            backer,
+            amount_invested: amount,
+            insurance_fee,
+            share_percentage,
+            block_index: Some(block_index),
+            invested_at: now,
+            nft_token_id: None,
+            share_units: Some(share_units),
+            paid_out: Some(0),
+        });
+        tranche_id
+    });
+    
+    // Later investments add to the position instead of replacing it
+    BACKERS.with(|backers| {
+        let mut backers = backers.borrow_mut();
+        let position = match backers.get(&backer) {
+            Some(mut position) => {
+                position.amount_invested += amount; // Track full amount including insurance fee
+                let units = position.share_units.unwrap_or(0) + share_units;
+                position.share_units = Some(units);
+                position.share_percentage = units_to_percentage(units);
+                position.last_investment_at = Some(now);
+                position.tranche_count = Some(position.tranche_count.unwrap_or(1) + 1);
+                position
+            }
+            None => BackerInfo {
+                amount_invested: amount,
+                nft_token_id: None,
+                share_percentage,
+                total_claimed: 0,
+                investment_timestamp: now,
+                refunded_at: None,
+                last_investment_at: Some(now),
+                tranche_count: Some(1),
+                share_units: Some(share_units),
+            },
+        };
+        backers.insert(backer, position);
+    });
+    
+    Ok(AppliedInvestment { tranche_id, insurance_fee, share_units, goal_reached })
+}
+
+// Tells the factory the funding goal was reached so it can mark the campaign Funded.
+// Anyone may call this to re-send a notification that failed during `invest`.
+#[update]
+async fn report_funding_goal_reached() -> Result<(), String> {
+    let (factory, campaign_id) = read_config(|config| {
+        if config.current_funding < config.funding_goal {
+            return Err("Funding goal has not been reached".to_string());
+        }
+        let factory = config.factory_canister
+            .ok_or_else(|| "Factory canister not known".to_string())?;
+        Ok((factory, config.campaign_id))
+    })??;
+    
+    let response = Call::unbounded_wait(factory, "notify_funding_goal_reached")
+        .with_arg(campaign_id)
+        .await
+        .map_err(|e| format!("Factory call failed: {:?}", e))?;
+    
+    response.candid::<Result<(), String>>()
+        .map_err(|e| format!("Failed to decode factory response: {:?}", e))?
+}
+
+fn failed_investment(message: String) -> InvestmentResult {
+    InvestmentResult {
+        success: false,
+        nft_token_id: None,
+        share_percentage: 0.0,
+        block_index: None,
+        message,
+        tranche_id: None,
+        share_units: None,
+    }
+}
+
+// Pays out campaign funds held by the vault; called by the stream canister when a stream is claimed.
+// The ledger fee comes out of `amount`, and `amount` is charged against what the vault's payout
+// streams to `to` still entitle them to. With `payout_ref` the outcome is kept for `get_payout`,
+// so the stream canister can learn it even when this call fails after the ledger has already paid.
+#[update]
+async fn transfer(to: Principal, amount: u64, payout_ref: Option<u64>) -> Result<u64, String> {
+    let caller = msg_caller();
+    
+    let (ledger, campaign_id) = read_config(|config| {
+        if config.stream_canister != Some(caller) {
+            return Err("Only the stream canister can transfer funds".to_string());
+        }
+        
+        let ledger = config.ledger_canister
+            .ok_or_else(|| "Ledger canister not configured".to_string())?;
+        Ok((ledger, config.campaign_id))
+    })??;
+    
+    if amount == 0 {
+        return Err("Transfer amount must be greater than 0".to_string());
+    }
+    
+    let fee = icrc1_fee(ledger).await?;
+    let net_amount = amount.saturating_sub(fee);
+    if net_amount == 0 {
+        return Err("Transfer amount does not cover the ledger fee".to_string());
+    }
+    // Checked after the await, so a concurrent call with the same reference can't slip past
+    if payout_ref.is_some_and(|payout_ref| PAYOUTS.with(|payouts| payouts.borrow().contains_key(&payout_ref))) {
+        return Err("Payout reference is already in use".to_string());
+    }
+    
+    reserve_stream_allowance(to, amount)?;
+    let created_at_time = time();
+    let journal = match begin_operation(OperationKind::Payout { to, amount: net_amount, created_at_time, payout_ref, fee: Some(fee) }) {
+        Ok(journal) => journal,
+        Err(e) => {
+            credit_stream_allowance(to, amount);
+            return Err(e);
+        }
+    };
+    if let Some(payout_ref) = payout_ref {
+        PAYOUTS.with(|payouts| payouts.borrow_mut().insert(payout_ref, PayoutRecord {
+            payout_ref,
+            to,
+            amount,
+            requested_at: created_at_time,
+            status: PayoutStatus::Pending,
+        }));
+    }
+    
+    let transfer_args = payout_transfer_args(to, net_amount, Some(fee), campaign_id, created_at_time);
+    // Only a rejection from the ledger proves nothing was paid. Any other failure leaves the
+    // payout pending and the operation interrupted, for reconciliation to look up.
+    let block_index = match call_icrc1_transfer(ledger, transfer_args).await? {
+        Ok(block_index) => nat_to_u64(block_index)?,
+        Err(e) => {
+            let reason = format!("Ledger rejected transfer: {:?}", e);
+            set_payout_status(payout_ref, PayoutStatus::Failed { reason: reason.clone() });
+            credit_stream_allowance(to, amount);
+            journal.complete();
+            return Err(reason);
+        }
+    };
+    
+    record_payout(to, net_amount, block_index, payout_ref);
+    journal.complete();
+    
+    Ok(block_index)
+}
+
+fn record_payout(to: Principal, amount: u64, block_index: u64, payout_ref: Option<u64>) {
+    record_transfer(LedgerTransfer {
+        kind: TransferKind::Payout,
+        counterparty: to,
+        amount,
+        block_index,
+        timestamp: time(),
+    });
+    set_payout_status(payout_ref, PayoutStatus::Paid { block_index });
+}
+
+fn set_payout_status(payout_ref: Option<u64>, status: PayoutStatus) {
+    let Some(payout_ref) = payout_ref else { return };
+    PAYOUTS.with(|payouts| {
+        let mut payouts = payouts.borrow_mut();
+        if let Some(mut payout) = payouts.get(&payout_ref) {
+            payout.status = status;
+            payouts.insert(payout_ref, payout);
+        }
+    });
+}
+
+#[query]
+fn get_payout(payout_ref: u64) -> Option<PayoutRecord> {
+    PAYOUTS.with(|payouts| payouts.borrow().get(&payout_ref))
+}
+
+// Streams opened for `recipient` raise what `transfer` may pay them, as do payouts the ledger
+// never applied
+fn credit_stream_allowance(recipient: Principal, amount: u64) {
+    STREAM_ALLOWANCES.with(|allowances| {
+        let mut allowances = allowances.borrow_mut();
+        let allowance = allowances.get(&recipient).unwrap_or(0);
+        allowances.insert(recipient, allowance.saturating_add(amount));
+    });
+}
+
+fn reserve_stream_allowance(recipient: Principal, amount: u64) -> Result<(), String> {
+    STREAM_ALLOWANCES.with(|allowances| {
+        let mut allowances = allowances.borrow_mut();
+        let allowance = allowances.get(&recipient).unwrap_or(0);
+        if amount > allowance {
+            return Err(format!(
+                "Transfer of {} exceeds the {} still streamed to {}",
+                amount, allowance, recipient.to_text()
+            ));
+        }
+        allowances.insert(recipient, allowance - amount);
+        Ok(())
+    })
+}
+
+fn payout_transfer_args(to: Principal, amount: u64, fee: Option<u64>, campaign_id: u64, created_at_time: u64) -> TransferArg {
+    TransferArg {
+        from_subaccount: Some(campaign_subaccount(campaign_id).to_vec()),
+        to: Account { owner: to, subaccount: None },
+        amount: Nat::from(amount),
+        fee: fee.map(Nat::from),
+        memo: Some(campaign_id.to_be_bytes().to_vec()),
+        created_at_time: Some(created_at_time),
+    }
+}
+
+// Called by the factory when the creator launches the campaign
+#[update]
+fn launch() -> Result<(), String> {
+    let caller = msg_caller();
+    
+    mutate_config(|config| {
+        if config.factory_canister != Some(caller) {
+            return Err("Only the campaign factory can launch the vault".to_string());
+        }
+        
+        config.awaiting_launch = None;
+        Ok(())
+    })
+}
+
+// Called by the factory while provisioning the campaign. The connector accepts
+// registrations from the vault itself, so the vault forwards them.
+#[update]
+async fn register_revenue_oracle(endpoints: Vec<ApiEndpoint>, update_frequency: u64) -> Result<(), String> {
+    let caller = msg_caller();
+    
+    let (campaign_id, oracle) = read_config(|config| {
+        if config.factory_canister != Some(caller) {
+            return Err("Only the campaign factory can register the revenue oracle".to_string());
+        }
+        
+        config
+            .oracle_canister
+            .map(|oracle| (config.campaign_id, oracle))
+            .ok_or_else(|| "Oracle canister not configured".to_string())
+    })??;
+    
+    let result: Result<(), String> = Call::unbounded_wait(oracle, "register_campaign_oracle")
+        .with_args(&(campaign_id, canister_self(), endpoints, update_frequency))
+        .await
+        .map_err(|e| format!("Failed to call oracle: {:?}", e))?
+        .candid()
+        .map_err(|e| format!("Failed to decode oracle response: {:?}", e))?;
+    
+    result
+}
+
+// Called by the factory when the creator edits the campaign. Terms that shape backer
+// shares are frozen once anyone has invested.
+#[update]
+fn update_campaign_metadata(update: VaultMetadataUpdate) -> Result<(), String> {
+    let caller = msg_caller();
+    
+    mutate_config(|config| {
+        if config.factory_canister != Some(caller) {
+            return Err("Only the campaign factory can update campaign metadata".to_string());
+        }
+        
+        let changes_terms = update.funding_goal.is_some() || update.revenue_share_percentage.is_some();
+        if changes_terms && config.current_funding > 0 {
+            return Err("Funding terms cannot change after investments were made".to_string());
+        }
+        
+        if let Some(title) = update.title {
+            if title.trim().is_empty() {
+                return Err("title cannot be empty".to_string());
+            }
+            config.title = title;
+        }
+        if let Some(funding_goal) = update.funding_goal {
+            if funding_goal == 0 {
+                return Err("funding goal must be greater than 0".to_string());
+            }
+            config.funding_goal = funding_goal;
+        }
+        if let Some(share) = update.revenue_share_percentage {
+            if share == 0 || share > 100 {
+                return Err("revenue share must be between 1-100%".to_string());
+            }
+            config.revenue_share_percentage = share;
+        }
+        if let Some(endpoints) = update.oracle_endpoints {
+            config.oracle_endpoints = endpoints;
+        }
+        if let Some(deadline) = update.funding_deadline {
+            config.funding_deadline = Some(deadline);
+        }
+        
+        Ok(())
+    })
+}
+
+// Called by the factory when the raise failed at its deadline or the campaign was cancelled
+#[update]
+fn open_refunds() -> Result<(), String> {
+    let caller = msg_caller();
+    
+    mutate_config(|config| {
+        if config.factory_canister != Some(caller) {
+            return Err("Only the campaign factory can open refunds".to_string());
+        }
+        
+        if config.refunds_opened_at.is_none() {
+            config.refunds_opened_at = Some(time());
+            ic_cdk::println!("Refunds opened for campaign {}", config.campaign_id);
+        }
+        
+        Ok(())
+    })
+}
+
+// Returns the caller's investment once refunds are open. The full amount invested,
+// including the insurance fee, is returned minus the ledger fee for the transfer.
+#[update]
+async fn claim_refund() -> Result<u64, String> {
+    let caller = msg_caller();
+    let _lock = OperationLock::acquire(LockKey::Backer(caller))?;
+    
+    let (ledger, campaign_id) = read_config(|config| {
+        if config.refunds_opened_at.is_none() {
+            return Err("Refunds are not open for this campaign".to_string());
+        }
+        
+        let ledger = config.ledger_canister
+            .ok_or_else(|| "Ledger canister not configured".to_string())?;
+        Ok((ledger, config.campaign_id))
+    })??;
+    
+    let mut info = BACKERS.with(|backers| backers.borrow().get(&caller))
+        .ok_or_else(|| "Caller is not a backer of this campaign".to_string())?;
+    
+    if info.refunded_at.is_some() {
+        return Err("Investment has already been refunded".to_string());
+    }
+    
+    // Mark the refund before the first await so a concurrent claim can't pay twice;
+    // every early return below rolls it back so the backer can try again
+    info.refunded_at = Some(time());
+    BACKERS.with(|backers| {
+        backers.borrow_mut().insert(caller, info.clone());
+    });
+    
+    let fee = match icrc1_fee(ledger).await {
+        Ok(fee) => fee,
+        Err(e) => {
+            reopen_refund(caller);
+            return Err(e);
+        }
+    };
+    let amount = info.amount_invested.saturating_sub(fee);
+    
+    if amount == 0 {
+        reopen_refund(caller);
+        return Err("Investment does not cover the ledger fee".to_string());
+    }
+    
+    let created_at_time = time();
+    let journal = match begin_operation(OperationKind::Refund { backer: caller, amount, fee, created_at_time }) {
+        Ok(journal) => journal,
+        Err(e) => {
+            reopen_refund(caller);
+            return Err(e);
+        }
+    };
+    
+    let transfer_args = refund_transfer_args(caller, amount, fee, campaign_id, created_at_time);
+    // As in `invest`: roll back only on a rejection from the ledger. Otherwise the refund stays
+    // claimed until reconciliation finds out from the ledger's history whether it was paid.
+    let block_index = match call_icrc1_transfer(ledger, transfer_args).await {
+        Ok(Ok(block_index)) => nat_to_u64(block_index),
+        Ok(Err(e)) => {
+            // Roll back so the backer can try again
+            reopen_refund(caller);
+            journal.complete();
+            return Err(format!("Ledger rejected transfer: {:?}", e));
+        }
+        Err(e) => Err(e),
+    };
+    let block_index = block_index.map_err(|e| {
+        format!("Refund outcome unknown ({}); it is settled by reconcile_pending_operations", e)
+    })?;
+    
+    record_transfer(LedgerTransfer {
+        kind: TransferKind::Refund,
+        counterparty: caller,
+        amount,
+        block_index,
+        timestamp: time(),
+    });
+    journal.complete();
+    
+    // The refund stands either way; `void_refunded_positions` retries the registry later
+    if let Err(e) = void_positions_of(caller).await {
+        ic_cdk::println!("Failed to void positions of {}: {}", caller.to_text(), e);
+    }
+    
+    Ok(block_index)
+}
+
+// Refunded backers no longer hold a position: their tranches get no revenue and no new tokens
+fn is_refunded(backer: Principal) -> bool {
+    BACKERS.with(|backers| backers.borrow().get(&backer)).is_some_and(|info| info.refunded_at.is_some())
+}
+
+// Marks the backer's position NFTs void in the registry so they stop trading
+async fn void_positions_of(backer: Principal) -> Result<(), String> {
+    let token_ids: Vec<u64> = TRANCHES.with(|tranches| {
+        tranches.borrow().values()
+            .filter(|tranche| tranche.backer == backer)
+            .filter_map(|tranche| tranche.nft_token_id)
+            .collect()
+    });
+    if token_ids.is_empty() {
+        return Ok(());
+    }
+    
+    let nft_registry = get_nft_registry_canister()
+        .ok_or_else(|| "NFT registry not configured".to_string())?;
+    Call::unbounded_wait(nft_registry, "void_positions")
+        .with_arg(token_ids)
+        .await
+        .map_err(|e| format!("NFT registry call failed: {:?}", e))?
+        .candid::<Result<(), String>>()
+        .map_err(|e| format!("Failed to decode registry response: {:?}", e))?
+}
+
+// Voids the tokens of every refunded backer. Safe to repeat; the registry ignores tokens it
+// has already voided.
+#[update]
+async fn void_refunded_positions() -> Result<u64, String> {
+    let refunded: Vec<Principal> = BACKERS.with(|backers| {
+        backers.borrow().iter()
+            .filter(|(_, info)| info.refunded_at.is_some())
+            .map(|(backer, _)| backer)
+            .collect()
+    });
+    
+    for backer in &refunded {
+        void_positions_of(*backer).await?;
+    }
+    Ok(refunded.len() as u64)
+}
+
+fn refund_transfer_args(backer: Principal, amount: u64, fee: u64, campaign_id: u64, created_at_time: u64) -> TransferArg {
+    TransferArg {
+        from_subaccount: Some(campaign_subaccount(campaign_id).to_vec()),
+        to: Account { owner: backer, subaccount: None },
+        amount: Nat::from(amount),
+        fee: Some(Nat::from(fee)),
+        memo: Some(campaign_id.to_be_bytes().to_vec()),
+        created_at_time: Some(created_at_time),
+    }
+}
+
+fn reopen_refund(backer: Principal) {
+    BACKERS.with(|backers| {
+        let mut backers = backers.borrow_mut();
+        if let Some(mut info) = backers.get(&backer) {
+            info.refunded_at = None;
+            backers.insert(backer, info);
+        }
+    });
+}
+
+// Funds for each campaign live in a dedicated subaccount derived from the campaign ID
+fn campaign_subaccount(campaign_id: u64) -> [u8; 32] {
+    let mut subaccount = [0u8; 32];
+    subaccount[24..].copy_from_slice(&campaign_id.to_be_bytes());
+    subaccount
+}
+
+fn vault_account(campaign_id: u64) -> Account {
+    Account {
+        owner: canister_self(),
+        subaccount: Some(campaign_subaccount(campaign_id).to_vec()),
+    }
+}
+
+async fn call_icrc2_transfer_from(ledger: Principal, args: TransferFromArgs) -> Result<Result<Nat, TransferFromError>, String> {
+    Call::unbounded_wait(ledger, "icrc2_transfer_from")
+        .with_arg(args)
+        .await
+        .map_err(|e| format!("Failed to call ledger: {:?}", e))?
+        .candid()
+        .map_err(|e| format!("Failed to decode ledger response: {:?}", e))
+}
+
+async fn call_icrc1_transfer(ledger: Principal, args: TransferArg) -> Result<Result<Nat, TransferError>, String> {
+    Call::unbounded_wait(ledger, "icrc1_transfer")
+        .with_arg(args)
+        .await
+        .map_err(|e| format!("Failed to call ledger: {:?}", e))?
+        .candid()
+        .map_err(|e| format!("Failed to decode ledger response: {:?}", e))
+}
+
+// Blocks fetched per `get_transactions` call while searching the ledger's history
+const HISTORY_PAGE_SIZE: u64 = 1_000;
+// Pages `reconcile_pending_operations` searches per operation; a transfer further back
+// than that is left to `resolve_pending_operation`
+const HISTORY_PAGE_BUDGET: u32 = 10;
+// Ledgers accept a `created_at_time` slightly ahead of their clock, so a block can carry an
+// earlier timestamp than the transfer it records. Searching stops this far before it.
+const LEDGER_CLOCK_DRIFT_NANOS: u64 = 5 * 60 * 1_000_000_000;
+
+// A journaled ledger transfer, as it appears in the ledger's history if it went through
+#[derive(Clone, Debug, PartialEq)]
+struct ExpectedTransfer {
+    from: Account,
+    to: Account,
+    spender: Option<Account>,
+    amount: u64,
+    memo: Option<Vec<u8>>,
+    created_at_time: u64,
+}
+
+// What the ledger's history says about a journaled transfer
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LedgerOutcome {
+    Completed(u64),
+    NotApplied,
+}
+
+impl ExpectedTransfer {
+    fn from_transfer_from(args: &TransferFromArgs, spender: Principal) -> Self {
+        ExpectedTransfer {
+            from: args.from.clone(),
+            to: args.to.clone(),
+            spender: Some(Account { owner: spender, subaccount: args.spender_subaccount.clone() }),
+            amount: nat_to_u64(args.amount.clone()).unwrap_or(u64::MAX),
+            memo: args.memo.clone(),
+            created_at_time: args.created_at_time.unwrap_or(0),
+        }
+    }
+    
+    fn from_transfer(args: &TransferArg, sender: Principal) -> Self {
+        ExpectedTransfer {
+            from: Account { owner: sender, subaccount: args.from_subaccount.clone() },
+            to: args.to.clone(),
+            spender: None,
+            amount: nat_to_u64(args.amount.clone()).unwrap_or(u64::MAX),
+            memo: args.memo.clone(),
+            created_at_time: args.created_at_time.unwrap_or(0),
+        }
+    }
+    
+    fn matches(&self, transaction: &LedgerTransaction) -> bool {
+        let Some(transfer) = &transaction.transfer else { return false };
+        same_account(&transfer.from, &self.from)
+            && same_account(&transfer.to, &self.to)
+            && match (&transfer.spender, &self.spender) {
+                (Some(recorded), Some(expected)) => same_account(recorded, expected),
+                (None, None) => true,
+                _ => false,
+            }
+            && transfer.amount == self.amount
+            && transfer.memo == self.memo
+            && transfer.created_at_time == Some(self.created_at_time)
+    }
+}
+
+// Ledgers treat a missing subaccount as the all-zero one
+fn same_account(a: &Account, b: &Account) -> bool {
+    let default_subaccount = [0u8; 32];
+    let subaccount = |account: &Account| account.subaccount.clone().unwrap_or_else(|| default_subaccount.to_vec());
+    a.owner == b.owner && subaccount(a) == subaccount(b)
+}
+
+// The ledger transfer a journaled operation made, if it made one
+fn journaled_transfer(kind: &OperationKind, campaign_id: u64) -> Option<ExpectedTransfer> {
+    let vault = canister_self();
+    match *kind {
+        OperationKind::Invest { backer, amount, created_at_time } => Some(ExpectedTransfer::from_transfer_from(
+            &investment_transfer_args(backer, amount, campaign_id, created_at_time),
+            vault,
+        )),
+        OperationKind::Refund { backer, amount, fee, created_at_time } => Some(ExpectedTransfer::from_transfer(
+            &refund_transfer_args(backer, amount, fee, campaign_id, created_at_time),
+            vault,
+        )),
+        OperationKind::Payout { to, amount, created_at_time, fee, .. } => Some(ExpectedTransfer::from_transfer(
+            &payout_transfer_args(to, amount, fee, campaign_id, created_at_time),
+            vault,
+        )),
+        _ => None,
+    }
+}
+
+// Searches the ledger's history backwards from its newest block. Blocks are in time order,
+// so reaching one older than the transfer's `created_at_time` proves it was never applied.
+// With a page budget the search gives up with an error instead of reading further back.
+async fn find_ledger_transfer(ledger: Principal, expected: &ExpectedTransfer, page_budget: Option<u32>) -> Result<LedgerOutcome, String> {
+    let oldest_possible = expected.created_at_time.saturating_sub(LEDGER_CLOCK_DRIFT_NANOS);
+    let mut end = nat_to_u64(get_transactions(ledger, 0, 0).await?.log_length)?;
+    let mut pages = 0;
+    
+    while end > 0 {
+        if page_budget.is_some_and(|budget| pages >= budget) {
+            return Err(format!(
+                "Transfer not found in the newest {} ledger blocks; settle it with resolve_pending_operation",
+                pages as u64 * HISTORY_PAGE_SIZE
+            ));
+        }
+        let start = end.saturating_sub(HISTORY_PAGE_SIZE);
+        for (block_index, transaction) in fetch_ledger_blocks(ledger, start, end - start).await?.iter().rev() {
+            if expected.matches(transaction) {
+                return Ok(LedgerOutcome::Completed(*block_index));
+            }
+            if transaction.timestamp < oldest_possible {
+                return Ok(LedgerOutcome::NotApplied);
+            }
+        }
+        end = start;
+        pages += 1;
+    }
+    Ok(LedgerOutcome::NotApplied)
+}
+
+// Blocks `start..start + length` in order, following the ledger to its archives as needed.
+// Fails unless every block in the range comes back.
+async fn fetch_ledger_blocks(ledger: Principal, start: u64, length: u64) -> Result<Vec<(u64, LedgerTransaction)>, String> {
+    let response = get_transactions(ledger, start, length).await?;
+    let mut blocks = Vec::new();
+    
+    for archived in response.archived_transactions {
+        let archived_start = nat_to_u64(archived.start.clone())?;
+        let range: TransactionRange = Call::unbounded_wait(archived.callback.0.principal, &archived.callback.0.method)
+            .with_arg(GetTransactionsRequest { start: archived.start, length: archived.length })
+            .await
+            .map_err(|e| format!("Failed to call ledger archive: {:?}", e))?
+            .candid()
+            .map_err(|e| format!("Failed to decode ledger archive response: {:?}", e))?;
+        blocks.extend((archived_start..).zip(range.transactions));
+    }
+    let first_index = nat_to_u64(response.first_index)?;
+    blocks.extend((first_index..).zip(response.transactions));
+    
+    blocks.retain(|(block_index, _)| (start..start + length).contains(block_index));
+    blocks.sort_by_key(|(block_index, _)| *block_index);
+    let complete = blocks.len() as u64 == length
+        && blocks.iter().zip(start..).all(|((block_index, _), expected)| *block_index == expected);
+    if !complete {
+        return Err(format!("Ledger returned incomplete history for blocks {}..{}", start, start + length));
+    }
+    Ok(blocks)
+}
+
+async fn get_transactions(ledger: Principal, start: u64, length: u64) -> Result<GetTransactionsResponse, String> {
+    Call::unbounded_wait(ledger, "get_transactions")
+        .with_arg(GetTransactionsRequest { start: Nat::from(start), length: Nat::from(length) })
+        .await
+        .map_err(|e| format!("Failed to call ledger: {:?}", e))?
+        .candid()
+        .map_err(|e| format!("Failed to decode ledger history: {:?}", e))
+}
+
+async fn icrc1_fee(ledger: Principal) -> Result<u64, String> {
+    let fee: Nat = Call::unbounded_wait(ledger, "icrc1_fee")
+        .await
+        .map_err(|e| format!("Failed to call ledger: {:?}", e))?
+        .candid()
+        .map_err(|e| format!("Failed to decode ledger response: {:?}", e))?;
+    
+    nat_to_u64(fee)
+}
+
+// Mints the position NFT for one tranche. Safe to repeat: a tranche that already has a token
+// returns it, and the registry returns the existing token for a tranche it has already minted.
+#[update]
+async fn mint_nft_for_tranche(tranche_id: u64) -> Result<u64, String> {
+    let tranche = TRANCHES.with(|tranches| tranches.borrow().get(&tranche_id))
+        .ok_or_else(|| "Tranche not found".to_string())?;
+    
+    if let Some(token_id) = tranche.nft_token_id {
+        return Ok(token_id);
+    }
+    if is_refunded(tranche.backer) {
+        return Err("Tranche was refunded; its position is void".to_string());
+    }
+    
+    let nft_registry = get_nft_registry_canister()
+        .ok_or_else(|| "NFT registry not configured".to_string())?;
+    let _lock = OperationLock::acquire(LockKey::Tranche(tranche_id))?;
+    let journal = begin_operation(OperationKind::Mint { tranche_id })?;
+    let campaign_id = get_campaign_id();
+    let request = MintRequest {
+        to: tranche.backer,
+        campaign_id,
+        vault_canister: canister_self(),
+        tranche_id,
+        investment_amount: tranche.amount_invested,
+        share_units: tranche.share_units.unwrap_or(0),
+        share_percentage: tranche.share_percentage,
+        metadata_json: format!(
+            "{{\"campaign_id\":{},\"tranche_id\":{},\"investment\":{},\"share\":{:.2}}}",
+            campaign_id,
+            tranche_id,
+            tranche.amount_invested,
+            tranche.share_percentage
+        ),
+    };
+    
+    let result = Call::unbounded_wait(nft_registry, "mint_position")
+        .with_arg(request)
+        .await
+        .map_err(|e| format!("Failed to call NFT registry: {:?}", e))
+        .and_then(|response| {
+            response.candid::<Result<u64, String>>()
+                .map_err(|e| format!("Failed to decode NFT registry response: {:?}", e))
+        });
+    let token_id = match result {
+        Ok(Ok(token_id)) => token_id,
+        Ok(Err(e)) | Err(e) => {
+            journal.complete();
+            return Err(e);
+        }
+    };
+    
+    TRANCHES.with(|tranches| {
+        let mut tranches = tranches.borrow_mut();
+        if let Some(mut tranche) = tranches.get(&tranche_id) {
+            tranche.nft_token_id = Some(token_id);
+            tranches.insert(tranche_id, tranche);
+        }
+    });
+    BACKERS.with(|backers| {
+        let mut backers = backers.borrow_mut();
+        if let Some(mut backer_info) = backers.get(&tranche.backer) {
+            backer_info.nft_token_id.get_or_insert(token_id);
+            backers.insert(tranche.backer, backer_info);
+        }
+    });
+    PENDING_MINTS.with(|pending| pending.borrow_mut().remove(&tranche_id));
+    journal.complete();
+    
+    Ok(token_id)
+}
+
+fn queue_pending_mint(tranche_id: u64, error: String) {
+    let now = time();
+    PENDING_MINTS.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        let entry = match pending.get(&tranche_id) {
+            Some(mut entry) => {
+                entry.attempts += 1;
+                entry.last_error = error;
+                entry.last_attempt_at = now;
+                entry
+            }
+            None => PendingMint {
+                tranche_id,
+                attempts: 1,
+                last_error: error,
+                queued_at: now,
+                last_attempt_at: now,
+            },
+        };
+        pending.insert(tranche_id, entry);
+    });
+}
+
+// Retries every queued mint. Anyone may call this; each tranche still mints at most one NFT.
+// Returns the tranches that now have a token.
+#[update]
+async fn retry_pending_mints() -> Vec<(u64, u64)> {
+    let queued: Vec<u64> = PENDING_MINTS.with(|pending| pending.borrow().keys().collect());
+    
+    let mut minted = Vec::new();
+    for tranche_id in queued {
+        // Refunded positions no longer get a token
+        let refunded = TRANCHES.with(|tranches| tranches.borrow().get(&tranche_id))
+            .is_some_and(|tranche| is_refunded(tranche.backer));
+        if refunded {
+            PENDING_MINTS.with(|pending| pending.borrow_mut().remove(&tranche_id));
+            continue;
+        }
+        
+        match mint_nft_for_tranche(tranche_id).await {
+            Ok(token_id) => minted.push((tranche_id, token_id)),
+            Err(e) => queue_pending_mint(tranche_id, e),
+        }
+    }
+    minted
+}
+
+#[query]
+fn get_pending_mints() -> Vec<PendingMint> {
+    PENDING_MINTS.with(|pending| pending.borrow().values().collect())
+}
+
+// Only the oracle canister reports verified revenue. The creator may attest revenue by hand,
+// which is always recorded as unverified; anyone else is rejected. Reports over the reporter's
+// limits, or over the limit the factory set for the reporter's `source`, are quarantined until
+// the factory reviews them.
+#[update]
+fn update_revenue(amount: u64, source: String, verified: bool) -> Result<(), String> {
+    let caller = msg_caller();
+    
+    let (is_oracle, limit, source_limit) = read_config(|config| {
+        let is_oracle = config.oracle_canister == Some(caller);
+        if !is_oracle && config.creator != caller {
+            return Err("Only the revenue oracle or the campaign creator can report revenue".to_string());
+        }
+        Ok((is_oracle, reporter_limit(config, caller), source_limit(config, caller, &source)))
+    })??;
+    
+    let now = time();
+    let source_key = (caller, source.clone());
+    let mut activity = REPORTER_ACTIVITY.with(|activity| activity.borrow().get(&caller)).unwrap_or_default();
+    let mut source_activity = source_limit.map(|limit| {
+        (limit, SOURCE_ACTIVITY.with(|activity| activity.borrow().get(&source_key)).unwrap_or_default())
+    });
+    
+    // Both limits are checked so each window restarts on time
+    let source_breach = source_activity.as_mut()
+        .and_then(|(limit, activity)| limit_breach(activity, limit, amount, now));
+    let reason = limit_breach(&mut activity, &limit, amount, now).or(source_breach);
+    
+    if let Some(reason) = reason {
+        if activity.pending_quarantine >= MAX_PENDING_QUARANTINE_PER_REPORTER {
+            return Err("Revenue report rejected: too many of your reports are awaiting review".to_string());
+        }
+        let report_id = quarantine_revenue(caller, amount, &source, reason);
+        activity.pending_quarantine += 1;
+        store_reporter_activity(caller, activity, source_key, source_activity);
+        return Err(format!("Report exceeds the reporter's revenue limits; report {} quarantined", report_id));
+    }
+    
+    record_revenue(amount, source, is_oracle && verified, caller)?;
+    activity.reported_in_window += amount;
+    if let Some((_, source_activity)) = source_activity.as_mut() {
+        source_activity.reported_in_window += amount;
+    }
+    store_reporter_activity(caller, activity, source_key, source_activity);
+    Ok(())
+}
+
+// Checks a report against one limit, first restarting the limit's window if it has ended
+fn limit_breach(activity: &mut ReporterActivity, limit: &RevenueLimit, amount: u64, now: u64) -> Option<QuarantineReason> {
+    let window_nanos = limit.window_seconds.saturating_mul(1_000_000_000);
+    if now >= activity.window_started_at.saturating_add(window_nanos) {
+        activity.window_started_at = now;
+        activity.reported_in_window = 0;
+    }
+    
+    if amount > limit.max_per_report {
+        Some(QuarantineReason::ExceedsReportLimit)
+    } else if activity.reported_in_window.saturating_add(amount) > limit.max_per_window {
+        Some(QuarantineReason::ExceedsWindowLimit)
+    } else {
+        None
+    }
+}
+
+fn store_reporter_activity(
+    reporter: Principal,
+    activity: ReporterActivity,
+    source_key: (Principal, String),
+    source_activity: Option<(RevenueLimit, ReporterActivity)>,
+) {
+    REPORTER_ACTIVITY.with(|stored| stored.borrow_mut().insert(reporter, activity));
+    if let Some((_, source_activity)) = source_activity {
+        SOURCE_ACTIVITY.with(|stored| stored.borrow_mut().insert(source_key, source_activity));
+    }
+}
+
+fn record_revenue(amount: u64, source: String, verified: bool, reporter: Principal) -> Result<(), String> {
+    mutate_config(|config| {
+        config.total_revenue += amount;
+        Ok(())
+    })?;
+    
+    add_to_open_epoch(amount);
+    ic_cdk::println!("Revenue updated: {} from {}", amount, source);
+    
+    let revenue_update = RevenueUpdate {
+        amount,
+        source,
+        timestamp: time(),
+        oracle_verification: verified,
+        reporter: Some(reporter),
+    };
+    
+    REVENUE_HISTORY.with(|history| {
+        history.borrow().append(&revenue_update).expect("failed to append revenue update");
+    });
+    
+    Ok(())
+}
+
+fn reporter_limit(config: &VaultConfig, reporter: Principal) -> RevenueLimit {
+    config.reporter_limits.as_ref()
+        .and_then(|limits| limits.iter().find(|(limited, _)| *limited == reporter).map(|(_, limit)| limit.clone()))
+        .unwrap_or_else(|| default_revenue_limit(config.funding_goal))
+}
+
+fn source_limit(config: &VaultConfig, reporter: Principal, source: &str) -> Option<RevenueLimit> {
+    config.source_limits.as_ref()?
+        .iter()
+        .find(|(limited, limited_source, _)| *limited == reporter && limited_source == source)
+        .map(|(_, _, limit)| limit.clone())
+}
+
+fn default_revenue_limit(funding_goal: u64) -> RevenueLimit {
+    RevenueLimit {
+        max_per_report: funding_goal,
+        max_per_window: funding_goal,
+        window_seconds: DEFAULT_REVENUE_WINDOW_SECONDS,
+    }
+}
+
+fn quarantine_revenue(reporter: Principal, amount: u64, source: &str, reason: QuarantineReason) -> u64 {
+    QUARANTINED_REVENUE.with(|quarantine| {
+        let mut quarantine = quarantine.borrow_mut();
+        let report_id = quarantine.last_key_value().map_or(1, |(id, _)| id + 1);
+        quarantine.insert(report_id, QuarantinedRevenue {
+            report_id,
+            reporter,
+            amount,
+            source: source.to_string(),
+            reason,
+            reported_at: time(),
+            status: QuarantineStatus::Pending,
+        });
+        report_id
+    })
+}
+
+// Called by the factory. An accepted report is counted as unverified revenue from its original reporter.
+#[update]
+fn review_quarantined_revenue(report_id: u64, accept: bool) -> Result<(), String> {
+    let caller = msg_caller();
+    read_config(|config| {
+        if config.factory_canister != Some(caller) {
+            return Err("Only the campaign factory can review quarantined revenue".to_string());
+        }
+        Ok(())
+    })??;
+    
+    let mut report = QUARANTINED_REVENUE.with(|quarantine| quarantine.borrow().get(&report_id))
+        .ok_or_else(|| "Quarantined report not found".to_string())?;
+    if report.status != QuarantineStatus::Pending {
+        return Err("Report has already been reviewed".to_string());
+    }
+    
+    let reviewed_at = time();
+    if accept {
+        record_revenue(report.amount, report.source.clone(), false, report.reporter)?;
+        report.status = QuarantineStatus::Accepted { reviewed_at };
+    } else {
+        report.status = QuarantineStatus::Rejected { reviewed_at };
+    }
+    REPORTER_ACTIVITY.with(|activity| {
+        let mut activity = activity.borrow_mut();
+        if let Some(mut reporter) = activity.get(&report.reporter) {
+            reporter.pending_quarantine = reporter.pending_quarantine.saturating_sub(1);
+            activity.insert(report.reporter, reporter);
+        }
+    });
+    QUARANTINED_REVENUE.with(|quarantine| quarantine.borrow_mut().insert(report_id, report));
+    
+    Ok(())
+}
+
+#[query]
+fn get_quarantined_revenue(pending_only: bool) -> Vec<QuarantinedRevenue> {
+    QUARANTINED_REVENUE.with(|quarantine| {
+        quarantine.borrow().values()
+            .filter(|report| !pending_only || report.status == QuarantineStatus::Pending)
+            .collect()
+    })
+}
+
+// Called by the factory. `None` puts the reporter back on the default limit. With a `source`
+// the limit applies to that source alone, on top of the reporter's limit, and `None` removes it.
+#[update]
+fn set_revenue_limit(reporter: Principal, limit: Option<RevenueLimit>, source: Option<String>) -> Result<(), String> {
+    let caller = msg_caller();
+    
+    if limit.as_ref().is_some_and(|limit| limit.window_seconds == 0) {
+        return Err("Limit window must be at least one second".to_string());
+    }
+    
+    mutate_config(|config| {
+        if config.factory_canister != Some(caller) {
+            return Err("Only the campaign factory can set revenue limits".to_string());
+        }
+        
+        match source {
+            Some(source) => {
+                let limits = config.source_limits.get_or_insert_with(Vec::new);
+                limits.retain(|(limited, limited_source, _)| *limited != reporter || *limited_source != source);
+                if let Some(limit) = limit {
+                    limits.push((reporter, source, limit));
+                }
+            }
+            None => {
+                let limits = config.reporter_limits.get_or_insert_with(Vec::new);
+                limits.retain(|(limited, _)| *limited != reporter);
+                if let Some(limit) = limit {
+                    limits.push((reporter, limit));
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+// Limits set by the factory; other reporters get the default
+#[query]
+fn get_revenue_limits() -> Vec<(Principal, RevenueLimit)> {
+    read_config(|config| config.reporter_limits.clone().unwrap_or_default()).unwrap_or_default()
+}
+
+#[query]
+fn get_source_revenue_limits() -> Vec<(Principal, String, RevenueLimit)> {
+    read_config(|config| config.source_limits.clone().unwrap_or_default()).unwrap_or_default()
+}
+
+// The reporter's running totals, or those of one of their sources with its own limit
+#[query]
+fn get_reporter_activity(reporter: Principal, source: Option<String>) -> Option<ReporterActivity> {
+    match source {
+        Some(source) => SOURCE_ACTIVITY.with(|activity| activity.borrow().get(&(reporter, source))),
+        None => REPORTER_ACTIVITY.with(|activity| activity.borrow().get(&reporter)),
+    }
+}
+
+fn new_epoch(epoch_id: u64) -> DistributionEpoch {
+    DistributionEpoch {
+        epoch_id,
+        status: EpochStatus::Open,
+        opened_at: time(),
+        closed_at: None,
+        revenue: 0,
+        investor_pool: 0,
+        entitled: 0,
+        dust: 0,
+        holder_count: 0,
+        includes_legacy_revenue: false,
+    }
+}
+
+// The latest epoch is the open one; a new epoch is opened when the latest is closing or closed
+fn open_epoch_id() -> u64 {
+    EPOCHS.with(|epochs| {
+        let mut epochs = epochs.borrow_mut();
+        match epochs.last_key_value() {
+            Some((epoch_id, epoch)) if epoch.status == EpochStatus::Open => epoch_id,
+            latest => {
+                let epoch_id = latest.map_or(1, |(epoch_id, _)| epoch_id + 1);
+                epochs.insert(epoch_id, new_epoch(epoch_id));
+                epoch_id
+            }
+        }
+    })
+}
+
+fn add_to_open_epoch(amount: u64) {
+    let epoch_id = open_epoch_id();
+    EPOCHS.with(|epochs| {
+        let mut epochs = epochs.borrow_mut();
+        if let Some(mut epoch) = epochs.get(&epoch_id) {
+            epoch.revenue += amount;
+            epochs.insert(epoch_id, epoch);
+        }
+    });
+}
+
+fn set_epoch(epoch: DistributionEpoch) {
+    EPOCHS.with(|epochs| epochs.borrow_mut().insert(epoch.epoch_id, epoch));
+}
+
+// Closes the open epoch, or finishes one whose close failed while resolving holders. Anyone
+// may call this once the epoch has revenue; holders then claim with `claim_epoch_payout`.
+#[update]
+async fn close_epoch() -> Result<DistributionEpoch, String> {
+    let _lock = OperationLock::acquire(LockKey::EpochClose)?;
+    
+    let closing = EPOCHS.with(|epochs| {
+        epochs.borrow().values().find(|epoch| epoch.status == EpochStatus::Closing)
+    });
+    let mut epoch = match closing {
+        Some(epoch) => epoch,
+        None => {
+            let epoch_id = open_epoch_id();
+            let epoch = EPOCHS.with(|epochs| epochs.borrow().get(&epoch_id))
+                .ok_or_else(|| "Open epoch not found".to_string())?;
+            if epoch.revenue == 0 {
+                return Err("The open epoch has no revenue to distribute".to_string());
+            }
+            epoch
+        }
+    };
+    
+    let journal = begin_operation(OperationKind::CloseEpoch { epoch_id: epoch.epoch_id })?;
+    if epoch.status == EpochStatus::Open {
+        epoch.status = EpochStatus::Closing;
+        set_epoch(epoch.clone());
+        open_epoch_id(); // Revenue reported while holders are resolved goes to the next epoch
+    }
+    
+    let tranches = distributable_tranches();
+    let result = match resolve_tranche_owners(&tranches).await {
+        Ok(owners) => finalize_epoch(epoch, &tranches, owners, time()),
+        Err(e) => Err(e),
+    };
+    journal.complete(); // On failure the epoch stays Closing and the next call resumes it
+    result
+}
+
+// Refunded tranches hold no share of the revenue; their part stays in the vault as dust
+fn distributable_tranches() -> Vec<Tranche> {
+    TRANCHES.with(|tranches| {
+        tranches.borrow().values().filter(|tranche| !is_refunded(tranche.backer)).collect()
+    })
+}
+
+// The investors' part of an epoch's revenue. Computed in u128 so large revenue can't overflow.
+fn investor_pool_for(revenue: u64, revenue_share_percentage: u8, creator_slashed: bool) -> u64 {
+    let revenue = revenue as u128;
+    let revenue_share_percentage = revenue_share_percentage.min(100) as u128;
+    let mut investor_pool = revenue * revenue_share_percentage / 100;
+    
+    // A slashed creator forfeits half of their share to investors
+    if creator_slashed {
+        let creator_share = revenue * (100 - revenue_share_percentage) / 100;
+        investor_pool += creator_share * 50 / 100;
+    }
+    investor_pool as u64 // Never more than the revenue
+}
+
+// Snapshots the holders of a closing epoch and records their entitlements
+fn finalize_epoch(mut epoch: DistributionEpoch, tranches: &[Tranche], owners: Vec<Principal>, closed_at: u64) -> Result<DistributionEpoch, String> {
+    let (revenue_share_percentage, creator_slashed) = read_config(|config| {
+        (config.revenue_share_percentage, !SLASH_EVENTS.with(|events| events.borrow().is_empty()))
+    })?;
+    let investor_pool = investor_pool_for(epoch.revenue, revenue_share_percentage, creator_slashed);
+    
+    let holders: Vec<(u64, u64)> = tranches.iter()
+        .map(|tranche| (tranche.tranche_id, tranche.share_units.unwrap_or(0)))
+        .collect();
+    let (allocations, _) = allocate_pool(investor_pool, &holders)?;
+    
+    // Each holder's entitlement is the sum over the tranches they hold right now
+    let mut entitlements: Vec<EpochEntitlement> = Vec::new();
+    for ((tranche, owner), (_, allocation)) in tranches.iter().zip(owners).zip(allocations) {
+        let already_paid = if epoch.includes_legacy_revenue { tranche.paid_out.unwrap_or(0) } else { 0 };
+        let amount = allocation.saturating_sub(already_paid);
+        let entitlement = match entitlements.iter_mut().find(|entitlement| entitlement.holder == owner) {
+            Some(entitlement) => entitlement,
+            None => {
+                entitlements.push(EpochEntitlement {
+                    epoch_id: epoch.epoch_id,
+                    holder: owner,
+                    tranche_ids: Vec::new(),
+                    share_units: 0,
+                    amount: 0,
+                    status: EntitlementStatus::Unclaimed,
+                });
+                entitlements.last_mut().expect("entitlement was just pushed")
+            }
+        };
+        entitlement.tranche_ids.push(tranche.tranche_id);
+        entitlement.share_units += tranche.share_units.unwrap_or(0);
+        entitlement.amount += amount;
+    }
+    
+    let entitled: u64 = entitlements.iter().map(|entitlement| entitlement.amount).sum();
+    epoch.investor_pool = investor_pool;
+    epoch.entitled = entitled;
+    epoch.dust = investor_pool - entitled;
+    epoch.holder_count = entitlements.len() as u32;
+    epoch.status = EpochStatus::Closed;
+    epoch.closed_at = Some(closed_at);
+    
+    ENTITLEMENTS.with(|stored| {
+        let mut stored = stored.borrow_mut();
+        for entitlement in entitlements {
+            stored.insert((entitlement.epoch_id, entitlement.holder), entitlement);
+        }
+    });
+    set_epoch(epoch.clone());
+    
+    Ok(epoch)
+}
+
+// Kept for existing callers: closes the open epoch and returns what each holder may now claim
+#[update]
+async fn distribute_payouts() -> Result<Vec<(Principal, u64)>, String> {
+    let epoch = close_epoch().await?;
+    Ok(get_epoch_entitlements(epoch.epoch_id)
+        .into_iter()
+        .map(|entitlement| (entitlement.holder, entitlement.amount))
+        .collect())
+}
+
+// Streams the caller's entitlement for a closed epoch. The entitlement is marked before the
+// stream call, so concurrent claims for the same epoch are rejected.
+#[update]
+async fn claim_epoch_payout(epoch_id: u64) -> Result<EpochEntitlement, String> {
+    let caller = msg_caller();
+    let key = (epoch_id, caller);
+    let _lock = OperationLock::acquire(LockKey::Backer(caller))?;
+    
+    let (stream_canister, stream_settings) = stream_target()?;
+    
+    let mut entitlement = ENTITLEMENTS.with(|stored| stored.borrow().get(&key))
+        .ok_or_else(|| "No entitlement for this epoch".to_string())?;
+    if entitlement.status != EntitlementStatus::Unclaimed {
+        return Err("Entitlement has already been claimed".to_string());
+    }
+    if entitlement.amount == 0 {
+        return Err("Nothing to claim for this epoch".to_string());
+    }
+    
+    let journal = begin_operation(OperationKind::EpochPayout { epoch_id, holder: caller, amount: entitlement.amount })?;
+    entitlement.status = EntitlementStatus::Claiming;
+    ENTITLEMENTS.with(|stored| stored.borrow_mut().insert(key, entitlement.clone()));
+    
+    let result = create_payout_stream(stream_canister, caller, entitlement.amount, stream_settings).await;
+    let settled = settle_epoch_payout(epoch_id, caller, result.as_ref().ok().map(|stream_id| (time(), *stream_id)));
+    journal.complete();
+    result.and(settled.ok_or_else(|| "Entitlement not found".to_string()))
+}
+
+// Records the outcome of a payout stream for an entitlement: claimed when a stream was
+// created, otherwise claimable again
+fn settle_epoch_payout(epoch_id: u64, holder: Principal, streamed: Option<(u64, Option<u64>)>) -> Option<EpochEntitlement> {
+    let key = (epoch_id, holder);
+    let mut entitlement = ENTITLEMENTS.with(|stored| stored.borrow().get(&key))?;
+    
+    match streamed {
+        Some((claimed_at, stream_id)) => {
+            entitlement.status = EntitlementStatus::Claimed { claimed_at, stream_id };
+            credit_stream_allowance(holder, entitlement.amount);
+            BACKERS.with(|backers| {
+                let mut backers = backers.borrow_mut();
+                if let Some(mut info) = backers.get(&holder) {
+                    info.total_claimed += entitlement.amount;
+                    backers.insert(holder, info);
+                }
+            });
+        }
+        None => entitlement.status = EntitlementStatus::Unclaimed,
+    }
+    ENTITLEMENTS.with(|stored| stored.borrow_mut().insert(key, entitlement.clone()));
+    Some(entitlement)
+}
+
+// Streams an approved insurance claim to its claimer and marks it paid
+#[update]
+async fn claim_insurance_payout(claim_id: u64) -> Result<Option<u64>, String> {
+    let caller = msg_caller();
+    let _lock = OperationLock::acquire(LockKey::Backer(caller))?;
+    let (stream_canister, stream_settings) = stream_target()?;
+    
+    let mut claim = INSURANCE_CLAIMS.with(|claims| claims.borrow().get(&claim_id))
+        .ok_or_else(|| format!("Claim with ID {} not found", claim_id))?;
+    if claim.claimer != caller {
+        return Err("Only the claimer can collect an insurance payout".to_string());
+    }
+    if !matches!(claim.status, ClaimStatus::Approved) {
+        return Err(format!("Claim is not approved. Current status: {:?}", claim.status));
+    }
+    
+    // Marked paid first so a second call can't stream it again; restored if the stream fails
+    let journal = begin_operation(OperationKind::InsurancePayout { claim_id, claimer: caller, amount: claim.amount })?;
+    claim.status = ClaimStatus::Paid;
+    INSURANCE_CLAIMS.with(|claims| claims.borrow_mut().insert(claim_id, claim.clone()));
+    
+    let result = create_payout_stream(stream_canister, caller, claim.amount, stream_settings).await;
+    match result {
+        Ok(_) => credit_stream_allowance(caller, claim.amount),
+        Err(_) => reopen_insurance_claim(claim_id),
+    }
+    journal.complete();
+    result
+}
+
+fn reopen_insurance_claim(claim_id: u64) {
+    INSURANCE_CLAIMS.with(|claims| {
+        let mut claims = claims.borrow_mut();
+        if let Some(mut claim) = claims.get(&claim_id) {
+            claim.status = ClaimStatus::Approved;
+            claims.insert(claim_id, claim);
+        }
+    });
+}
+
+fn stream_target() -> Result<(Principal, Option<StreamSettings>), String> {
+    read_config(|config| {
+        let stream_settings = config.payout_stream.clone().map(|stream| StreamSettings {
+            stream_type: stream.stream_type,
+            duration_seconds: stream.duration_seconds,
+            campaign_id: config.campaign_id,
+        });
+        config.stream_canister
+            .map(|stream_canister| (stream_canister, stream_settings))
+            .ok_or_else(|| "Stream canister not configured".to_string())
+    })?
+}
+
+async fn create_payout_stream(
+    stream_canister: Principal,
+    recipient: Principal,
+    amount: u64,
+    settings: Option<StreamSettings>,
+) -> Result<Option<u64>, String> {
+    let stream_ids = Call::unbounded_wait(stream_canister, "create_streams")
+        .with_args(&(vec![(recipient, amount)], settings))
+        .await
+        .map_err(|e| format!("Failed to create streams: {:?}", e))?
+        .candid::<Result<Vec<u64>, String>>()
+        .map_err(|e| format!("Failed to decode stream response: {:?}", e))??;
+    Ok(stream_ids.first().copied())
+}
+
+#[query]
+fn get_epoch(epoch_id: u64) -> Option<DistributionEpoch> {
+    EPOCHS.with(|epochs| epochs.borrow().get(&epoch_id))
+}
+
+#[query]
+fn list_epochs() -> Vec<DistributionEpoch> {
+    EPOCHS.with(|epochs| epochs.borrow().values().collect())
+}
+
+#[query]
+fn get_epoch_entitlements(epoch_id: u64) -> Vec<EpochEntitlement> {
+    ENTITLEMENTS.with(|stored| {
+        stored.borrow()
+            .range((epoch_id, Principal::management_canister())..)
+            .take_while(|((id, _), _)| *id == epoch_id)
+            .map(|(_, entitlement)| entitlement)
+            .collect()
+    })
+}
+
+// Entitlements the holder has not claimed yet, across all closed epochs
+#[query]
+fn get_unclaimed_entitlements(holder: Principal) -> Vec<EpochEntitlement> {
+    ENTITLEMENTS.with(|stored| {
+        stored.borrow()
+            .values()
+            .filter(|entitlement| entitlement.holder == holder && entitlement.status == EntitlementStatus::Unclaimed)
+            .collect()
+    })
+}
+
+// Settles operations whose callback trapped after their inter-canister call. Anyone may call
+// this: each outcome is read back from the ledger's history or the stream canister, nothing
+// is sent again, and settling twice changes nothing. Returns the operations still pending.
+#[update]
+async fn reconcile_pending_operations() -> Vec<PendingOperation> {
+    let interrupted: Vec<PendingOperation> = PENDING_OPERATIONS.with(|operations| {
+        operations.borrow().values().filter(|operation| is_interrupted(operation.op_id)).collect()
+    });
+    
+    for operation in interrupted {
+        // Skip anything another call has picked up since
+        let _lock = match operation.kind.lock_key().map(OperationLock::acquire).transpose() {
+            Ok(lock) => lock,
+            Err(_) => continue,
+        };
+        if !is_interrupted(operation.op_id) {
+            continue;
+        }
+        OPERATIONS_IN_FLIGHT.with(|in_flight| in_flight.borrow_mut().insert(operation.op_id));
+        let journal = JournalGuard(operation.op_id);
+        
+        match settle_operation(&operation).await {
+            Ok(follow_up) => {
+                journal.complete();
+                run_follow_up(follow_up).await;
+            }
+            Err(e) => PENDING_OPERATIONS.with(|operations| {
+                let mut operations = operations.borrow_mut();
+                if let Some(mut pending) = operations.get(&operation.op_id) {
+                    pending.reconcile_attempts += 1;
+                    pending.last_error = Some(e);
+                    operations.insert(operation.op_id, pending);
+                }
+            }),
+        }
+    }
+    
+    get_pending_operations()
+}
+
+// Called by the factory for a ledger transfer reconciliation can't settle, such as one further
+// back in the ledger's history than it searches. A given `block_index` must hold the journaled
+// transfer; without one, the history is searched back to the operation's start.
+#[update]
+async fn resolve_pending_operation(op_id: u64, block_index: Option<u64>) -> Result<(), String> {
+    let caller = msg_caller();
+    read_config(|config| {
+        if config.factory_canister != Some(caller) {
+            return Err("Only the campaign factory can resolve pending operations".to_string());
+        }
+        Ok(())
+    })??;
+    
+    let operation = PENDING_OPERATIONS.with(|operations| operations.borrow().get(&op_id))
+        .ok_or_else(|| "Pending operation not found".to_string())?;
+    let _lock = operation.kind.lock_key().map(OperationLock::acquire).transpose()?;
+    if !is_interrupted(op_id) {
+        return Err("Operation is still in progress".to_string());
+    }
+    OPERATIONS_IN_FLIGHT.with(|in_flight| in_flight.borrow_mut().insert(op_id));
+    let journal = JournalGuard(op_id);
+    
+    let outcome = look_up_journaled_transfer(&operation.kind, block_index, None).await?;
+    let follow_up = record_ledger_outcome(&operation.kind, outcome)?;
+    journal.complete();
+    run_follow_up(follow_up).await;
+    Ok(())
+}
+
+// Calls made once an operation is settled and out of the journal, so a failure here can't
+// lead to the operation being settled twice
+enum FollowUp {
+    ReportGoalReached,
+    VoidPositions(Principal),
+}
+
+async fn run_follow_up(follow_up: Option<FollowUp>) {
+    match follow_up {
+        Some(FollowUp::ReportGoalReached) => {
+            if let Err(e) = report_funding_goal_reached().await {
+                ic_cdk::println!("Failed to notify factory of funded campaign: {}", e);
+            }
+        }
+        Some(FollowUp::VoidPositions(backer)) => {
+            if let Err(e) = void_positions_of(backer).await {
+                ic_cdk::println!("Failed to void positions of {}: {}", backer.to_text(), e);
+            }
+        }
+        None => {}
+    }
+}
+
+async fn settle_operation(operation: &PendingOperation) -> Result<Option<FollowUp>, String> {
+    match operation.kind.clone() {
+        OperationKind::Invest { .. } | OperationKind::Refund { .. } | OperationKind::Payout { .. } => {
+            let outcome = look_up_journaled_transfer(&operation.kind, None, Some(HISTORY_PAGE_BUDGET)).await?;
+            record_ledger_outcome(&operation.kind, outcome)
+        }
+        OperationKind::Mint { tranche_id } => {
+            // The registry returns the existing token if the mint went through
+            queue_pending_mint(tranche_id, "Mint interrupted; queued during reconciliation".to_string());
+            Ok(None)
+        }
+        // The epoch is still Closing, and the next `close_epoch` resumes it
+        OperationKind::CloseEpoch { .. } => Ok(None),
+        OperationKind::EpochPayout { epoch_id, holder, amount } => {
+            let stream = find_payout_stream(holder, amount, operation.started_at).await?;
+            settle_epoch_payout(epoch_id, holder, stream.map(|stream| (stream.start_time, Some(stream.stream_id))));
+            Ok(None)
+        }
+        OperationKind::InsurancePayout { claim_id, claimer, amount } => {
+            match find_payout_stream(claimer, amount, operation.started_at).await? {
+                Some(_) => credit_stream_allowance(claimer, amount),
+                None => reopen_insurance_claim(claim_id),
+            }
+            Ok(None)
+        }
+    }
+}
+
+// Finds the operation's transfer in the ledger's history, either at `block_index` or by search
+async fn look_up_journaled_transfer(kind: &OperationKind, block_index: Option<u64>, page_budget: Option<u32>) -> Result<LedgerOutcome, String> {
+    let (ledger, campaign_id) = ledger_and_campaign()?;
+    let expected = journaled_transfer(kind, campaign_id)
+        .ok_or_else(|| "Operation made no ledger transfer".to_string())?;
+    
+    match block_index {
+        Some(block_index) => {
+            let blocks = fetch_ledger_blocks(ledger, block_index, 1).await?;
+            if !blocks.iter().any(|(_, transaction)| expected.matches(transaction)) {
+                return Err(format!("Ledger block {} is not the journaled transfer", block_index));
+            }
+            Ok(LedgerOutcome::Completed(block_index))
+        }
+        None => find_ledger_transfer(ledger, &expected, page_budget).await,
+    }
+}
+
+// Records what the ledger's history says about a journaled transfer
+fn record_ledger_outcome(kind: &OperationKind, outcome: LedgerOutcome) -> Result<Option<FollowUp>, String> {
+    match (kind.clone(), outcome) {
+        (OperationKind::Invest { backer, amount, .. }, LedgerOutcome::Completed(block_index)) => {
+            let applied = apply_investment(backer, amount, block_index)?;
+            queue_pending_mint(applied.tranche_id, "Investment recorded during reconciliation".to_string());
+            Ok(applied.goal_reached.then_some(FollowUp::ReportGoalReached))
+        }
+        (OperationKind::Invest { .. }, LedgerOutcome::NotApplied) => Ok(None),
+        (OperationKind::Refund { backer, amount, .. }, LedgerOutcome::Completed(block_index)) => {
+            record_transfer(LedgerTransfer {
+                kind: TransferKind::Refund,
+                counterparty: backer,
+                amount,
+                block_index,
+                timestamp: time(),
+            });
+            Ok(Some(FollowUp::VoidPositions(backer)))
+        }
+        (OperationKind::Refund { backer, .. }, LedgerOutcome::NotApplied) => {
+            reopen_refund(backer);
+            Ok(None)
+        }
+        (OperationKind::Payout { to, amount, payout_ref, .. }, LedgerOutcome::Completed(block_index)) => {
+            record_payout(to, amount, block_index, payout_ref);
+            Ok(None)
+        }
+        (OperationKind::Payout { to, amount, payout_ref, fee, .. }, LedgerOutcome::NotApplied) => {
+            set_payout_status(payout_ref, PayoutStatus::Failed { reason: "Transfer is not in the ledger's history".to_string() });
+            // Payouts journaled without a fee predate allowances and reserved none
+            if let Some(fee) = fee {
+                credit_stream_allowance(to, amount + fee);
+            }
+            Ok(None)
+        }
+        _ => Err("Operation made no ledger transfer".to_string()),
+    }
+}
+
+fn ledger_and_campaign() -> Result<(Principal, u64), String> {
+    read_config(|config| {
+        config.ledger_canister
+            .map(|ledger| (ledger, config.campaign_id))
+            .ok_or_else(|| "Ledger canister not configured".to_string())
+    })?
+}
+
+// A stream this vault created for `recipient` since `since`. Only one payout per recipient
+// is in flight at a time, so a match of the amount identifies it.
+async fn find_payout_stream(recipient: Principal, amount: u64, since: u64) -> Result<Option<StreamRecord>, String> {
+    let (stream_canister, _) = stream_target()?;
+    let streams: Vec<StreamRecord> = Call::unbounded_wait(stream_canister, "get_user_streams")
+        .with_arg(recipient)
+        .await
+        .map_err(|e| format!("Failed to call stream canister: {:?}", e))?
+        .candid()
+        .map_err(|e| format!("Failed to decode streams: {:?}", e))?;
+    
+    let vault = canister_self();
+    Ok(streams.into_iter().find(|stream| {
+        stream.vault_canister == vault && stream.total_amount == amount && stream.start_time >= since
+    }))
+}
+
+#[query]
+fn get_pending_operations() -> Vec<PendingOperation> {
+    PENDING_OPERATIONS.with(|operations| operations.borrow().values().collect())
+}
+
+// Looks up the current holder of each tranche's token in the registry, one call per batch.
+// A tranche whose token hasn't been minted yet, or that the registry doesn't know, still
+// belongs to the backer who invested.
+async fn resolve_tranche_owners(tranches: &[Tranche]) -> Result<Vec<Principal>, String> {
+    let minted: Vec<(usize, u64)> = tranches
+        .iter()
+        .enumerate()
+        .filter_map(|(position, tranche)| tranche.nft_token_id.map(|token_id| (position, token_id)))
+        .collect();
+    let mut owners: Vec<Principal> = tranches.iter().map(|tranche| tranche.backer).collect();
+    if minted.is_empty() {
+        return Ok(owners);
+    }
+    let nft_registry = get_nft_registry_canister()
+        .ok_or_else(|| "NFT registry not configured".to_string())?;
+    
+    for batch in minted.chunks(OWNER_LOOKUP_BATCH_SIZE) {
+        let token_ids: Vec<u64> = batch.iter().map(|(_, token_id)| *token_id).collect();
+        let found = Call::unbounded_wait(nft_registry, "icrc7_owners_of")
+            .with_arg(&token_ids)
+            .await
+            .map_err(|e| format!("Failed to look up token owners: {:?}", e))?
+            .candid::<Vec<Option<Principal>>>()
+            .map_err(|e| format!("Failed to decode token owners: {:?}", e))?;
+        if found.len() != batch.len() {
+            return Err(format!("NFT registry returned {} owners for {} tokens", found.len(), batch.len()));
+        }
+        
+        for ((position, token_id), owner) in batch.iter().zip(found) {
+            match owner {
+                Some(owner) => owners[*position] = owner,
+                None => ic_cdk::println!(
+                    "Token {} of tranche {} not found in NFT registry; paying the backer",
+                    token_id, tranches[*position].tranche_id
+                ),
+            }
         }
+    }
+    Ok(owners)
+}
+
+fn get_campaign_id() -> u64 {
+    read_config(|config| config.campaign_id).unwrap_or(0)
+}
+
+fn get_nft_registry_canister() -> Option<Principal> {
+    read_config(|config| config.nft_registry_canister).ok().flatten()
+}
+
+#[query]
+fn get_vault_state() -> Option<VaultState> {
+    let config = CONFIG.with(|cell| cell.borrow().get().clone())?;
+    
+    Some(VaultState {
+        campaign_id: config.campaign_id,
+        creator: config.creator,
+        title: config.title,
+        funding_goal: config.funding_goal,
+        current_funding: config.current_funding,
+        revenue_share_percentage: config.revenue_share_percentage,
+        total_revenue: config.total_revenue,
+        oracle_endpoints: config.oracle_endpoints,
+        nft_registry_canister: config.nft_registry_canister,
+        stream_canister: config.stream_canister,
+        oracle_canister: config.oracle_canister,
+        ledger_canister: config.ledger_canister,
+        backers: BACKERS.with(|backers| backers.borrow().iter().collect::<HashMap<_, _>>()),
+        revenue_history: REVENUE_HISTORY.with(|history| history.borrow().iter().collect()),
+        created_at: config.created_at,
+        insurance_pool_balance: config.insurance_pool_balance,
+        insurance_fee_percentage: config.insurance_fee_percentage,
+        insurance_coverage_ratio: config.insurance_coverage_ratio,
+        insurance_claims: INSURANCE_CLAIMS.with(|claims| claims.borrow().iter().map(|(_, c)| c).collect()),
+        slashing_conditions: config.slashing_conditions,
+        slashed_creators: SLASH_EVENTS.with(|events| events.borrow().iter().collect()),
+        ledger_transfers: LEDGER_TRANSFERS.with(|log| log.borrow().iter().collect()),
+        funding_deadline: config.funding_deadline,
+        refunds_opened_at: config.refunds_opened_at,
+        payout_stream: config.payout_stream,
+    })
+}
+
+#[query]
+fn get_vault_stats() -> Option<VaultStats> {
+    let config = CONFIG.with(|cell| cell.borrow().get().clone())?;
+    
+    Some(VaultStats {
+        current_funding: config.current_funding,
+        funding_goal: config.funding_goal,
+        backer_count: BACKERS.with(|backers| backers.borrow().len()),
+        total_revenue: config.total_revenue,
+    })
+}
+
+#[query]
+fn get_backer_info(backer: Principal) -> Option<BackerInfo> {
+    BACKERS.with(|backers| backers.borrow().get(&backer))
+}
+
+#[query]
+fn get_backer_tranches(backer: Principal) -> Vec<Tranche> {
+    TRANCHES.with(|tranches| {
+        tranches
+            .borrow()
+            .iter()
+            .map(|(_, tranche)| tranche)
+            .filter(|tranche| tranche.backer == backer)
+            .collect()
     })
 }
 
+#[query]
+fn get_tranche(tranche_id: u64) -> Option<Tranche> {
+    TRANCHES.with(|tranches| tranches.borrow().get(&tranche_id))
+}
+
+#[query]
+fn get_funding_progress() -> (u64, u64, f64) {
+    read_config(|config| {
+        let percentage = (config.current_funding as f64 / config.funding_goal as f64) * 100.0;
+        (config.current_funding, config.funding_goal, percentage)
+    }).unwrap_or((0, 0, 0.0))
+}
+
+// Insurance-related functions
+
+#[query]
+fn get_insurance_pool_info() -> (u64, u8, u8) {
+    read_config(|config| {
+        (
+            config.insurance_pool_balance,
+            config.insurance_fee_percentage,
+            config.insurance_coverage_ratio
+        )
+    }).unwrap_or((0, 0, 0))
+}
+
 #[update]
 fn file_insurance_claim(amount: u64, reason: String, evidence: Vec<String>) -> Result<u64, String> {
     let caller = msg_caller();
     
-    VAULT_STATE.with(|state_ref| {
-        let mut state_opt = state_ref.borrow_mut();
-        if let Some(ref mut state) = *state_opt {
-            // Check if caller is a backer
-            if let Some(backer_info) = state.backers.get(&caller) {
-                // Calculate max claimable amount (coverage ratio * investment)
-                let max_claimable = (backer_info.amount_invested * state.insurance_coverage_ratio as u64) / 100;
-                
-                if amount > max_claimable {
-                    return Err(format!("Claim exceeds maximum coverage of {}", max_claimable));
-                }
-                
-                // Check if there's enough in the insurance pool
-                if amount > state.insurance_pool_balance {
-                    return Err("Insufficient funds in insurance pool".to_string());
-                }
-                
-                // Create claim
-                let claim_id = state.insurance_claims.len() as u64;
-                let claim = InsuranceClaim {
-                    claim_id,
-                    claimer: caller,
-                    amount,
-                    reason,
-                    evidence,
-                    status: ClaimStatus::Pending,
-                    filed_at: time(),
-                    resolved_at: None,
-                    approver: None,
-                };
-                
-                state.insurance_claims.push(claim);
-                
-                Ok(claim_id)
-            } else {
-                Err("Only backers can file insurance claims".to_string())
-            }
-        } else {
-            Err("Vault not initialized".to_string())
-        }
+    let (insurance_coverage_ratio, insurance_pool_balance) = read_config(|config| {
+        (config.insurance_coverage_ratio, config.insurance_pool_balance)
+    })?;
+    
+    // Check if caller is a backer
+    let backer_info = BACKERS.with(|backers| backers.borrow().get(&caller))
+        .ok_or_else(|| "Only backers can file insurance claims".to_string())?;
+    
+    // Calculate max claimable amount (coverage ratio * investment)
+    let max_claimable = (backer_info.amount_invested * insurance_coverage_ratio as u64) / 100;
+    
+    if amount > max_claimable {
+        return Err(format!("Claim exceeds maximum coverage of {}", max_claimable));
+    }
+    
+    // Check if there's enough in the insurance pool
+    if amount > insurance_pool_balance {
+        return Err("Insufficient funds in insurance pool".to_string());
+    }
+    
+    INSURANCE_CLAIMS.with(|claims| {
+        let mut claims = claims.borrow_mut();
+        
+        // Create claim
+        let claim_id = claims.len();
+        let claim = InsuranceClaim {
+            claim_id,
+            claimer: caller,
+            amount,
+            reason,
+            evidence,
+            status: ClaimStatus::Pending,
+            filed_at: time(),
+            resolved_at: None,
+            approver: None,
+        };
+        
+        claims.insert(claim_id, claim);
+        
+        Ok(claim_id)
     })
 }
 
 #[update]
-fn process_insurance_claim(claim_id: u64, approve: bool, notes: String) -> Result<(), String> {
+fn process_insurance_claim(claim_id: u64, approve: bool, _notes: String) -> Result<(), String> {
     let caller = msg_caller();
     
-    VAULT_STATE.with(|state_ref| {
-        let mut state_opt = state_ref.borrow_mut();
-        if let Some(ref mut state) = *state_opt {
-            // Check if caller is creator or has governance rights
-            if state.creator != caller {
-                return Err("Only creator or governance can process claims".to_string());
-            }
-            
-            // Find the claim
-            if let Some(claim) = state.insurance_claims.iter_mut().find(|c| c.claim_id == claim_id) {
-                if matches!(claim.status, ClaimStatus::Pending) {
-                    if approve {
-                        // Make sure we have enough in the pool
-                        if claim.amount > state.insurance_pool_balance {
-                            return Err("Insufficient funds in insurance pool".to_string());
-                        }
-                        
-                        // Update claim status
-                        claim.status = ClaimStatus::Approved;
-                        claim.resolved_at = Some(time());
-                        claim.approver = Some(caller);
-                        
-                        // Reduce the insurance pool
-                        state.insurance_pool_balance -= claim.amount;
-                                                
-                    } else {
-                        // Reject the claim
-                        claim.status = ClaimStatus::Rejected;
-                        claim.resolved_at = Some(time());
-                        claim.approver = Some(caller);
-                    }
-                    
-                    Ok(())
-                } else {
-                    Err(format!("Claim is not pending. Current status: {:?}", claim.status))
-                }
-            } else {
-                Err(format!("Claim with ID {} not found", claim_id))
-            }
-        } else {
-            Err("Vault not initialized".to_string())
+    let (creator, insurance_pool_balance) = read_config(|config| {
+        (config.creator, config.insurance_pool_balance)
+    })?;
+    
+    // Check if caller is creator or has governance rights
+    if creator != caller {
+        return Err("Only creator or governance can process claims".to_string());
+    }
+    
+    // Find the claim
+    let mut claim = INSURANCE_CLAIMS.with(|claims| claims.borrow().get(&claim_id))
+        .ok_or_else(|| format!("Claim with ID {} not found", claim_id))?;
+    
+    if !matches!(claim.status, ClaimStatus::Pending) {
+        return Err(format!("Claim is not pending. Current status: {:?}", claim.status));
+    }
+    
+    if approve {
+        // Make sure we have enough in the pool
+        if claim.amount > insurance_pool_balance {
+            return Err("Insufficient funds in insurance pool".to_string());
         }
-    })
+        
+        // Reduce the insurance pool
+        mutate_config(|config| {
+            config.insurance_pool_balance -= claim.amount;
+            Ok(())
+        })?;
+        
+        // Update claim status
+        claim.status = ClaimStatus::Approved;
+    } else {
+        // Reject the claim
+        claim.status = ClaimStatus::Rejected;
+    }
+    
+    claim.resolved_at = Some(time());
+    claim.approver = Some(caller);
+    
+    INSURANCE_CLAIMS.with(|claims| {
+        claims.borrow_mut().insert(claim_id, claim);
+    });
+    
+    Ok(())
 }
 
 // Slashing-related functions
 
 #[update]
-fn propose_slashing(creator: Principal, reason: SlashReason, evidence: Vec<String>) -> Result<u64, String> {
+fn propose_slashing(creator: Principal, reason: SlashReason, _evidence: Vec<String>) -> Result<u64, String> {
     let caller = msg_caller();
     
-
-    VAULT_STATE.with(|state_ref| {
-        let mut state_opt = state_ref.borrow_mut();
-        if let Some(ref mut state) = *state_opt {
-            // Basic validation
-            if creator != state.creator {
-                return Err("Target is not the creator of this campaign".to_string());
-            }
-            
-         
-            let creator_share = state.total_revenue * (100 - state.revenue_share_percentage) as u64 / 100;
-            let slash_amount = creator_share / 2;
-            
-            // Record slash event
-            let slash_event = SlashEvent {
-                creator,
-                campaign_id: state.campaign_id,
-                reason,
-                amount_slashed: slash_amount,
-                beneficiaries: state.backers.keys().cloned().collect(), // Distribute to all backers
-                executed_at: time(),
-                approved_by: vec![caller], 
-            };
-            
-            state.slashed_creators.push(slash_event.clone());
-            
-            
-            state.insurance_pool_balance += slash_amount;
-            
-            Ok(state.slashed_creators.len() as u64 - 1)
-        } else {
-            Err("Vault not initialized".to_string())
+    let (campaign_id, slash_amount) = mutate_config(|config| {
+        // Basic validation
+        if creator != config.creator {
+            return Err("Target is not the creator of this campaign".to_string());
         }
-    })
+        
+     
+        let creator_share = config.total_revenue * (100 - config.revenue_share_percentage) as u64 / 100;
+        let slash_amount = creator_share / 2;
+        
+        config.insurance_pool_balance += slash_amount;
+        
+        Ok((config.campaign_id, slash_amount))
+    })?;
+    
+    // Record slash event
+    let slash_event = SlashEvent {
+        creator,
+        campaign_id,
+        reason,
+        amount_slashed: slash_amount,
+        beneficiaries: BACKERS.with(|backers| backers.borrow().iter().map(|(k, _)| k).collect()), // Distribute to all backers
+        executed_at: time(),
+        approved_by: vec![caller], 
+    };
+    
+    let index = SLASH_EVENTS.with(|events| {
+        events.borrow().append(&slash_event).expect("failed to append slash event")
+    });
+    
+    Ok(index)
 }
 
 #[query]
 fn get_slashing_conditions() -> SlashingConditions {
-    VAULT_STATE.with(|state_ref| {
-        state_ref.borrow().as_ref().map_or_else(
-            || SlashingConditions {
-                missed_revenue_reports_threshold: 0,
-                revenue_decline_threshold_percentage: 0,
-                minimum_active_period_days: 0,
-                governance_votes_required: 0,
-            },
-            |s| s.slashing_conditions.clone()
-        )
+    read_config(|config| config.slashing_conditions.clone()).unwrap_or(SlashingConditions {
+        missed_revenue_reports_threshold: 0,
+        revenue_decline_threshold_percentage: 0,
+        minimum_active_period_days: 0,
+        governance_votes_required: 0,
     })
 }
 
 #[query]
 fn get_slash_events() -> Vec<SlashEvent> {
-    VAULT_STATE.with(|state_ref| {
-        state_ref.borrow().as_ref().map_or_else(
-            || Vec::new(),
-            |s| s.slashed_creators.clone()
-        )
-    })
+    SLASH_EVENTS.with(|events| events.borrow().iter().collect())
 }
 
 #[query]
 fn get_insurance_claims(backer: Option<Principal>) -> Vec<InsuranceClaim> {
-    VAULT_STATE.with(|state_ref| {
-        if let Some(ref state) = *state_ref.borrow() {
-            match backer {
-                Some(principal) => state.insurance_claims.iter()
-                    .filter(|claim| claim.claimer == principal)
-                    .cloned()
-                    .collect(),
-                None => state.insurance_claims.clone(),
-            }
-        } else {
-            Vec::new()
-        }
+    INSURANCE_CLAIMS.with(|claims| {
+        claims.borrow().iter()
+            .map(|(_, claim)| claim)
+            .filter(|claim| backer.is_none_or(|principal| claim.claimer == principal))
+            .collect()
     })
 }
 
 #[query]
 fn get_insurance_claim(claim_id: u64) -> Option<InsuranceClaim> {
-    VAULT_STATE.with(|state_ref| {
-        state_ref.borrow().as_ref().and_then(|s| {
-            s.insurance_claims.iter()
-                .find(|c| c.claim_id == claim_id)
-                .cloned()
-        })
-    })
+    INSURANCE_CLAIMS.with(|claims| claims.borrow().get(&claim_id))
 }
 
 #[update]
@@ -658,30 +3058,55 @@ fn set_canister_refs(
 ) -> Result<(), String> {
     let caller = msg_caller();
     
-    VAULT_STATE.with(|state_ref| {
-        let mut state_opt = state_ref.borrow_mut();
-        if let Some(ref mut state) = *state_opt {
-            if state.creator != caller {
-                return Err("Only creator can set canister references".to_string());
-            }
-            
-            if let Some(nft) = nft_registry {
-                state.nft_registry_canister = Some(nft);
-            }
-            if let Some(stream) = stream {
-                state.stream_canister = Some(stream);
-            }
-            if let Some(oracle) = oracle {
-                state.oracle_canister = Some(oracle);
-            }
-            
-            Ok(())
-        } else {
-            Err("Vault not initialized".to_string())
+    mutate_config(|config| {
+        if config.creator != caller && config.factory_canister != Some(caller) {
+            return Err("Only creator or factory can set canister references".to_string());
+        }
+        
+        if let Some(nft) = nft_registry {
+            config.nft_registry_canister = Some(nft);
+        }
+        if let Some(stream) = stream {
+            config.stream_canister = Some(stream);
+        }
+        if let Some(oracle) = oracle {
+            config.oracle_canister = Some(oracle);
         }
+        
+        Ok(())
+    })
+}
+
+#[update]
+fn set_ledger_canister(ledger: Principal) -> Result<(), String> {
+    let caller = msg_caller();
+    let funds_moved = !LEDGER_TRANSFERS.with(|log| log.borrow().is_empty());
+    
+    mutate_config(|config| {
+        if config.creator != caller {
+            return Err("Only creator can set the ledger canister".to_string());
+        }
+        
+        // Switching ledgers would strand funds already held on the old one
+        if funds_moved {
+            return Err("Ledger cannot be changed after funds have moved".to_string());
+        }
+        
+        config.ledger_canister = Some(ledger);
+        Ok(())
     })
 }
 
+#[query]
+fn get_vault_account() -> Option<Account> {
+    read_config(|config| vault_account(config.campaign_id)).ok()
+}
+
+#[query]
+fn get_ledger_transfers() -> Vec<LedgerTransfer> {
+    LEDGER_TRANSFERS.with(|log| log.borrow().iter().collect())
+}
+
 #[update]
 fn update_insurance_settings(
     fee_percentage: Option<u8>,
@@ -690,40 +3115,418 @@ fn update_insurance_settings(
 ) -> Result<(), String> {
     let caller = msg_caller();
     
-    VAULT_STATE.with(|state_ref| {
-        let mut state_opt = state_ref.borrow_mut();
-        if let Some(ref mut state) = *state_opt {
-            // Only creator or DAO governance can update these settings
-            if state.creator != caller {
-                return Err("Only creator or governance can update insurance settings".to_string());
+    mutate_config(|config| {
+        // Only creator or DAO governance can update these settings
+        if config.creator != caller {
+            return Err("Only creator or governance can update insurance settings".to_string());
+        }
+        
+        // Update insurance fee percentage if provided
+        if let Some(fee) = fee_percentage {
+            if fee > 20 {
+                return Err("Insurance fee cannot exceed 20%".to_string());
             }
-            
-            // Update insurance fee percentage if provided
-            if let Some(fee) = fee_percentage {
-                if fee > 20 {
-                    return Err("Insurance fee cannot exceed 20%".to_string());
-                }
-                state.insurance_fee_percentage = fee;
+            config.insurance_fee_percentage = fee;
+        }
+        
+        // Update coverage ratio if provided
+        if let Some(ratio) = coverage_ratio {
+            if ratio > 100 {
+                return Err("Coverage ratio cannot exceed 100%".to_string());
             }
+            config.insurance_coverage_ratio = ratio;
+        }
+        
+        // Update slashing conditions if provided
+        if let Some(conditions) = slashing_conditions {
+            config.slashing_conditions = conditions;
+        }
+        
+        Ok(())
+    })
+}
+
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn share_units_for_scales_to_the_funding_goal() {
+        assert_eq!(share_units_for(1_000, 1_000), SHARE_SCALE);
+        assert_eq!(share_units_for(500, 1_000), SHARE_SCALE / 2);
+        assert_eq!(share_units_for(1, 3), SHARE_SCALE / 3);
+        assert_eq!(share_units_for(u64::MAX, u64::MAX), SHARE_SCALE);
+        assert_eq!(share_units_for(5, 0), 5 * SHARE_SCALE); // A zero goal is treated as 1
+    }
+    
+    #[test]
+    fn allocate_pool_gives_the_leftover_to_the_largest_remainders() {
+        let third = SHARE_SCALE / 3;
+        let (allocations, dust) = allocate_pool(100, &[(1u64, third), (2, third), (3, third)]).unwrap();
+        // Equal remainders: the lower key wins the unit left over
+        assert_eq!(allocations, vec![(1, 34), (2, 33), (3, 33)]);
+        assert_eq!(dust, 0);
+    }
+    
+    #[test]
+    fn allocate_pool_keeps_the_unsold_part_as_dust() {
+        let (allocations, dust) = allocate_pool(101, &[(7u64, SHARE_SCALE / 2)]).unwrap();
+        // Holders win ties against the unsold shares
+        assert_eq!(allocations, vec![(7, 51)]);
+        assert_eq!(dust, 50);
+        
+        let (allocations, dust) = allocate_pool(250, &[] as &[(u64, u64)]).unwrap();
+        assert!(allocations.is_empty());
+        assert_eq!(dust, 250);
+    }
+    
+    #[test]
+    fn allocate_pool_rejects_more_units_than_the_supply() {
+        assert!(allocate_pool(100, &[(1u64, SHARE_SCALE), (2, 1)]).is_err());
+    }
+    
+    #[test]
+    fn allocate_pool_never_drifts_from_the_pool() {
+        let holders: Vec<(u64, u64)> = vec![
+            (1, 1_234_567_891),
+            (2, 987_654_321),
+            (3, 3_333_333_333),
+            (4, 1),
+            (5, 4_444_444_444),
+        ];
+        for pool in [0u64, 1, 2, 7, 99, 1_000, 123_456_789, u64::MAX / 2] {
+            let (allocations, dust) = allocate_pool(pool, &holders).unwrap();
+            let total: u128 = allocations.iter().map(|(_, amount)| *amount as u128).sum::<u128>() + dust as u128;
+            assert_eq!(total, pool as u128, "pool {}", pool);
             
-            // Update coverage ratio if provided
-            if let Some(ratio) = coverage_ratio {
-                if ratio > 100 {
-                    return Err("Coverage ratio cannot exceed 100%".to_string());
-                }
-                state.insurance_coverage_ratio = ratio;
+            // Each holder gets its exact share rounded down, or one more
+            for ((holder, units), (allocated_to, amount)) in holders.iter().zip(&allocations) {
+                assert_eq!(holder, allocated_to);
+                let floor = (pool as u128 * *units as u128 / SHARE_SCALE as u128) as u64;
+                assert!(*amount == floor || *amount == floor + 1, "pool {} holder {}", pool, holder);
             }
-            
-            // Update slashing conditions if provided
-            if let Some(conditions) = slashing_conditions {
-                state.slashing_conditions = conditions;
+        }
+    }
+    
+    #[test]
+    fn gross_for_net_is_the_smallest_investment_that_fills_the_gap() {
+        for fee_percentage in [0u8, 1, 2, 5, 20] {
+            for net in [1u64, 2, 49, 98, 100, 1_000, 123_456_789] {
+                let gross = gross_for_net(net, fee_percentage);
+                assert!(net_of_fee(gross, fee_percentage) >= net, "fee {} net {}", fee_percentage, net);
+                assert!(net_of_fee(gross - 1, fee_percentage) < net, "fee {} net {}", fee_percentage, net);
             }
-            
-            Ok(())
-        } else {
-            Err("Vault not initialized".to_string())
         }
-    })
-}
+    }
+    
+    #[test]
+    fn gross_for_net_covers_the_fee() {
+        assert_eq!(gross_for_net(0, 2), 0);
+        assert_eq!(gross_for_net(100, 0), 100);
+        assert_eq!(gross_for_net(98, 2), 99); // The 1.98 fee rounds down to 1
+        assert_eq!(gross_for_net(980, 2), 999);
+        assert_eq!(gross_for_net(1_000, 20), 1_249);
+        assert_eq!(gross_for_net(u64::MAX, 20), u64::MAX);
+    }
+    
+    fn ledger_block(transfer: &ExpectedTransfer) -> LedgerTransaction {
+        LedgerTransaction {
+            kind: "transfer".to_string(),
+            transfer: Some(ipx_types::LedgerTransactionTransfer {
+                from: transfer.from.clone(),
+                to: transfer.to.clone(),
+                spender: transfer.spender.clone(),
+                amount: Nat::from(transfer.amount),
+                fee: None,
+                memo: transfer.memo.clone(),
+                created_at_time: Some(transfer.created_at_time),
+            }),
+            timestamp: transfer.created_at_time,
+        }
+    }
+    
+    fn investment(vault: Principal) -> ExpectedTransfer {
+        let args = TransferFromArgs {
+            spender_subaccount: None,
+            from: Account { owner: Principal::anonymous(), subaccount: None },
+            to: Account { owner: vault, subaccount: Some(campaign_subaccount(7).to_vec()) },
+            amount: Nat::from(500u64),
+            fee: None,
+            memo: Some(7u64.to_be_bytes().to_vec()),
+            created_at_time: Some(1_000),
+        };
+        ExpectedTransfer::from_transfer_from(&args, vault)
+    }
+    
+    #[test]
+    fn journaled_transfer_matches_its_block_with_default_subaccounts_spelled_out() {
+        let vault = Principal::management_canister();
+        let expected = investment(vault);
+        let mut block = ledger_block(&expected);
+        if let Some(transfer) = block.transfer.as_mut() {
+            transfer.from.subaccount = Some(vec![0; 32]);
+            transfer.spender = Some(Account { owner: vault, subaccount: Some(vec![0; 32]) });
+        }
+        assert!(expected.matches(&block));
+    }
+    
+    #[test]
+    fn journaled_transfer_does_not_match_other_blocks() {
+        let vault = Principal::management_canister();
+        let expected = investment(vault);
+        
+        let mut other_time = expected.clone();
+        other_time.created_at_time += 1;
+        let mut other_amount = expected.clone();
+        other_amount.amount -= 1;
+        let mut no_spender = expected.clone();
+        no_spender.spender = None;
+        let mut other_campaign = expected.clone();
+        other_campaign.to.subaccount = Some(campaign_subaccount(8).to_vec());
+        for other in [other_time, other_amount, no_spender, other_campaign] {
+            assert!(!expected.matches(&ledger_block(&other)), "{:?}", other);
+        }
+        
+        let mut approval = ledger_block(&expected);
+        approval.kind = "approve".to_string();
+        approval.transfer = None;
+        assert!(!expected.matches(&approval));
+    }
 
-ic_cdk::export_candid!();
\ No newline at end of file
+    
+    #[test]
+    fn ledger_history_round_trips_through_candid() {
+        let vault = Principal::management_canister();
+        let response = GetTransactionsResponse {
+            log_length: Nat::from(2_001u64),
+            first_index: Nat::from(2_000u64),
+            transactions: vec![ledger_block(&investment(vault))],
+            archived_transactions: vec![ipx_types::ArchivedTransactions {
+                start: Nat::from(0u64),
+                length: Nat::from(2_000u64),
+                callback: ipx_types::QueryArchiveFn::new(vault, "get_transactions".to_string()),
+            }],
+        };
+        let bytes = Encode!(&response).unwrap();
+        let decoded = Decode!(&bytes, GetTransactionsResponse).unwrap();
+        assert!(investment(vault).matches(&decoded.transactions[0]));
+        assert_eq!(decoded.archived_transactions[0].callback.0.method, "get_transactions");
+    }
+    
+    #[test]
+    fn transfers_are_capped_by_the_streams_opened_for_the_recipient() {
+        let recipient = Principal::from_slice(&[1]);
+        let other = Principal::from_slice(&[2]);
+        assert!(reserve_stream_allowance(recipient, 1).is_err());
+        
+        credit_stream_allowance(recipient, 100);
+        assert!(reserve_stream_allowance(other, 1).is_err());
+        assert!(reserve_stream_allowance(recipient, 60).is_ok());
+        assert!(reserve_stream_allowance(recipient, 41).is_err());
+        
+        // A payout the ledger never applied can be paid again
+        credit_stream_allowance(recipient, 60);
+        assert!(reserve_stream_allowance(recipient, 100).is_ok());
+        assert!(reserve_stream_allowance(recipient, 1).is_err());
+    }
+    
+    fn set_test_config(revenue_share_percentage: u8) {
+        let insurance = default_insurance_settings();
+        let config = VaultConfig {
+            campaign_id: 7,
+            creator: Principal::anonymous(),
+            title: "Test".to_string(),
+            funding_goal: 1_000,
+            current_funding: 1_000,
+            revenue_share_percentage,
+            total_revenue: 0,
+            oracle_endpoints: Vec::new(),
+            nft_registry_canister: None,
+            stream_canister: None,
+            oracle_canister: None,
+            ledger_canister: None,
+            factory_canister: None,
+            funding_deadline: None,
+            refunds_opened_at: None,
+            awaiting_launch: None,
+            payout_stream: None,
+            reporter_limits: None,
+            source_limits: None,
+            created_at: 0,
+            insurance_pool_balance: 0,
+            insurance_fee_percentage: insurance.fee_percentage,
+            insurance_coverage_ratio: insurance.coverage_ratio,
+            slashing_conditions: insurance.slashing_conditions,
+        };
+        CONFIG.with(|cell| cell.borrow_mut().set(Some(config)).unwrap());
+    }
+    
+    fn test_tranche(tranche_id: u64, backer: Principal, share_units: u64, paid_out: Option<u64>) -> Tranche {
+        Tranche {
+            tranche_id,
+            backer,
+            amount_invested: 0,
+            insurance_fee: 0,
+            share_percentage: 0.0,
+            block_index: None,
+            invested_at: 0,
+            nft_token_id: None,
+            share_units: Some(share_units),
+            paid_out,
+        }
+    }
+    
+    fn closing_epoch(revenue: u64, includes_legacy_revenue: bool) -> DistributionEpoch {
+        DistributionEpoch {
+            epoch_id: 1,
+            status: EpochStatus::Closing,
+            opened_at: 0,
+            closed_at: None,
+            revenue,
+            investor_pool: 0,
+            entitled: 0,
+            dust: 0,
+            holder_count: 0,
+            includes_legacy_revenue,
+        }
+    }
+    
+    #[test]
+    fn investor_pool_for_does_not_overflow() {
+        assert_eq!(investor_pool_for(1_000, 40, false), 400);
+        assert_eq!(investor_pool_for(1_000, 40, true), 700); // Plus half of the creator's 600
+        assert_eq!(investor_pool_for(u64::MAX, 100, false), u64::MAX);
+        assert_eq!(investor_pool_for(u64::MAX, 30, false), (u64::MAX as u128 * 30 / 100) as u64);
+        assert!(investor_pool_for(u64::MAX, 30, true) < u64::MAX);
+    }
+    
+    #[test]
+    fn finalize_epoch_adds_up_each_owners_tranches() {
+        set_test_config(40);
+        let alice = Principal::from_slice(&[1]);
+        let bob = Principal::from_slice(&[2]);
+        let tranches = vec![
+            test_tranche(1, alice, SHARE_SCALE / 4, None),
+            test_tranche(2, bob, SHARE_SCALE / 2, None),
+            test_tranche(3, bob, SHARE_SCALE / 4, None), // Sold on to alice
+        ];
+        
+        let epoch = finalize_epoch(closing_epoch(1_000, false), &tranches, vec![alice, bob, alice], 42).unwrap();
+        assert_eq!(epoch.status, EpochStatus::Closed);
+        assert_eq!(epoch.closed_at, Some(42));
+        assert_eq!((epoch.investor_pool, epoch.entitled, epoch.dust, epoch.holder_count), (400, 400, 0, 2));
+        
+        let entitlements = get_epoch_entitlements(1);
+        let of = |holder: Principal| entitlements.iter().find(|entitlement| entitlement.holder == holder).unwrap();
+        assert_eq!(of(alice).tranche_ids, vec![1, 3]);
+        assert_eq!((of(alice).share_units, of(alice).amount), (SHARE_SCALE / 2, 200));
+        assert_eq!(of(bob).tranche_ids, vec![2]);
+        assert_eq!(of(bob).amount, 200);
+    }
+    
+    #[test]
+    fn finalize_epoch_deducts_what_the_legacy_epoch_already_paid() {
+        set_test_config(40);
+        let alice = Principal::from_slice(&[1]);
+        let bob = Principal::from_slice(&[2]);
+        let tranches = vec![
+            test_tranche(1, alice, SHARE_SCALE / 2, Some(50)),
+            test_tranche(2, bob, SHARE_SCALE / 2, Some(500)),
+        ];
+        
+        let epoch = finalize_epoch(closing_epoch(1_000, true), &tranches, vec![alice, bob], 0).unwrap();
+        let entitlements = get_epoch_entitlements(1);
+        assert_eq!(entitlements.iter().map(|entitlement| entitlement.amount).collect::<Vec<_>>(), vec![150, 0]);
+        // What was paid before the epoch stays out of the entitlements
+        assert_eq!((epoch.investor_pool, epoch.entitled, epoch.dust), (400, 150, 250));
+        
+        // Later epochs ignore earlier payouts
+        let mut next = closing_epoch(1_000, false);
+        next.epoch_id = 2;
+        let epoch = finalize_epoch(next, &tranches, vec![alice, bob], 0).unwrap();
+        assert_eq!(epoch.entitled, 400);
+    }
+    
+    #[test]
+    fn refunded_tranches_are_left_out_of_epochs() {
+        let refunded = Principal::from_slice(&[1]);
+        let holder = Principal::from_slice(&[2]);
+        for (backer, refunded_at) in [(refunded, Some(5)), (holder, None)] {
+            BACKERS.with(|backers| backers.borrow_mut().insert(backer, BackerInfo {
+                amount_invested: 500,
+                nft_token_id: None,
+                share_percentage: 0.0,
+                total_claimed: 0,
+                investment_timestamp: 0,
+                refunded_at,
+                last_investment_at: None,
+                tranche_count: Some(1),
+                share_units: Some(SHARE_SCALE / 2),
+            }));
+        }
+        TRANCHES.with(|tranches| {
+            let mut tranches = tranches.borrow_mut();
+            tranches.insert(1, test_tranche(1, refunded, SHARE_SCALE / 2, None));
+            tranches.insert(2, test_tranche(2, holder, SHARE_SCALE / 2, None));
+        });
+        
+        let tranches = distributable_tranches();
+        assert_eq!(tranches.iter().map(|tranche| tranche.tranche_id).collect::<Vec<_>>(), vec![2]);
+    }
+    
+    const SECOND: u64 = 1_000_000_000;
+    
+    #[test]
+    fn limit_breach_quarantines_reports_over_either_cap() {
+        let limit = RevenueLimit { max_per_report: 100, max_per_window: 250, window_seconds: 60 };
+        let mut activity = ReporterActivity::default();
+        let now = 1_000 * SECOND;
+        
+        assert_eq!(limit_breach(&mut activity, &limit, 101, now), Some(QuarantineReason::ExceedsReportLimit));
+        assert_eq!(activity.window_started_at, now); // The first report opens the window
+        
+        for amount in [100, 100] {
+            assert_eq!(limit_breach(&mut activity, &limit, amount, now + SECOND), None);
+            activity.reported_in_window += amount;
+        }
+        assert_eq!(limit_breach(&mut activity, &limit, 51, now + 2 * SECOND), Some(QuarantineReason::ExceedsWindowLimit));
+        assert_eq!(limit_breach(&mut activity, &limit, 50, now + 2 * SECOND), None);
+    }
+    
+    #[test]
+    fn limit_breach_restarts_the_window_once_it_ends() {
+        let limit = RevenueLimit { max_per_report: 100, max_per_window: 100, window_seconds: 60 };
+        let mut activity = ReporterActivity { window_started_at: SECOND, reported_in_window: 100, pending_quarantine: 3 };
+        
+        assert_eq!(limit_breach(&mut activity, &limit, 1, 60 * SECOND), Some(QuarantineReason::ExceedsWindowLimit));
+        assert_eq!(limit_breach(&mut activity, &limit, 100, 61 * SECOND), None);
+        assert_eq!((activity.window_started_at, activity.reported_in_window), (61 * SECOND, 0));
+        assert_eq!(activity.pending_quarantine, 3); // Reports awaiting review outlive the window
+        
+        // A window too long to represent in nanoseconds never ends
+        activity.reported_in_window = 100;
+        let endless = RevenueLimit { window_seconds: u64::MAX, ..limit };
+        assert!(limit_breach(&mut activity, &endless, 1, u64::MAX - 1).is_some());
+    }
+    
+    #[test]
+    fn source_limits_apply_to_their_own_reporter_and_source() {
+        set_test_config(40);
+        let oracle = Principal::from_slice(&[1]);
+        let limit = RevenueLimit { max_per_report: 5, max_per_window: 10, window_seconds: 60 };
+        mutate_config(|config| {
+            config.source_limits = Some(vec![(oracle, "youtube".to_string(), limit.clone())]);
+            Ok(())
+        }).unwrap();
+        
+        read_config(|config| {
+            assert_eq!(source_limit(config, oracle, "youtube").map(|limit| limit.max_per_report), Some(5));
+            assert!(source_limit(config, oracle, "spotify").is_none());
+            assert!(source_limit(config, Principal::from_slice(&[2]), "youtube").is_none());
+            // Sources only tighten the reporter's own limit, which still applies
+            assert_eq!(reporter_limit(config, oracle).max_per_report, 1_000);
+        }).unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+// Candid types and helpers shared by IPX canisters. Both sides of a call depend on this
+// crate, so a request can't drift from what the receiving canister decodes.
+use candid::{CandidType, Nat, Principal};
+use serde::{Deserialize, Serialize};
+
+// Re-exported for `impl_candid_storable!`, so callers don't need matching imports
+#[doc(hidden)]
+pub use candid as __candid;
+#[doc(hidden)]
+pub use ic_stable_structures as __stable_structures;
+
+// Stable values are Candid-encoded so records can gain optional fields across upgrades
+#[macro_export]
+macro_rules! impl_candid_storable {
+    ($($t:ty),* $(,)?) => {
+        $(
+            impl $crate::__stable_structures::Storable for $t {
+                fn to_bytes(&self) -> ::std::borrow::Cow<'_, [u8]> {
+                    ::std::borrow::Cow::Owned(
+                        $crate::__candid::Encode!(self).expect("failed to encode stable value"),
+                    )
+                }
+
+                fn from_bytes(bytes: ::std::borrow::Cow<[u8]>) -> Self {
+                    $crate::__candid::Decode!(bytes.as_ref(), Self).expect("failed to decode stable value")
+                }
+
+                const BOUND: $crate::__stable_structures::storable::Bound =
+                    $crate::__stable_structures::storable::Bound::Unbounded;
+            }
+        )*
+    };
+}
+
+// Sent by a vault to the nft-registry's `mint_position` for one investment tranche.
+// `(vault_canister, tranche_id)` identifies the position, so re-sending a request
+// returns the token minted the first time instead of minting another.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct MintRequest {
+    pub to: Principal,
+    pub campaign_id: u64,
+    pub vault_canister: Principal,
+    pub tranche_id: u64,
+    pub investment_amount: u64,
+    pub share_units: u64,
+    pub share_percentage: f64, // Display only
+    pub metadata_json: String,
+}
+
+// Caps on the revenue a vault accepts from one reporter, or from one of a reporter's sources. The
+// factory sets them on a vault, and reporters without one get the vault's default; reports over
+// any cap that applies are quarantined for review instead of being counted.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RevenueLimit {
+    pub max_per_report: u64,
+    pub max_per_window: u64,
+    pub window_seconds: u64,
+}
+
+// Platforms revenue-api-connector reads revenue from. The factory maps oracle endpoint URLs
+// onto these by host, and the connector reads the figure at `revenue_path` in the response.
+pub struct RevenuePlatform {
+    pub name: &'static str,
+    pub domains: &'static [&'static str], // Subdomains match too
+    pub revenue_path: &'static str, // JSON pointer
+}
+
+pub const REVENUE_PLATFORMS: &[RevenuePlatform] = &[
+    RevenuePlatform {
+        name: "youtube",
+        domains: &["youtubeanalytics.googleapis.com", "youtube.googleapis.com"],
+        revenue_path: "/reports/0/data/totals/0/values/0",
+    },
+    RevenuePlatform {
+        name: "spotify",
+        domains: &["spotify.com"],
+        revenue_path: "/revenue/total",
+    },
+    RevenuePlatform {
+        name: "substack",
+        domains: &["substack.com"],
+        revenue_path: "/stats/revenue/total",
+    },
+];
+
+pub fn revenue_platform(name: &str) -> Option<&'static RevenuePlatform> {
+    REVENUE_PLATFORMS.iter().find(|platform| platform.name == name)
+}
+
+// `host` must already be lowercase
+pub fn revenue_platform_for_host(host: &str) -> Option<&'static RevenuePlatform> {
+    REVENUE_PLATFORMS.iter().find(|platform| {
+        platform.domains.iter().any(|domain| {
+            host == *domain || host.strip_suffix(domain).is_some_and(|prefix| prefix.ends_with('.'))
+        })
+    })
+}
+
+// Minimal ICRC-1/ICRC-2 ledger types used by the vault and the factory
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+// Ledger amounts and block indices are `nat`; IPX stores them as u64
+pub fn nat_to_u64(value: Nat) -> Result<u64, String> {
+    u64::try_from(value.0).map_err(|_| "Ledger value does not fit in u64".to_string())
+}
+
+// The ledger's `get_transactions` history, as served by ICRC-1 ledgers. Only transfers are
+// read; other fields of a block are ignored on decode.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct GetTransactionsRequest {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetTransactionsResponse {
+    pub log_length: Nat,
+    pub first_index: Nat,
+    pub transactions: Vec<LedgerTransaction>,
+    pub archived_transactions: Vec<ArchivedTransactions>,
+}
+
+// Older blocks are served by an archive canister through `callback`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchivedTransactions {
+    pub start: Nat,
+    pub length: Nat,
+    pub callback: QueryArchiveFn,
+}
+
+candid::define_function!(pub QueryArchiveFn : (GetTransactionsRequest) -> (TransactionRange) query);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransactionRange {
+    pub transactions: Vec<LedgerTransaction>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LedgerTransaction {
+    pub kind: String,
+    pub transfer: Option<LedgerTransactionTransfer>,
+    pub timestamp: u64,
+}
+
+// `spender` is set for ICRC-2 `transfer_from`
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LedgerTransactionTransfer {
+    pub from: Account,
+    pub to: Account,
+    pub spender: Option<Account>,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
@@ -60,12 +60,67 @@ echo -e "${GREEN}Building ipx-dao...${NC}"
 cargo build --target wasm32-unknown-unknown --release --package ipx-dao
 candid-extractor target/wasm32-unknown-unknown/release/ipx_dao.wasm > candid/ipx-dao.did
 
+# Build and extract for mock-ledger (local ICRC ledger used by the vault)
+echo -e "${GREEN}Building mock-ledger...${NC}"
+cargo build --target wasm32-unknown-unknown --release --package mock-ledger
+candid-extractor target/wasm32-unknown-unknown/release/mock_ledger.wasm > candid/mock-ledger.did
+
 # Create, build and deploy canisters
 echo -e "${GREEN}Creating and deploying canisters...${NC}"
 dfx canister create --all
 dfx build
+
+# Fee-less mock ledger backing vault custody in local development
+dfx deploy mock-ledger --argument "(null)"
+
+# The standalone vault requires init args; deploy it first with the current identity as creator
+dfx deploy vault --argument "(record {
+    campaign_id = 0 : nat64;
+    ledger_canister = opt principal \"$(dfx canister id mock-ledger)\";
+    metadata = record {
+        creator = principal \"$(dfx identity get-principal)\";
+        title = \"Development Vault\";
+        description = \"\";
+        funding_goal = 1_000_000 : nat64;
+        revenue_share_percentage = 10 : nat8;
+        oracle_endpoints = vec {};
+    };
+})"
 dfx deploy
 
+# Upload the vault wasm so the factory can install it into per-campaign vaults
+echo -e "${GREEN}Uploading vault wasm to campaign-factory...${NC}"
+VAULT_WASM=target/wasm32-unknown-unknown/release/vault.wasm
+printf '(blob "%s", "%s")' "$(od -An -v -tx1 $VAULT_WASM | tr -d ' \n' | sed 's/../\\&/g')" "0.1.0" > vault_wasm_arg.did
+dfx canister call campaign-factory set_vault_wasm --argument-file vault_wasm_arg.did
+rm vault_wasm_arg.did
+
+# Point creator verification at the nft-registry; verification stays optional in development
+dfx canister call campaign-factory set_verification_config "(record {
+    nft_registry = opt principal \"$(dfx canister id nft-registry)\";
+    require_verified_creator = false;
+    min_subscribers = null;
+    min_views = null;
+})"
+
+# Let ipx-dao proposals manage the creator allow/deny list; no creation deposit in development
+dfx canister call campaign-factory set_anti_spam_config "(record {
+    max_campaigns_per_window = 3 : nat32;
+    window_seconds = 86400 : nat64;
+    allowlist_only = false;
+    dao_canister = opt principal \"$(dfx canister id ipx-dao)\";
+    deposit_ledger = opt principal \"$(dfx canister id mock-ledger)\";
+    deposit_amount = 0 : nat64;
+})"
+dfx canister call ipx-dao set_campaign_factory "(principal \"$(dfx canister id campaign-factory)\")"
+
+# Canisters every new vault is wired to while the factory provisions it
+dfx canister call campaign-factory set_platform_canisters "(record {
+    ipx_stream = opt principal \"$(dfx canister id ipx-stream)\";
+    revenue_connector = opt principal \"$(dfx canister id revenue-api-connector)\";
+    ledger = opt principal \"$(dfx canister id mock-ledger)\";
+})"
+
 # Get canister IDs
 VAULT_ID=$(dfx canister id vault)
 CAMPAIGN_ID=$(dfx canister id campaign-factory)